@@ -0,0 +1,14 @@
+CREATE TABLE user_identities
+(
+    "id"       uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "user_id"  uuid      NOT NULL
+        CONSTRAINT "identity_user" REFERENCES users (id) ON DELETE CASCADE,
+    "provider" text      NOT NULL,
+    "issuer"   text      NOT NULL,
+    "subject"  text      NOT NULL,
+    "email"    text      NOT NULL DEFAULT '',
+    "created"  timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "unique_identity" UNIQUE (issuer, subject)
+);
+
+CREATE INDEX "identity_user" ON user_identities (user_id);
@@ -0,0 +1,21 @@
+CREATE TABLE user_totp
+(
+    "user_id"   uuid      NOT NULL PRIMARY KEY
+        CONSTRAINT "totp_user" REFERENCES users (id) ON DELETE CASCADE,
+    "secret"    bytea     NOT NULL,
+    "enabled"   boolean   NOT NULL DEFAULT false,
+    -- The last accepted time step, a code can not be used twice.
+    "last_step" bigint    NOT NULL DEFAULT 0,
+    "created"   timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE TABLE totp_recovery_codes
+(
+    "id"      uuid    NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "user_id" uuid    NOT NULL
+        CONSTRAINT "recovery_code_totp" REFERENCES user_totp (user_id) ON DELETE CASCADE,
+    "code"    text    NOT NULL, -- crypt hashed
+    "used"    boolean NOT NULL DEFAULT false
+);
+
+CREATE INDEX "recovery_code_user" ON totp_recovery_codes (user_id);
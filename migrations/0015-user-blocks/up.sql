@@ -0,0 +1,11 @@
+CREATE TABLE user_blocks
+(
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "block_user" REFERENCES users (id) ON DELETE CASCADE,
+    "blocked_id" uuid      NOT NULL
+        CONSTRAINT "block_blocked_user" REFERENCES users (id) ON DELETE CASCADE,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "user_block_pair" PRIMARY KEY (user_id, blocked_id)
+);
+
+CREATE INDEX "block_blocked_user" ON user_blocks (blocked_id);
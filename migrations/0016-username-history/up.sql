@@ -0,0 +1,12 @@
+CREATE TABLE username_history
+(
+    "id"       uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "user_id"  uuid      NOT NULL
+        CONSTRAINT "username_history_user" REFERENCES users (id) ON DELETE CASCADE,
+    -- The username before the change.
+    "username" text      NOT NULL,
+    "changed"  timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "username_history_user" ON username_history (user_id);
+CREATE INDEX "username_history_username" ON username_history (username);
@@ -0,0 +1,3 @@
+ALTER TABLE media
+    DROP COLUMN "width",
+    DROP COLUMN "height";
@@ -0,0 +1,3 @@
+ALTER TABLE media
+    ADD COLUMN "width"  integer DEFAULT NULL,
+    ADD COLUMN "height" integer DEFAULT NULL;
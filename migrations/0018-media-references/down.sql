@@ -0,0 +1,3 @@
+DROP INDEX "message_media";
+DROP INDEX "media_uploader";
+DROP INDEX "media_filename";
@@ -0,0 +1,3 @@
+CREATE INDEX "message_media" ON messages (media_id) WHERE media_id IS NOT NULL;
+CREATE INDEX "media_uploader" ON media (uploader_id);
+CREATE INDEX "media_filename" ON media (filename);
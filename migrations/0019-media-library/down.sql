@@ -0,0 +1,5 @@
+DROP INDEX "media_space";
+
+ALTER TABLE media
+    DROP COLUMN "space_id",
+    DROP COLUMN "channel_id";
@@ -0,0 +1,14 @@
+ALTER TABLE media
+    ADD COLUMN "space_id"   uuid DEFAULT NULL
+        CONSTRAINT "media_space" REFERENCES spaces (id) ON DELETE SET NULL,
+    ADD COLUMN "channel_id" uuid DEFAULT NULL
+        CONSTRAINT "media_channel" REFERENCES channels (id) ON DELETE SET NULL;
+
+CREATE INDEX "media_space" ON media (space_id, created);
+
+UPDATE media
+SET space_id   = ch.space_id,
+    channel_id = ch.id
+FROM messages msg
+    INNER JOIN channels ch ON msg.channel_id = ch.id
+WHERE msg.media_id = media.id;
@@ -0,0 +1,7 @@
+CREATE TABLE space_upload_policies
+(
+    "space_id" uuid      NOT NULL PRIMARY KEY
+        CONSTRAINT "upload_policy_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "policy"   jsonb     NOT NULL,
+    "modified" timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
@@ -0,0 +1,5 @@
+ALTER TABLE channels
+    DROP COLUMN "category_id",
+    DROP COLUMN "pos";
+
+DROP TABLE channel_categories;
@@ -0,0 +1,2 @@
+ALTER TABLE channels
+    ADD COLUMN "archived" boolean NOT NULL DEFAULT false;
@@ -0,0 +1,3 @@
+-- Hashed passwords can't be restored.
+UPDATE spaces
+SET password = '';
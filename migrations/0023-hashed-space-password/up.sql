@@ -0,0 +1,3 @@
+UPDATE spaces
+SET password = crypt(password, gen_salt('bf'))
+WHERE password <> '';
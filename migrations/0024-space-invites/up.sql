@@ -0,0 +1,20 @@
+CREATE TABLE space_invites
+(
+    "id"         uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "space_id"   uuid      NOT NULL
+        CONSTRAINT "invite_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "creator_id" uuid      NOT NULL
+        CONSTRAINT "invite_creator" REFERENCES users (id) ON DELETE CASCADE,
+    "name"       text      NOT NULL DEFAULT '',
+    -- Players join it after joining the space.
+    "channel_id" uuid               DEFAULT NULL
+        CONSTRAINT "invite_channel" REFERENCES channels (id) ON DELETE SET NULL,
+    "spectator"  boolean   NOT NULL DEFAULT false,
+    "max_uses"   integer            DEFAULT NULL,
+    "uses"       integer   NOT NULL DEFAULT 0,
+    "expires"    timestamp          DEFAULT NULL,
+    "revoked"    boolean   NOT NULL DEFAULT false,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "invite_space" ON space_invites (space_id);
@@ -0,0 +1,11 @@
+-- Nominations of new space owners, waiting to be accepted.
+CREATE TABLE space_transfers
+(
+    "space_id"     uuid      NOT NULL PRIMARY KEY
+        CONSTRAINT "transfer_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "nominee_id"   uuid      NOT NULL
+        CONSTRAINT "transfer_nominee" REFERENCES users (id) ON DELETE CASCADE,
+    "nominator_id" uuid      NOT NULL
+        CONSTRAINT "transfer_nominator" REFERENCES users (id) ON DELETE CASCADE,
+    "created"      timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
@@ -0,0 +1,5 @@
+DROP TABLE space_member_roles;
+DROP TABLE space_roles;
+
+ALTER TABLE spaces
+    DROP COLUMN "member_permissions";
@@ -0,0 +1,29 @@
+-- Permissions every member has, 64 is uploading media.
+ALTER TABLE spaces
+    ADD COLUMN "member_permissions" integer NOT NULL DEFAULT 64;
+
+CREATE TABLE space_roles
+(
+    "id"          uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "space_id"    uuid      NOT NULL
+        CONSTRAINT "role_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "name"        text      NOT NULL,
+    -- A bitset, see `spaces::permissions::Permission`.
+    "permissions" integer   NOT NULL DEFAULT 0,
+    "created"     timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "role_space" ON space_roles (space_id);
+
+CREATE TABLE space_member_roles
+(
+    "user_id"  uuid NOT NULL,
+    "space_id" uuid NOT NULL,
+    "role_id"  uuid NOT NULL
+        CONSTRAINT "member_role_role" REFERENCES space_roles (id) ON DELETE CASCADE,
+    CONSTRAINT "member_role_member" FOREIGN KEY (user_id, space_id)
+        REFERENCES space_members (user_id, space_id) ON DELETE CASCADE,
+    CONSTRAINT "member_role_pair" PRIMARY KEY (user_id, role_id)
+);
+
+CREATE INDEX "member_role_space" ON space_member_roles (space_id);
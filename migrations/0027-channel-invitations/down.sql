@@ -0,0 +1,2 @@
+DROP TABLE channel_allowed_users;
+DROP TABLE channel_invitations;
@@ -0,0 +1,24 @@
+-- Invitations to channels, pending until accepted.
+CREATE TABLE channel_invitations
+(
+    "channel_id" uuid      NOT NULL
+        CONSTRAINT "invitation_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "invitation_user" REFERENCES users (id) ON DELETE CASCADE,
+    "inviter_id" uuid      NOT NULL
+        CONSTRAINT "invitation_inviter" REFERENCES users (id) ON DELETE CASCADE,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "invitation_pair" PRIMARY KEY (channel_id, user_id)
+);
+
+CREATE INDEX "invitation_user" ON channel_invitations (user_id);
+
+-- Members of the space who may join the private channel by themselves.
+CREATE TABLE channel_allowed_users
+(
+    "channel_id" uuid NOT NULL
+        CONSTRAINT "allowed_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "user_id"    uuid NOT NULL
+        CONSTRAINT "allowed_user" REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT "allowed_pair" PRIMARY KEY (channel_id, user_id)
+);
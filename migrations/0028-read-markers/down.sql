@@ -0,0 +1,2 @@
+DROP INDEX "message_channel_pos";
+DROP TABLE channel_read_markers;
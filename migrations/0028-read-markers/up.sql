@@ -0,0 +1,20 @@
+-- The position of the last message each user has read in a channel.
+CREATE TABLE channel_read_markers
+(
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "read_marker_user" REFERENCES users (id) ON DELETE CASCADE,
+    "channel_id" uuid      NOT NULL
+        CONSTRAINT "read_marker_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "pos"        float     NOT NULL DEFAULT 0.0,
+    "modified"   timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "read_marker_pair" PRIMARY KEY (user_id, channel_id)
+);
+
+CREATE INDEX "message_channel_pos" ON messages (channel_id, pos) WHERE deleted = false;
+
+-- Members have read the history before the markers.
+INSERT INTO channel_read_markers (user_id, channel_id, pos)
+SELECT cm.user_id, cm.channel_id, coalesce(max(m.pos), 0.0)
+FROM channel_members cm
+    LEFT JOIN messages m ON m.channel_id = cm.channel_id AND m.deleted = false
+GROUP BY cm.user_id, cm.channel_id;
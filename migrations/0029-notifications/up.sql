@@ -0,0 +1,20 @@
+-- Users mentioned by a message, kept until the user or the message is deleted.
+CREATE TABLE notifications
+(
+    "id"         uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "notification_user" REFERENCES users (id) ON DELETE CASCADE,
+    "message_id" uuid      NOT NULL
+        CONSTRAINT "notification_message" REFERENCES messages (id) ON DELETE CASCADE,
+    "channel_id" uuid      NOT NULL
+        CONSTRAINT "notification_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "space_id"   uuid      NOT NULL
+        CONSTRAINT "notification_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "sender_id"  uuid      NOT NULL
+        CONSTRAINT "notification_sender" REFERENCES users (id) ON DELETE CASCADE,
+    "read"       boolean   NOT NULL DEFAULT false,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "notification_message_user" UNIQUE (message_id, user_id)
+);
+
+CREATE INDEX "notification_inbox" ON notifications (user_id, created);
@@ -0,0 +1,35 @@
+-- Endpoints which receive the events of a space.
+CREATE TABLE space_webhooks
+(
+    "id"         uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "space_id"   uuid      NOT NULL
+        CONSTRAINT "webhook_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "creator_id" uuid      NOT NULL
+        CONSTRAINT "webhook_creator" REFERENCES users (id) ON DELETE CASCADE,
+    "url"        text      NOT NULL,
+    -- The key of the signatures of deliveries.
+    "secret"     text      NOT NULL,
+    -- The types of the subscribed events.
+    "events"     text[]    NOT NULL DEFAULT '{}',
+    "enabled"    boolean   NOT NULL DEFAULT true,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "webhook_space" ON space_webhooks (space_id);
+
+CREATE TABLE webhook_deliveries
+(
+    "id"          uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "webhook_id"  uuid      NOT NULL
+        CONSTRAINT "delivery_webhook" REFERENCES space_webhooks (id) ON DELETE CASCADE,
+    "event_type"  text      NOT NULL,
+    "payload"     text      NOT NULL,
+    "attempts"    integer   NOT NULL DEFAULT 0,
+    -- The response status of the last attempt.
+    "status_code" integer            DEFAULT null,
+    "error"       text               DEFAULT null,
+    "delivered"   timestamp          DEFAULT null,
+    "created"     timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "delivery_webhook" ON webhook_deliveries (webhook_id, created);
@@ -0,0 +1,3 @@
+-- Users registered through OpenID Connect have a random password they don't know.
+ALTER TABLE users
+    ADD COLUMN "password_set" boolean NOT NULL DEFAULT true;
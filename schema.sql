@@ -15,7 +15,13 @@ CREATE TABLE media
     "size"              integer   NOT NULL,
     "description"       text      NOT NULL DEFAULT '',
     "source"            text      NOT NULL DEFAULT '',
-    "created"           timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+    "created"           timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    -- Only for images.
+    "width"             integer            DEFAULT NULL,
+    "height"            integer            DEFAULT NULL,
+    -- Where the media was posted.
+    "space_id"          uuid               DEFAULT NULL,
+    "channel_id"        uuid               DEFAULT NULL
 );
 
 CREATE TABLE users
@@ -29,12 +35,17 @@ CREATE TABLE users
     "joined"      timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
     "deactivated" boolean   NOT NULL DEFAULT false,
     "avatar_id"   uuid               DEFAULT NULL
-        CONSTRAINT "user_avatar" REFERENCES media (id) ON DELETE SET NULL
+        CONSTRAINT "user_avatar" REFERENCES media (id) ON DELETE SET NULL,
+    -- Users registered through OpenID Connect have a random password they don't know.
+    "password_set" boolean  NOT NULL DEFAULT true
 );
 
 ALTER TABLE media
     ADD CONSTRAINT "media_uploader" FOREIGN KEY (uploader_id) REFERENCES users (id) ON DELETE RESTRICT;
 
+CREATE INDEX "media_uploader" ON media (uploader_id);
+CREATE INDEX "media_filename" ON media (filename);
+
 CREATE TABLE users_extension
 (
     "user_id"  uuid  NOT NULL PRIMARY KEY
@@ -42,6 +53,68 @@ CREATE TABLE users_extension
     "settings" jsonb NOT NULL DEFAULT '{}'
 );
 
+CREATE TABLE user_identities
+(
+    "id"       uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "user_id"  uuid      NOT NULL
+        CONSTRAINT "identity_user" REFERENCES users (id) ON DELETE CASCADE,
+    "provider" text      NOT NULL,
+    "issuer"   text      NOT NULL,
+    "subject"  text      NOT NULL,
+    "email"    text      NOT NULL DEFAULT '',
+    "created"  timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "unique_identity" UNIQUE (issuer, subject)
+);
+
+CREATE INDEX "identity_user" ON user_identities (user_id);
+
+CREATE TABLE username_history
+(
+    "id"       uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "user_id"  uuid      NOT NULL
+        CONSTRAINT "username_history_user" REFERENCES users (id) ON DELETE CASCADE,
+    -- The username before the change.
+    "username" text      NOT NULL,
+    "changed"  timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "username_history_user" ON username_history (user_id);
+CREATE INDEX "username_history_username" ON username_history (username);
+
+CREATE TABLE user_totp
+(
+    "user_id"   uuid      NOT NULL PRIMARY KEY
+        CONSTRAINT "totp_user" REFERENCES users (id) ON DELETE CASCADE,
+    "secret"    bytea     NOT NULL,
+    "enabled"   boolean   NOT NULL DEFAULT false,
+    -- The last accepted time step, a code can not be used twice.
+    "last_step" bigint    NOT NULL DEFAULT 0,
+    "created"   timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE TABLE totp_recovery_codes
+(
+    "id"      uuid    NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "user_id" uuid    NOT NULL
+        CONSTRAINT "recovery_code_totp" REFERENCES user_totp (user_id) ON DELETE CASCADE,
+    "code"    text    NOT NULL, -- crypt hashed
+    "used"    boolean NOT NULL DEFAULT false
+);
+
+CREATE INDEX "recovery_code_user" ON totp_recovery_codes (user_id);
+
+CREATE TABLE user_blocks
+(
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "block_user" REFERENCES users (id) ON DELETE CASCADE,
+    "blocked_id" uuid      NOT NULL
+        CONSTRAINT "block_blocked_user" REFERENCES users (id) ON DELETE CASCADE,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "user_block_pair" PRIMARY KEY (user_id, blocked_id)
+);
+
+CREATE INDEX "block_blocked_user" ON user_blocks (blocked_id);
+
 CREATE TABLE spaces
 (
     "id"                uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
@@ -53,12 +126,14 @@ CREATE TABLE spaces
         CONSTRAINT "space_owner" REFERENCES users (id) ON DELETE RESTRICT,
     "is_public"         boolean   NOT NULL DEFAULT true,
     "deleted"           boolean   NOT NULL DEFAULT false,
-    "password"          text      NOT NULL DEFAULT '',    -- plain text
+    "password"          text      NOT NULL DEFAULT '',    -- hashed by crypt, empty if not set
     "language"          text      NOT NULL DEFAULT '',    -- ISO 639-1
     "default_dice_type" text      NOT NULL DEFAULT 'd20', -- d20, d100, FATE ...
     "invite_token"      uuid      NOT NULL DEFAULT gen_random_uuid(),
     "explorable"        boolean   NOT NULL DEFAULT false,
-    "allow_spectator"   boolean   NOT NULL DEFAULT true
+    "allow_spectator"   boolean   NOT NULL DEFAULT true,
+    -- Permissions every member has, see `spaces::permissions::Permission`.
+    "member_permissions" integer  NOT NULL DEFAULT 64
 );
 
 CREATE TABLE space_members
@@ -72,6 +147,44 @@ CREATE TABLE space_members
     CONSTRAINT "user_space_id_pair" PRIMARY KEY ("user_id", "space_id")
 );
 
+CREATE TABLE space_roles
+(
+    "id"          uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "space_id"    uuid      NOT NULL
+        CONSTRAINT "role_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "name"        text      NOT NULL,
+    -- A bitset, see `spaces::permissions::Permission`.
+    "permissions" integer   NOT NULL DEFAULT 0,
+    "created"     timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "role_space" ON space_roles (space_id);
+
+CREATE TABLE space_member_roles
+(
+    "user_id"  uuid NOT NULL,
+    "space_id" uuid NOT NULL,
+    "role_id"  uuid NOT NULL
+        CONSTRAINT "member_role_role" REFERENCES space_roles (id) ON DELETE CASCADE,
+    CONSTRAINT "member_role_member" FOREIGN KEY (user_id, space_id)
+        REFERENCES space_members (user_id, space_id) ON DELETE CASCADE,
+    CONSTRAINT "member_role_pair" PRIMARY KEY (user_id, role_id)
+);
+
+CREATE INDEX "member_role_space" ON space_member_roles (space_id);
+
+-- Nominations of new space owners, waiting to be accepted.
+CREATE TABLE space_transfers
+(
+    "space_id"     uuid      NOT NULL PRIMARY KEY
+        CONSTRAINT "transfer_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "nominee_id"   uuid      NOT NULL
+        CONSTRAINT "transfer_nominee" REFERENCES users (id) ON DELETE CASCADE,
+    "nominator_id" uuid      NOT NULL
+        CONSTRAINT "transfer_nominator" REFERENCES users (id) ON DELETE CASCADE,
+    "created"      timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
 CREATE TABLE channels
 (
     "id"                   uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
@@ -86,9 +199,28 @@ CREATE TABLE channels
     "default_roll_command" text      NOT NULL DEFAULT 'd',
     "is_document"          bool      NOT NULL DEFAULT false,
     "old_name"             text      NOT NULL DEFAULT '',
+    "category_id"          uuid               DEFAULT NULL,
+    "pos"                  float     NOT NULL DEFAULT 0.0,
+    -- Archived channels are read-only.
+    "archived"             boolean   NOT NULL DEFAULT false,
     CONSTRAINT "unique_channel_name_in_space" UNIQUE (space_id, name)
 );
 
+CREATE TABLE channel_categories
+(
+    "id"       uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
+    "space_id" uuid      NOT NULL
+        CONSTRAINT "category_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "name"     text      NOT NULL,
+    "pos"      float     NOT NULL DEFAULT 0.0,
+    "created"  timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "category_space" ON channel_categories (space_id);
+
+ALTER TABLE channels
+    ADD CONSTRAINT "channel_category" FOREIGN KEY (category_id) REFERENCES channel_categories (id) ON DELETE SET NULL;
+
 CREATE TABLE channel_members
 (
     "user_id"        uuid      NOT NULL
@@ -103,6 +235,52 @@ CREATE TABLE channel_members
     CONSTRAINT "user_channel_id_pair" PRIMARY KEY ("user_id", "channel_id")
 );
 
+CREATE TABLE space_invites
+(
+    "id"         uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "space_id"   uuid      NOT NULL
+        CONSTRAINT "invite_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "creator_id" uuid      NOT NULL
+        CONSTRAINT "invite_creator" REFERENCES users (id) ON DELETE CASCADE,
+    "name"       text      NOT NULL DEFAULT '',
+    -- Players join it after joining the space.
+    "channel_id" uuid               DEFAULT NULL
+        CONSTRAINT "invite_channel" REFERENCES channels (id) ON DELETE SET NULL,
+    "spectator"  boolean   NOT NULL DEFAULT false,
+    "max_uses"   integer            DEFAULT NULL,
+    "uses"       integer   NOT NULL DEFAULT 0,
+    "expires"    timestamp          DEFAULT NULL,
+    "revoked"    boolean   NOT NULL DEFAULT false,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "invite_space" ON space_invites (space_id);
+
+-- Invitations to channels, pending until accepted.
+CREATE TABLE channel_invitations
+(
+    "channel_id" uuid      NOT NULL
+        CONSTRAINT "invitation_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "invitation_user" REFERENCES users (id) ON DELETE CASCADE,
+    "inviter_id" uuid      NOT NULL
+        CONSTRAINT "invitation_inviter" REFERENCES users (id) ON DELETE CASCADE,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "invitation_pair" PRIMARY KEY (channel_id, user_id)
+);
+
+CREATE INDEX "invitation_user" ON channel_invitations (user_id);
+
+-- Members of the space who may join the private channel by themselves.
+CREATE TABLE channel_allowed_users
+(
+    "channel_id" uuid NOT NULL
+        CONSTRAINT "allowed_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "user_id"    uuid NOT NULL
+        CONSTRAINT "allowed_user" REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT "allowed_pair" PRIMARY KEY (channel_id, user_id)
+);
+
 CREATE TABLE messages
 (
     "id"                uuid      NOT NULL DEFAULT uuid_generate_v1mc() PRIMARY KEY,
@@ -143,6 +321,91 @@ ALTER TABLE messages
 CREATE INDEX "message_pos" ON messages (pos);
 CREATE INDEX "message_tags" ON messages USING GIN (tags);
 CREATE INDEX "message_channel" ON messages USING btree (channel_id);
+CREATE INDEX "message_media" ON messages (media_id) WHERE media_id IS NOT NULL;
+CREATE INDEX "message_channel_pos" ON messages (channel_id, pos) WHERE deleted = false;
+
+-- The position of the last message each user has read in a channel.
+CREATE TABLE channel_read_markers
+(
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "read_marker_user" REFERENCES users (id) ON DELETE CASCADE,
+    "channel_id" uuid      NOT NULL
+        CONSTRAINT "read_marker_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "pos"        float     NOT NULL DEFAULT 0.0,
+    "modified"   timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "read_marker_pair" PRIMARY KEY (user_id, channel_id)
+);
+
+-- Users mentioned by a message, kept until the user or the message is deleted.
+CREATE TABLE notifications
+(
+    "id"         uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "user_id"    uuid      NOT NULL
+        CONSTRAINT "notification_user" REFERENCES users (id) ON DELETE CASCADE,
+    "message_id" uuid      NOT NULL
+        CONSTRAINT "notification_message" REFERENCES messages (id) ON DELETE CASCADE,
+    "channel_id" uuid      NOT NULL
+        CONSTRAINT "notification_channel" REFERENCES channels (id) ON DELETE CASCADE,
+    "space_id"   uuid      NOT NULL
+        CONSTRAINT "notification_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "sender_id"  uuid      NOT NULL
+        CONSTRAINT "notification_sender" REFERENCES users (id) ON DELETE CASCADE,
+    "read"       boolean   NOT NULL DEFAULT false,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT "notification_message_user" UNIQUE (message_id, user_id)
+);
+
+CREATE INDEX "notification_inbox" ON notifications (user_id, created);
+
+-- Endpoints which receive the events of a space.
+CREATE TABLE space_webhooks
+(
+    "id"         uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "space_id"   uuid      NOT NULL
+        CONSTRAINT "webhook_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "creator_id" uuid      NOT NULL
+        CONSTRAINT "webhook_creator" REFERENCES users (id) ON DELETE CASCADE,
+    "url"        text      NOT NULL,
+    -- The key of the signatures of deliveries.
+    "secret"     text      NOT NULL,
+    -- The types of the subscribed events.
+    "events"     text[]    NOT NULL DEFAULT '{}',
+    "enabled"    boolean   NOT NULL DEFAULT true,
+    "created"    timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "webhook_space" ON space_webhooks (space_id);
+
+CREATE TABLE webhook_deliveries
+(
+    "id"          uuid      NOT NULL DEFAULT gen_random_uuid() PRIMARY KEY,
+    "webhook_id"  uuid      NOT NULL
+        CONSTRAINT "delivery_webhook" REFERENCES space_webhooks (id) ON DELETE CASCADE,
+    "event_type"  text      NOT NULL,
+    "payload"     text      NOT NULL,
+    "attempts"    integer   NOT NULL DEFAULT 0,
+    -- The response status of the last attempt.
+    "status_code" integer            DEFAULT null,
+    "error"       text               DEFAULT null,
+    "delivered"   timestamp          DEFAULT null,
+    "created"     timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX "delivery_webhook" ON webhook_deliveries (webhook_id, created);
+
+ALTER TABLE media
+    ADD CONSTRAINT "media_space" FOREIGN KEY (space_id) REFERENCES spaces (id) ON DELETE SET NULL,
+    ADD CONSTRAINT "media_channel" FOREIGN KEY (channel_id) REFERENCES channels (id) ON DELETE SET NULL;
+
+CREATE INDEX "media_space" ON media (space_id, created);
+
+CREATE TABLE space_upload_policies
+(
+    "space_id" uuid      NOT NULL PRIMARY KEY
+        CONSTRAINT "upload_policy_space" REFERENCES spaces (id) ON DELETE CASCADE,
+    "policy"   jsonb     NOT NULL,
+    "modified" timestamp NOT NULL DEFAULT (now() at time zone 'utc')
+);
 
 CREATE TABLE restrained_members
 (
@@ -28,6 +28,15 @@ impl Connection {
     pub async fn remove(&mut self, key: &[u8]) -> Result<(), CacheError> {
         self.inner.del(key).await
     }
+
+    /// Count in a window which starts from the first time, returns the count.
+    pub async fn incr_with_expiration(&mut self, key: &[u8], seconds: usize) -> Result<u32, CacheError> {
+        let count: u32 = self.inner.incr(key, 1).await?;
+        if count == 1 {
+            self.inner.expire(key, seconds).await?;
+        }
+        Ok(count)
+    }
 }
 
 #[derive(Clone)]
@@ -1,6 +1,7 @@
 pub mod api;
+mod export;
 pub mod handlers;
 pub mod models;
 
 pub use handlers::router;
-pub use models::{Channel, ChannelMember};
+pub use models::{Channel, ChannelCategory, ChannelMember};
@@ -1,4 +1,5 @@
-use super::models::{Channel, ChannelMember};
+use super::export::ExportFormat;
+use super::models::{Channel, ChannelInvitation, ChannelMember};
 use crate::channels::models::Member;
 use crate::spaces::Space;
 use crate::users::User;
@@ -39,6 +40,22 @@ pub struct Edit {
     pub is_document: Option<bool>,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BySpace {
+    pub id: Uuid,
+    /// Include archived channels.
+    #[serde(default)]
+    pub archived: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Archive {
+    pub channel_id: Uuid,
+    pub archived: bool,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct CheckChannelName {
@@ -70,6 +87,24 @@ pub struct ChannelWithRelated {
 pub struct ChannelWithMember {
     pub channel: Channel,
     pub member: ChannelMember,
+    /// Only counted when listing the channels of the user.
+    pub unread: Option<Unread>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Unread {
+    /// The position of the last read message.
+    pub read_pos: f64,
+    pub count: i64,
+    pub mentions: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadMarker {
+    pub channel_id: Uuid,
+    pub pos: f64,
 }
 
 #[derive(Serialize, Debug)]
@@ -96,6 +131,28 @@ pub struct AddMember {
     pub character_name: String,
 }
 
+/// Used to invite and kick members, and to cancel or decline invitations.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelUser {
+    pub channel_id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationWithChannel {
+    pub invitation: ChannelInvitation,
+    pub channel: Channel,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetAllowedUsers {
+    pub channel_id: Uuid,
+    pub user_ids: Vec<Uuid>,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Export {
@@ -103,4 +160,40 @@ pub struct Export {
     #[serde(with = "crate::date_format::option")]
     #[serde(default)]
     pub after: Option<NaiveDateTime>,
+    /// Download the log as a file, instead of the messages in the response.
+    pub format: Option<ExportFormat>,
+    #[serde(default)]
+    pub in_game_only: bool,
+    #[serde(default)]
+    pub hide_folded: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateCategory {
+    pub space_id: Uuid,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EditCategory {
+    pub category_id: Uuid,
+    pub name: String,
+}
+
+/// `range` is the positions of the neighbours after moving, `None` at an end.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveChannel {
+    pub channel_id: Uuid,
+    pub category_id: Option<Uuid>,
+    pub range: (Option<f64>, Option<f64>),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveCategory {
+    pub category_id: Uuid,
+    pub range: (Option<f64>, Option<f64>),
 }
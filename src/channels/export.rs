@@ -0,0 +1,206 @@
+//! Render channel logs as documents.
+use crate::messages::Message;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Messages as they are in the API.
+    Json,
+    Text,
+    Markdown,
+    /// A self-contained page.
+    Html,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Text => "text/plain; charset=utf-8",
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+            ExportFormat::Html => "text/html; charset=utf-8",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Text => "txt",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+const HTML_STYLE: &str = "body { max-width: 48em; margin: 2em auto; padding: 0 1em; font-family: sans-serif; \
+line-height: 1.6; } .message { margin: 0.5em 0; } .out-game { color: #888; font-size: 0.9em; } \
+.action { font-style: italic; } .name { font-weight: bold; } .time { color: #aaa; font-size: 0.8em; }";
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\n' => escaped.push_str("<br>"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_{}[]()<>#+-.!|~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_csv(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub struct Renderer {
+    format: ExportFormat,
+    title: String,
+    /// Text colors of channel members.
+    colors: HashMap<Uuid, String>,
+}
+
+impl Renderer {
+    pub fn new(format: ExportFormat, title: &str, colors: HashMap<Uuid, String>) -> Renderer {
+        Renderer {
+            format,
+            title: title.to_string(),
+            colors,
+        }
+    }
+
+    pub fn head(&self) -> String {
+        match self.format {
+            ExportFormat::Json => "[".to_string(),
+            ExportFormat::Text => format!("{}\n\n", self.title),
+            ExportFormat::Markdown => format!("# {}\n\n", escape_markdown(&*self.title)),
+            ExportFormat::Html => format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+                 <style>{style}</style>\n</head>\n<body>\n<h1>{title}</h1>\n",
+                title = escape_html(&*self.title),
+                style = HTML_STYLE
+            ),
+            ExportFormat::Csv => {
+                "id,created,sender_id,name,in_game,is_action,is_master,folded,whisper,text\r\n".to_string()
+            }
+        }
+    }
+
+    /// `index` is the position of the message in the output.
+    pub fn message(&self, index: usize, message: &Message) -> String {
+        let time = message.created.format("%Y-%m-%d %H:%M:%S");
+        match self.format {
+            ExportFormat::Json => {
+                let json = serde_json::to_string(message).expect("failed to serialize message");
+                if index == 0 {
+                    json
+                } else {
+                    format!(",{}", json)
+                }
+            }
+            ExportFormat::Text => {
+                if !message.in_game {
+                    format!("[{}] ({}: {})\n", time, message.name, message.text)
+                } else if message.is_action {
+                    format!("[{}] * {} {}\n", time, message.name, message.text)
+                } else {
+                    format!("[{}] {}: {}\n", time, message.name, message.text)
+                }
+            }
+            ExportFormat::Markdown => {
+                let name = escape_markdown(&*message.name);
+                let text = escape_markdown(&*message.text).replace('\n', "  \n");
+                if !message.in_game {
+                    format!("> **{}**: {}\n\n", name, text.replace("  \n", "  \n> "))
+                } else if message.is_action {
+                    format!("_**{}** {}_\n\n", name, text)
+                } else {
+                    format!("**{}**: {}\n\n", name, text)
+                }
+            }
+            ExportFormat::Html => {
+                let mut class = String::from("message");
+                class.push_str(if message.in_game { " in-game" } else { " out-game" });
+                if message.is_action {
+                    class.push_str(" action");
+                }
+                let mut html = format!(
+                    "<p class=\"{}\"><span class=\"time\">{}</span> <span class=\"name\"",
+                    class, time
+                );
+                if let Some(color) = self.colors.get(&message.sender_id) {
+                    write!(html, " style=\"color: {}\"", escape_html(color)).unwrap();
+                }
+                let separator = if message.is_action { "" } else { ":" };
+                writeln!(
+                    html,
+                    ">{}</span>{} <span class=\"text\">{}</span></p>",
+                    escape_html(&*message.name),
+                    separator,
+                    escape_html(&*message.text)
+                )
+                .unwrap();
+                html
+            }
+            ExportFormat::Csv => {
+                let fields = [
+                    message.id.to_string(),
+                    time.to_string(),
+                    message.sender_id.to_string(),
+                    escape_csv(&*message.name),
+                    message.in_game.to_string(),
+                    message.is_action.to_string(),
+                    message.is_master.to_string(),
+                    message.folded.to_string(),
+                    message.whisper_to_users.is_some().to_string(),
+                    escape_csv(&*message.text),
+                ];
+                format!("{}\r\n", fields.join(","))
+            }
+        }
+    }
+
+    pub fn tail(&self) -> String {
+        match self.format {
+            ExportFormat::Json => "]".to_string(),
+            ExportFormat::Html => "</body>\n</html>\n".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[test]
+fn escape_test() {
+    assert_eq!(
+        escape_html("<b>\"madoka\" & 'homura'</b>"),
+        "&lt;b&gt;&quot;madoka&quot; &amp; &#39;homura&#39;&lt;/b&gt;"
+    );
+    assert_eq!(escape_markdown("*sayaka* [kyoko]"), "\\*sayaka\\* \\[kyoko\\]");
+    assert_eq!(escape_csv("mami"), "mami");
+    assert_eq!(escape_csv("say \"hi\", mami"), "\"say \"\"hi\"\", mami\"");
+}
@@ -1,27 +1,37 @@
 use super::api::{Create, Edit};
-use super::models::ChannelMember;
+use super::export::Renderer;
+use super::models::{ChannelCategory, ChannelInvitation, ChannelMember};
 use super::Channel;
 use crate::channels::api::{
-    AddMember, ChannelMemberWithUser, ChannelWithMember, ChannelWithRelated, CheckChannelName, EditMember, Export,
-    JoinChannel,
+    AddMember, Archive, BySpace, ChannelMemberWithUser, ChannelUser, ChannelWithMember, ChannelWithRelated,
+    CheckChannelName, CreateCategory, EditCategory, EditMember, Export, InvitationWithChannel, JoinChannel,
+    MoveCategory, MoveChannel, ReadMarker, SetAllowedUsers,
 };
 use crate::channels::models::Member;
 use crate::csrf::authenticate;
-use crate::database;
-use crate::database::Querist;
-use crate::error::{AppError, Find};
+use crate::database::{self, Querist};
+use crate::error::{AppError, DbError, Find};
 use crate::events::context::get_heartbeat_map;
 use crate::events::Event;
 use crate::interface::{self, missing, ok_response, parse_body, parse_query, IdQuery, Response};
+use crate::media::content_disposition;
 use crate::messages::Message;
+use crate::spaces::permissions::{self, Permission};
 use crate::spaces::{Space, SpaceMember};
+use crate::users::UserBlock;
+use futures::{Stream, StreamExt};
+use hyper::body::Sender;
+use hyper::header;
 use hyper::{Body, Request};
 use std::collections::HashMap;
 use uuid::Uuid;
 
-async fn admin_only<T: Querist>(db: &mut T, user_id: &Uuid, space_id: &Uuid) -> Result<(), AppError> {
-    SpaceMember::get(db, user_id, space_id).await.or_no_permission()?;
-    Ok(())
+/// Masters of the channel, or members who can manage channels of the space.
+async fn master_only<T: Querist>(db: &mut T, user_id: &Uuid, channel: &Channel) -> Result<(), AppError> {
+    if ChannelMember::is_master(db, user_id, &channel.id).await? {
+        return Ok(());
+    }
+    permissions::require(db, user_id, &channel.space_id, Permission::ManageChannels).await
 }
 
 async fn query(req: Request<Body>) -> Result<Channel, AppError> {
@@ -85,7 +95,9 @@ async fn create(req: Request<Body>) -> Result<ChannelWithMember, AppError> {
     Space::get_by_id(db, &space_id)
         .await?
         .ok_or_else(|| AppError::BadRequest("The space not found".to_string()))?;
-    admin_only(db, &session.user_id, &space_id).await?;
+    SpaceMember::get(db, &session.user_id, &space_id)
+        .await
+        .or_no_permission()?;
 
     let channel = Channel::create(db, &space_id, &*name, is_public, default_dice_type.as_deref()).await?;
     let channel_member = ChannelMember::add_user(db, &session.user_id, &channel.id, &*character_name, true).await?;
@@ -93,6 +105,7 @@ async fn create(req: Request<Body>) -> Result<ChannelWithMember, AppError> {
     let joined = ChannelWithMember {
         channel,
         member: channel_member,
+        unread: None,
     };
     Event::space_updated(space_id);
     Ok(joined)
@@ -119,9 +132,7 @@ async fn edit(req: Request<Body>) -> Result<Channel, AppError> {
     let space_member = SpaceMember::get_by_channel(db, &session.user_id, &channel_id)
         .await
         .or_no_permission()?;
-    if !space_member.is_admin {
-        return Err(AppError::NoPermission(format!("user is not admin")));
-    }
+    permissions::require(db, &session.user_id, &space_member.space_id, Permission::ManageChannels).await?;
     let channel = Channel::edit(
         db,
         &channel_id,
@@ -168,10 +179,37 @@ async fn add_member(req: Request<Body>) -> Result<ChannelWithMember, AppError> {
     SpaceMember::get(db, &session.user_id, &channel.space_id)
         .await
         .or_no_permission()?;
+    if !channel.is_public {
+        // Masters of private channels invite users instead, only admins add them directly.
+        permissions::require(db, &session.user_id, &channel.space_id, Permission::ManageChannels).await?;
+    }
+    if UserBlock::is_blocked(db, &user_id, &session.user_id).await? {
+        return Err(AppError::NoPermission("You have been blocked by the user".to_string()));
+    }
     let member = ChannelMember::add_user(db, &user_id, &channel_id, &*character_name, false).await?;
     trans.commit().await?;
     Event::push_members(channel_id);
-    Ok(ChannelWithMember { channel, member })
+    Ok(ChannelWithMember {
+        channel,
+        member,
+        unread: None,
+    })
+}
+
+/// Advance the read marker, the devices of the user are synced by the `READ_MARKER` event.
+async fn read(req: Request<Body>) -> Result<f64, AppError> {
+    let session = authenticate(&req).await?;
+    let ReadMarker { channel_id, pos } = interface::parse_body(req).await?;
+
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+
+    ChannelMember::get(db, &session.user_id, &channel_id)
+        .await
+        .or_no_permission()?;
+    let pos = ChannelMember::set_read_marker(db, &session.user_id, &channel_id, pos).await?;
+    Event::read_marker(session.user_id, channel_id, pos);
+    Ok(pos)
 }
 
 async fn edit_member(req: Request<Body>) -> Result<ChannelMember, AppError> {
@@ -219,16 +257,22 @@ async fn join(req: Request<Body>) -> Result<ChannelWithMember, AppError> {
     let db = &mut trans;
 
     let channel = Channel::get_by_id(db, &channel_id).await.or_not_found()?;
-    if !channel.is_public {
-        return Err(AppError::NoPermission(format!("private channel")));
-    }
     SpaceMember::get(db, &session.user_id, &channel.space_id)
         .await
         .or_no_permission()?;
+    // Invited users join private channels by accepting the invitation.
+    let invited = ChannelInvitation::remove(db, &channel_id, &session.user_id).await? > 0;
+    if !channel.is_public && !invited && !Channel::is_allowed(db, &channel_id, &session.user_id).await? {
+        return Err(AppError::NoPermission("private channel".to_string()));
+    }
     let member = ChannelMember::add_user(db, &session.user_id, &channel.id, &*character_name, false).await?;
     trans.commit().await?;
     Event::push_members(channel_id);
-    Ok(ChannelWithMember { channel, member })
+    Ok(ChannelWithMember {
+        channel,
+        member,
+        unread: None,
+    })
 }
 
 async fn leave(req: Request<Body>) -> Result<bool, AppError> {
@@ -240,6 +284,129 @@ async fn leave(req: Request<Body>) -> Result<bool, AppError> {
     Ok(true)
 }
 
+async fn invite(req: Request<Body>) -> Result<ChannelInvitation, AppError> {
+    let session = authenticate(&req).await?;
+    let ChannelUser { channel_id, user_id } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let channel = Channel::get_by_id(db, &channel_id).await.or_not_found()?;
+    master_only(db, &session.user_id, &channel).await?;
+    SpaceMember::get(db, &user_id, &channel.space_id)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("The user is not a member of the space".to_string()))?;
+    if ChannelMember::get(db, &user_id, &channel_id).await?.is_some() {
+        return Err(AppError::BadRequest("The user is already in the channel".to_string()));
+    }
+    if UserBlock::is_blocked(db, &user_id, &session.user_id).await? {
+        return Err(AppError::NoPermission("You have been blocked by the user".to_string()));
+    }
+    let invitation = ChannelInvitation::create(db, &channel_id, &user_id, &session.user_id).await?;
+    Ok(invitation)
+}
+
+async fn my_invitations(req: Request<Body>) -> Result<Vec<InvitationWithChannel>, AppError> {
+    let session = authenticate(&req).await?;
+    let mut conn = database::get().await?;
+    ChannelInvitation::get_by_user(&mut *conn, &session.user_id)
+        .await
+        .map_err(Into::into)
+}
+
+async fn invitations(req: Request<Body>) -> Result<Vec<ChannelInvitation>, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let channel = Channel::get_by_id(db, &id).await.or_not_found()?;
+    master_only(db, &session.user_id, &channel).await?;
+    ChannelInvitation::get_by_channel(db, &id).await.map_err(Into::into)
+}
+
+async fn accept_invitation(req: Request<Body>) -> Result<ChannelWithMember, AppError> {
+    let session = authenticate(&req).await?;
+    let JoinChannel {
+        channel_id,
+        character_name,
+    } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let channel = Channel::get_by_id(db, &channel_id).await.or_not_found()?;
+    SpaceMember::get(db, &session.user_id, &channel.space_id)
+        .await
+        .or_no_permission()?;
+    if ChannelInvitation::remove(db, &channel_id, &session.user_id).await? == 0 {
+        return Err(AppError::NotFound("invitation"));
+    }
+    let member = ChannelMember::add_user(db, &session.user_id, &channel.id, &*character_name, false).await?;
+    trans.commit().await?;
+    Event::push_members(channel_id);
+    Ok(ChannelWithMember {
+        channel,
+        member,
+        unread: None,
+    })
+}
+
+/// The invited user declines, or a master cancels it.
+async fn remove_invitation(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let ChannelUser { channel_id, user_id } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    if user_id != session.user_id {
+        let channel = Channel::get_by_id(db, &channel_id).await.or_not_found()?;
+        master_only(db, &session.user_id, &channel).await?;
+    }
+    ChannelInvitation::remove(db, &channel_id, &user_id).await?;
+    Ok(true)
+}
+
+async fn kick(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let ChannelUser { channel_id, user_id } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let channel = Channel::get_by_id(db, &channel_id).await.or_not_found()?;
+    master_only(db, &session.user_id, &channel).await?;
+    let member = ChannelMember::get(db, &user_id, &channel_id).await.or_not_found()?;
+    if member.is_master {
+        permissions::require(db, &session.user_id, &channel.space_id, Permission::ManageChannels).await?;
+        // Or the user would be the master again after joining.
+        ChannelMember::set_master(db, &user_id, &channel_id, false).await?;
+    }
+    ChannelMember::remove_user(db, &user_id, &channel_id).await?;
+    // Or the user could join again right away.
+    Channel::remove_allowed_user(db, &channel_id, &user_id).await?;
+    ChannelInvitation::remove(db, &channel_id, &user_id).await?;
+    trans.commit().await?;
+    log::info!("The user {} was kicked from the channel {}", user_id, channel_id);
+    Event::push_members(channel_id);
+    Ok(true)
+}
+
+async fn allowed_users(req: Request<Body>) -> Result<Vec<Uuid>, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let channel = Channel::get_by_id(db, &id).await.or_not_found()?;
+    master_only(db, &session.user_id, &channel).await?;
+    Channel::allowed_users(db, &id).await.map_err(Into::into)
+}
+
+async fn set_allowed_users(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let SetAllowedUsers { channel_id, user_ids } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let channel = Channel::get_by_id(db, &channel_id).await.or_not_found()?;
+    master_only(db, &session.user_id, &channel).await?;
+    Channel::set_allowed_users(db, &channel_id, &*user_ids).await?;
+    Ok(true)
+}
+
 async fn delete(req: Request<Body>) -> Result<bool, AppError> {
     let session = authenticate(&req).await?;
     let IdQuery { id } = parse_query(req.uri())?;
@@ -249,7 +416,7 @@ async fn delete(req: Request<Body>) -> Result<bool, AppError> {
 
     let channel = Channel::get_by_id(db, &id).await.or_not_found()?;
 
-    admin_only(db, &session.user_id, &channel.space_id).await?;
+    permissions::require(db, &session.user_id, &channel.space_id, Permission::ManageChannels).await?;
 
     Channel::delete(db, &id).await?;
     log::info!("channel {} was deleted.", &id);
@@ -259,18 +426,44 @@ async fn delete(req: Request<Body>) -> Result<bool, AppError> {
 }
 
 async fn by_space(req: Request<Body>) -> Result<Vec<Channel>, AppError> {
-    let IdQuery { id } = parse_query(req.uri())?;
+    let BySpace { id, archived } = parse_query(req.uri())?;
     let mut conn = database::get().await?;
     let db = &mut *conn;
-    Channel::get_by_space(db, &id).await.map_err(Into::into)
+    Channel::get_by_space(db, &id, archived).await.map_err(Into::into)
 }
 
-async fn export(req: Request<Body>) -> Result<Vec<Message>, AppError> {
-    let Export { channel_id, after } = parse_query(req.uri())?;
+async fn archive(req: Request<Body>) -> Result<Channel, AppError> {
     let session = authenticate(&req).await?;
+    let Archive { channel_id, archived } = interface::parse_body(req).await?;
     let mut conn = database::get().await?;
-    let mut trans = conn.transaction().await?;
-    let db = &mut trans;
+    let db = &mut *conn;
+    let space_member = SpaceMember::get_by_channel(db, &session.user_id, &channel_id)
+        .await
+        .or_no_permission()?;
+    permissions::require(db, &session.user_id, &space_member.space_id, Permission::ManageChannels).await?;
+    let channel = Channel::set_archived(db, &channel_id, archived).await.or_not_found()?;
+    log::info!(
+        "The channel {} was {} by {}",
+        channel_id,
+        if archived { "archived" } else { "unarchived" },
+        session.user_id
+    );
+    Event::channel_edited(channel.clone());
+    Event::space_updated(channel.space_id);
+    Ok(channel)
+}
+
+async fn export(req: Request<Body>) -> Result<Response, AppError> {
+    let Export {
+        channel_id,
+        after,
+        format,
+        in_game_only,
+        hide_folded,
+    } = parse_query(req.uri())?;
+    let session = authenticate(&req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
 
     let channel = Channel::get_by_id(db, &channel_id).await?.or_not_found()?;
 
@@ -279,10 +472,65 @@ async fn export(req: Request<Body>) -> Result<Vec<Message>, AppError> {
         .or_no_permission()?;
     let channel_member = ChannelMember::get(db, &session.user_id, &channel_id).await?;
     if channel_member.is_none() && !space_member.is_admin {
-        return Err(AppError::NoPermission(format!("user is not channel member")));
+        return Err(AppError::NoPermission("user is not channel member".to_string()));
+    }
+    let is_master = channel_member.map_or(false, |member| member.is_master);
+    let hide =
+        !is_master && !permissions::has(db, &session.user_id, &channel.space_id, Permission::SeeWhispers).await?;
+    let format = match format {
+        Some(format) => format,
+        None => {
+            return Message::export(db, &channel.id, hide, after, in_game_only, hide_folded)
+                .await
+                .map(ok_response)
+                .map_err(Into::into)
+        }
+    };
+    let colors = ChannelMember::get_color_list(db, &channel.id).await?;
+    let renderer = Renderer::new(format, &*channel.name, colors);
+    let messages = Message::export_stream(db, &channel.id, after, in_game_only, hide_folded).await?;
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        // The connection is kept until all messages are received.
+        let _conn = conn;
+        if let Err(e) = send_export(&renderer, messages, hide, &mut sender).await {
+            log::warn!("Failed to send the export of a channel: {}", e);
+            sender.abort();
+        }
+    });
+    let filename = format!("{}.{}", channel.name, format.extension());
+    hyper::Response::builder()
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::CONTENT_DISPOSITION, content_disposition(true, &*filename))
+        .body(body)
+        .map_err(error_unexpected!())
+}
+
+async fn send_export(
+    renderer: &Renderer,
+    messages: impl Stream<Item = Result<Message, DbError>>,
+    hide: bool,
+    sender: &mut Sender,
+) -> Result<(), anyhow::Error> {
+    const CHUNK_SIZE: usize = 64 * 1024;
+    futures::pin_mut!(messages);
+    let mut buffer = renderer.head();
+    let mut index = 0;
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        // Whispers which can't be read are left out.
+        if hide && message.whisper_to_users.is_some() {
+            continue;
+        }
+        buffer.push_str(&*renderer.message(index, &message));
+        index += 1;
+        if buffer.len() >= CHUNK_SIZE {
+            sender.send_data(std::mem::take(&mut buffer).into()).await?;
+        }
     }
-    let hide = channel_member.map_or(true, |member| !member.is_master);
-    Message::export(db, &channel.id, hide, after).await.map_err(Into::into)
+    buffer.push_str(&*renderer.tail());
+    sender.send_data(buffer.into()).await?;
+    Ok(())
 }
 
 async fn my_channels(req: Request<Body>) -> Result<Vec<ChannelWithMember>, AppError> {
@@ -299,6 +547,90 @@ pub async fn check_channel_name_exists(req: Request<Body>) -> Result<bool, AppEr
     let channel = Channel::get_by_name(&mut *db, space_id, &*name).await?;
     Ok(channel.is_some())
 }
+/// Positions of both ends can't be the same, or the item can't be placed between them.
+fn check_range(range: (Option<f64>, Option<f64>)) -> Result<(Option<f64>, Option<f64>), AppError> {
+    match range {
+        (Some(a), Some(b)) if a == b => Err(AppError::BadRequest("a and b cannot be equal".to_string())),
+        (Some(a), Some(b)) if a > b => Ok((Some(b), Some(a))),
+        range => Ok(range),
+    }
+}
+
+async fn create_category(req: Request<Body>) -> Result<ChannelCategory, AppError> {
+    let session = authenticate(&req).await?;
+    let CreateCategory { space_id, name } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    permissions::require(db, &session.user_id, &space_id, Permission::ManageChannels).await?;
+    let category = ChannelCategory::create(db, &space_id, &*name).await?;
+    Event::space_updated(space_id);
+    Ok(category)
+}
+
+async fn edit_category(req: Request<Body>) -> Result<ChannelCategory, AppError> {
+    let session = authenticate(&req).await?;
+    let EditCategory { category_id, name } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let category = ChannelCategory::get_by_id(db, &category_id).await.or_not_found()?;
+    permissions::require(db, &session.user_id, &category.space_id, Permission::ManageChannels).await?;
+    let category = ChannelCategory::edit(db, &category_id, &*name).await?.or_not_found()?;
+    Event::space_updated(category.space_id);
+    Ok(category)
+}
+
+async fn move_category(req: Request<Body>) -> Result<ChannelCategory, AppError> {
+    let session = authenticate(&req).await?;
+    let MoveCategory { category_id, range } = interface::parse_body(req).await?;
+    let range = check_range(range)?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let category = ChannelCategory::get_by_id(db, &category_id).await.or_not_found()?;
+    permissions::require(db, &session.user_id, &category.space_id, Permission::ManageChannels).await?;
+    let category = ChannelCategory::move_to(db, &category_id, range).await.or_not_found()?;
+    Event::space_updated(category.space_id);
+    Ok(category)
+}
+
+async fn delete_category(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let category = ChannelCategory::get_by_id(db, &id).await.or_not_found()?;
+    permissions::require(db, &session.user_id, &category.space_id, Permission::ManageChannels).await?;
+    ChannelCategory::delete(db, &id).await?;
+    Event::space_updated(category.space_id);
+    Ok(true)
+}
+
+async fn move_channel(req: Request<Body>) -> Result<Channel, AppError> {
+    let session = authenticate(&req).await?;
+    let MoveChannel {
+        channel_id,
+        category_id,
+        range,
+    } = interface::parse_body(req).await?;
+    let range = check_range(range)?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let channel = Channel::get_by_id(db, &channel_id).await.or_not_found()?;
+    permissions::require(db, &session.user_id, &channel.space_id, Permission::ManageChannels).await?;
+    if let Some(category_id) = category_id.as_ref() {
+        let category = ChannelCategory::get_by_id(db, category_id).await.or_not_found()?;
+        if category.space_id != channel.space_id {
+            return Err(AppError::BadRequest(
+                "The category is not in the space of the channel.".to_string(),
+            ));
+        }
+    }
+    let channel = Channel::move_to(db, &channel_id, category_id.as_ref(), range)
+        .await
+        .or_not_found()?;
+    Event::space_updated(channel.space_id);
+    Ok(channel)
+}
+
 pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError> {
     use hyper::Method;
 
@@ -311,12 +643,27 @@ pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError
         ("/edit", Method::POST) => edit(req).await.map(ok_response),
         ("/add_member", Method::POST) => add_member(req).await.map(ok_response),
         ("/edit_member", Method::POST) => edit_member(req).await.map(ok_response),
+        ("/read", Method::POST) => read(req).await.map(ok_response),
         ("/all_members", Method::GET) => all_members(req).await.map(ok_response),
         ("/join", Method::POST) => join(req).await.map(ok_response),
         ("/leave", Method::POST) => leave(req).await.map(ok_response),
         ("/delete", Method::POST) => delete(req).await.map(ok_response),
+        ("/invite", Method::POST) => invite(req).await.map(ok_response),
+        ("/my_invitations", Method::GET) => my_invitations(req).await.map(ok_response),
+        ("/invitations", Method::GET) => invitations(req).await.map(ok_response),
+        ("/accept_invitation", Method::POST) => accept_invitation(req).await.map(ok_response),
+        ("/remove_invitation", Method::POST) => remove_invitation(req).await.map(ok_response),
+        ("/kick", Method::POST) => kick(req).await.map(ok_response),
+        ("/allowed_users", Method::GET) => allowed_users(req).await.map(ok_response),
+        ("/set_allowed_users", Method::POST) => set_allowed_users(req).await.map(ok_response),
         ("/check_name", Method::GET) => check_channel_name_exists(req).await.map(ok_response),
-        ("/export", Method::GET) => export(req).await.map(ok_response),
+        ("/export", Method::GET) => export(req).await,
+        ("/archive", Method::POST) => archive(req).await.map(ok_response),
+        ("/move", Method::POST) => move_channel(req).await.map(ok_response),
+        ("/create_category", Method::POST) => create_category(req).await.map(ok_response),
+        ("/edit_category", Method::POST) => edit_category(req).await.map(ok_response),
+        ("/move_category", Method::POST) => move_category(req).await.map(ok_response),
+        ("/delete_category", Method::POST) => delete_category(req).await.map(ok_response),
         _ => missing(),
     }
 }
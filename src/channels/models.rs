@@ -3,9 +3,9 @@ use postgres_types::FromSql;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::channels::api::{ChannelMemberWithUser, ChannelWithMember};
+use crate::channels::api::{ChannelMemberWithUser, ChannelWithMember, InvitationWithChannel, Unread};
 use crate::database::Querist;
-use crate::error::{DbError, ModelError};
+use crate::error::{AppError, DbError, ModelError};
 use crate::spaces::{Space, SpaceMember};
 use crate::users::User;
 use crate::utils::{inner_result_map, merge_blank};
@@ -29,6 +29,11 @@ pub struct Channel {
     pub deleted: bool,
     pub default_dice_type: String,
     pub default_roll_command: String,
+    pub category_id: Option<Uuid>,
+    /// Channels are sorted by this in their category.
+    pub pos: f64,
+    /// Read-only, and hidden from the channel list by default.
+    pub archived: bool,
 }
 
 impl Channel {
@@ -80,8 +85,14 @@ impl Channel {
         }
     }
 
-    pub async fn get_by_space<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<Vec<Channel>, DbError> {
-        let rows = db.query(include_str!("sql/get_by_space.sql"), &[space_id]).await?;
+    pub async fn get_by_space<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        with_archived: bool,
+    ) -> Result<Vec<Channel>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_by_space.sql"), &[space_id, &with_archived])
+            .await?;
         Ok(rows.into_iter().map(|row| row.get(0)).collect())
     }
 
@@ -89,6 +100,7 @@ impl Channel {
         db.execute(include_str!("sql/delete_channel.sql"), &[id]).await
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn edit<T: Querist>(
         db: &mut T,
         id: &Uuid,
@@ -128,6 +140,62 @@ impl Channel {
         Ok(row.try_get(0)?)
     }
 
+    pub async fn set_archived<T: Querist>(db: &mut T, id: &Uuid, archived: bool) -> Result<Option<Channel>, DbError> {
+        let result = db
+            .query_one(include_str!("sql/set_archived.sql"), &[id, &archived])
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    /// Messages can't be sent, edited or moved in archived channels.
+    pub fn check_writable(&self) -> Result<(), AppError> {
+        if self.archived {
+            return Err(AppError::NoPermission("The channel has been archived".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Users who may join the channel by themselves if it's private.
+    pub async fn allowed_users<T: Querist>(db: &mut T, id: &Uuid) -> Result<Vec<Uuid>, DbError> {
+        let rows = db.query(include_str!("sql/get_allowed_users.sql"), &[id]).await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    /// Replace the allowed users.
+    pub async fn set_allowed_users<T: Querist>(db: &mut T, id: &Uuid, user_ids: &[Uuid]) -> Result<(), DbError> {
+        db.execute(include_str!("sql/set_allowed_users.sql"), &[id, &user_ids])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_allowed_user<T: Querist>(db: &mut T, id: &Uuid, user_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/remove_allowed_user.sql"), &[id, user_id])
+            .await
+    }
+
+    pub async fn is_allowed<T: Querist>(db: &mut T, id: &Uuid, user_id: &Uuid) -> Result<bool, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/is_allowed.sql"), &[id, user_id])
+            .await?;
+        row.try_get(0)
+    }
+
+    /// Place the channel in a category, between the positions of `range`.
+    pub async fn move_to<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        category_id: Option<&Uuid>,
+        range: (Option<f64>, Option<f64>),
+    ) -> Result<Option<Channel>, DbError> {
+        let result = db
+            .query_one(
+                include_str!("sql/move_channel.sql"),
+                &[id, &category_id, &range.0, &range.1],
+            )
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
     pub async fn max_pos<T: Querist>(db: &mut T) -> Result<Vec<(Uuid, f64)>, DbError> {
         let rows = db.query(include_str!("sql/channel_max_pos.sql"), &[]).await?;
         let result: Vec<(Uuid, f64)> = rows.into_iter().map(|row| (row.get(0), row.get(1))).collect();
@@ -143,6 +211,11 @@ impl Channel {
             .map(|row| ChannelWithMember {
                 channel: row.get(0),
                 member: row.get(1),
+                unread: Some(Unread {
+                    read_pos: row.get(2),
+                    count: row.get(3),
+                    mentions: row.get(4),
+                }),
             })
             .collect();
         Ok(joined_channels)
@@ -203,12 +276,7 @@ impl ChannelMember {
         for row in rows {
             let member: ChannelMember = row.try_get(0)?;
             let id = member.channel_id;
-            if channel_member_map.contains_key(&id) {
-                let member_list = channel_member_map.get_mut(&id).unwrap();
-                member_list.push(member);
-            } else {
-                channel_member_map.insert(id, vec![member]);
-            }
+            channel_member_map.entry(id).or_default().push(member);
         }
         Ok(channel_member_map)
     }
@@ -257,6 +325,19 @@ impl ChannelMember {
         Ok(row.map(|row| row.get(0)))
     }
 
+    /// Move the read marker forward to `pos`, returns the marker after the update.
+    pub async fn set_read_marker<T: Querist>(
+        db: &mut T,
+        user_id: &Uuid,
+        channel_id: &Uuid,
+        pos: f64,
+    ) -> Result<f64, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/set_read_marker.sql"), &[user_id, channel_id, &pos])
+            .await?;
+        row.try_get(0)
+    }
+
     pub async fn remove_user<T: Querist>(db: &mut T, user_id: &Uuid, channel_id: &Uuid) -> Result<u64, DbError> {
         db.execute(include_str!("sql/remove_user_from_channel.sql"), &[user_id, channel_id])
             .await
@@ -366,6 +447,8 @@ impl Member {
 #[tokio::test]
 async fn channels_test() -> Result<(), crate::error::AppError> {
     use crate::database::Client;
+    use crate::media::Media;
+    use crate::messages::Message;
     use crate::spaces::Space;
     use crate::users::User;
 
@@ -389,13 +472,30 @@ async fn channels_test() -> Result<(), crate::error::AppError> {
     let space_ = Space::get_by_channel(db, &channel.id).await?.unwrap();
     assert_eq!(space.id, space_.id);
 
-    let channels = Channel::get_by_space(db, &space.id).await?;
+    let channels = Channel::get_by_space(db, &space.id, false).await?;
     assert_eq!(channels[0].id, channel.id);
 
+    // archive
+    let archived = Channel::set_archived(db, &channel.id, true).await?.unwrap();
+    assert!(archived.check_writable().is_err());
+    assert!(Channel::get_by_space(db, &space.id, false).await?.is_empty());
+    assert_eq!(Channel::get_by_space(db, &space.id, true).await?.len(), 1);
+    let channel = Channel::set_archived(db, &channel.id, false).await?.unwrap();
+    assert!(channel.check_writable().is_ok());
+
+    // categories
+    let category = ChannelCategory::create(db, &space.id, "Scenes").await?;
+    let category = ChannelCategory::edit(db, &category.id, "Archive").await?.unwrap();
+    assert_eq!(category.name, "Archive");
+    let categories = ChannelCategory::get_by_space(db, &space.id).await?;
+    assert_eq!(categories[0].id, category.id);
+    ChannelCategory::delete(db, &category.id).await?;
+    assert!(ChannelCategory::get_by_id(db, &category.id).await?.is_none());
+
     let new_name = "深水城水很深";
     let channel_edited = Channel::edit(db, &channel.id, Some(new_name), None, None, None, Some(false), None).await?;
     assert_eq!(channel_edited.name, new_name);
-    assert_eq!(channel_edited.is_public, false);
+    assert!(!channel_edited.is_public);
     let (_, space) = Channel::get_with_space(db, &channel.id).await?.unwrap();
     let channel = Channel::get_by_name(db, space.id, new_name).await?.unwrap();
     assert!(Channel::get_by_name(db, space.id, "Madoka").await?.is_none());
@@ -405,6 +505,62 @@ async fn channels_test() -> Result<(), crate::error::AppError> {
     let member = ChannelMember::add_user(db, &user.id, &channel.id, "", false).await?;
     let character_name = "Cocona";
     ChannelMember::set_name(db, &member.user_id, &member.channel_id, character_name).await?;
+
+    // private channels
+    let player = User::register(
+        db,
+        "channel_test_2@mythal.net",
+        "channel_test_user_2",
+        "Player",
+        password,
+    )
+    .await?;
+    SpaceMember::add_user(db, &player.id, &space.id).await?;
+    assert!(!Channel::is_allowed(db, &channel.id, &player.id).await?);
+    Channel::set_allowed_users(db, &channel.id, &[player.id, user.id]).await?;
+    assert!(Channel::is_allowed(db, &channel.id, &player.id).await?);
+    assert_eq!(Channel::remove_allowed_user(db, &channel.id, &player.id).await?, 1);
+    assert!(!Channel::is_allowed(db, &channel.id, &player.id).await?);
+    Channel::set_allowed_users(db, &channel.id, &[player.id, user.id]).await?;
+    Channel::set_allowed_users(db, &channel.id, &[user.id]).await?;
+    assert_eq!(Channel::allowed_users(db, &channel.id).await?, vec![user.id]);
+    ChannelInvitation::create(db, &channel.id, &player.id, &user.id).await?;
+    ChannelInvitation::create(db, &channel.id, &player.id, &user.id).await?;
+    let invitations = ChannelInvitation::get_by_user(db, &player.id).await?;
+    assert_eq!(invitations.len(), 1);
+    assert_eq!(invitations[0].channel.id, channel.id);
+    assert_eq!(ChannelInvitation::get_by_channel(db, &channel.id).await?.len(), 1);
+    assert!(ChannelInvitation::get(db, &channel.id, &player.id).await?.is_some());
+    assert_eq!(ChannelInvitation::remove(db, &channel.id, &player.id).await?, 1);
+    assert!(ChannelInvitation::get(db, &channel.id, &player.id).await?.is_none());
+
+    // media library
+    let media = Media::create(
+        db,
+        "image/png",
+        user.id,
+        "map.png",
+        "map.png",
+        "".to_string(),
+        0,
+        "",
+        None,
+        None,
+        None,
+    )
+    .await?;
+    Media::link(db, &media.id, &space.id, &channel.id).await?;
+    let gallery = Media::gallery(db, &space.id, &user.id, None, None, Some("image/%"), None, 16).await?;
+    assert_eq!(gallery[0].id, media.id);
+    let stranger = Uuid::nil();
+    assert!(Media::gallery(db, &space.id, &stranger, None, None, None, None, 16)
+        .await?
+        .is_empty());
+    let media = Media::set_description(db, &media.id, "The map of Mitakihara")
+        .await?
+        .unwrap();
+    assert_eq!(media.description, "The map of Mitakihara");
+    assert!(Media::is_space_admin(db, &media.id, &user.id).await?);
     let member_altered = ChannelMember::get(db, &user.id, &channel.id).await?.unwrap();
     assert_eq!(member.join_date, member_altered.join_date);
     assert_eq!(member_altered.character_name, character_name);
@@ -425,6 +581,24 @@ async fn channels_test() -> Result<(), crate::error::AppError> {
         .unwrap();
 
     let channel_2 = Channel::create(db, &space.id, "Test Channel 2", true, None).await?;
+    // messages before joining are not unread
+    let created = chrono::Utc::now().naive_utc();
+    Message::import(
+        db,
+        &Uuid::new_v4(),
+        &player.id,
+        &channel_2.id,
+        "Player",
+        "Before",
+        &serde_json::json!([]),
+        true,
+        false,
+        false,
+        None,
+        &created,
+        1.0,
+    )
+    .await?;
     ChannelMember::add_user(db, &user.id, &channel_2.id, "", false)
         .await
         .unwrap();
@@ -434,6 +608,20 @@ async fn channels_test() -> Result<(), crate::error::AppError> {
     assert_eq!(joined.len(), 2);
     assert_eq!(joined[0].member.channel_id, channel.id);
     assert_eq!(joined[1].member.channel_id, channel_2.id);
+    assert_eq!(joined[0].unread.as_ref().unwrap().count, 0);
+    assert_eq!(joined[1].unread.as_ref().unwrap().count, 0);
+
+    // read markers only move forward
+    assert_eq!(
+        ChannelMember::set_read_marker(db, &user.id, &channel.id, 4.0).await?,
+        4.0
+    );
+    assert_eq!(
+        ChannelMember::set_read_marker(db, &user.id, &channel.id, 2.0).await?,
+        4.0
+    );
+    let joined = Channel::get_by_user(db, user.id).await?;
+    assert_eq!(joined[0].unread.as_ref().unwrap().read_pos, 4.0);
 
     let member = Member::get_by_channel(db, channel.id).await?;
     assert_eq!(member.len(), 1);
@@ -451,3 +639,130 @@ async fn channels_test() -> Result<(), crate::error::AppError> {
     assert!(Channel::get_by_id(db, &channel.id).await?.is_none());
     Ok(())
 }
+
+/// A member of the space is invited to the channel, and joins it after accepting.
+#[derive(Debug, Clone, Serialize, Deserialize, FromSql)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "channel_invitations")]
+pub struct ChannelInvitation {
+    pub channel_id: Uuid,
+    pub user_id: Uuid,
+    pub inviter_id: Uuid,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl ChannelInvitation {
+    /// Inviting again replaces the previous invitation.
+    pub async fn create<T: Querist>(
+        db: &mut T,
+        channel_id: &Uuid,
+        user_id: &Uuid,
+        inviter_id: &Uuid,
+    ) -> Result<ChannelInvitation, DbError> {
+        let row = db
+            .query_exactly_one(
+                include_str!("sql/create_invitation.sql"),
+                &[channel_id, user_id, inviter_id],
+            )
+            .await?;
+        row.try_get(0)
+    }
+
+    pub async fn get<T: Querist>(
+        db: &mut T,
+        channel_id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<Option<ChannelInvitation>, DbError> {
+        let result = db
+            .query_one(include_str!("sql/get_invitation.sql"), &[channel_id, user_id])
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn get_by_user<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<Vec<InvitationWithChannel>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_invitations_by_user.sql"), &[user_id])
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(InvitationWithChannel {
+                    invitation: row.try_get(0)?,
+                    channel: row.try_get(1)?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn get_by_channel<T: Querist>(db: &mut T, channel_id: &Uuid) -> Result<Vec<ChannelInvitation>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_invitations_by_channel.sql"), &[channel_id])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn remove<T: Querist>(db: &mut T, channel_id: &Uuid, user_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/remove_invitation.sql"), &[channel_id, user_id])
+            .await
+    }
+}
+
+/// A group of channels in the sidebar of a space.
+#[derive(Debug, Clone, Serialize, Deserialize, FromSql)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "channel_categories")]
+pub struct ChannelCategory {
+    pub id: Uuid,
+    pub space_id: Uuid,
+    pub name: String,
+    pub pos: f64,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl ChannelCategory {
+    pub async fn create<T: Querist>(db: &mut T, space_id: &Uuid, name: &str) -> Result<ChannelCategory, ModelError> {
+        let name = merge_blank(name);
+        crate::validators::DISPLAY_NAME.run(&name)?;
+        let row = db
+            .query_exactly_one(include_str!("sql/create_category.sql"), &[space_id, &name])
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    pub async fn get_by_id<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<ChannelCategory>, DbError> {
+        let result = db.query_one(include_str!("sql/get_category.sql"), &[id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn get_by_space<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<Vec<ChannelCategory>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_categories_by_space.sql"), &[space_id])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn edit<T: Querist>(db: &mut T, id: &Uuid, name: &str) -> Result<Option<ChannelCategory>, ModelError> {
+        let name = merge_blank(name);
+        crate::validators::DISPLAY_NAME.run(&name)?;
+        let result = db.query_one(include_str!("sql/edit_category.sql"), &[id, &name]).await;
+        Ok(inner_result_map(result, |row| row.try_get(0))?)
+    }
+
+    /// Place the category between the positions of `range`.
+    pub async fn move_to<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        range: (Option<f64>, Option<f64>),
+    ) -> Result<Option<ChannelCategory>, DbError> {
+        let result = db
+            .query_one(include_str!("sql/move_category.sql"), &[id, &range.0, &range.1])
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    /// Channels in the category become uncategorized.
+    pub async fn delete<T: Querist>(db: &mut T, id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/delete_category.sql"), &[id]).await
+    }
+}
@@ -3,7 +3,15 @@ WITH add(channel_members) AS (
         VALUES ($1, $2, $3, $4, true)
         ON CONFLICT (user_id, channel_id) DO UPDATE SET is_joined = true, character_name = $3
         RETURNING channel_members
-)
+),
+     -- The history before joining is not unread.
+     marker AS (
+         INSERT INTO channel_read_markers (user_id, channel_id, pos)
+             SELECT $1, $2, coalesce(max(pos), 0.0)
+             FROM messages
+             WHERE channel_id = $2 AND deleted = false
+             ON CONFLICT (user_id, channel_id) DO NOTHING
+     )
 SELECT true AS created, channel_members FROM add
 UNION ALL
 SELECT false AS created, channel_members FROM channel_members
@@ -0,0 +1,3 @@
+INSERT INTO channel_categories (space_id, name, pos)
+VALUES ($1, $2, (SELECT COALESCE(max(pos), 0.0) + 1.0 FROM channel_categories WHERE space_id = $1))
+RETURNING channel_categories;
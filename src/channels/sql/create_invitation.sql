@@ -0,0 +1,5 @@
+INSERT INTO channel_invitations (channel_id, user_id, inviter_id)
+VALUES ($1, $2, $3)
+ON CONFLICT (channel_id, user_id) DO UPDATE SET inviter_id = excluded.inviter_id,
+                                                created    = excluded.created
+RETURNING channel_invitations;
@@ -0,0 +1,4 @@
+UPDATE channel_categories
+SET name = $2
+WHERE id = $1
+RETURNING channel_categories;
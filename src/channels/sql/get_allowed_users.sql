@@ -0,0 +1,3 @@
+SELECT user_id
+FROM channel_allowed_users
+WHERE channel_id = $1;
@@ -1,5 +1,7 @@
 SELECT channel
 FROM channels channel
+    LEFT JOIN channel_categories category ON category.id = channel.category_id
 WHERE channel.space_id = $1
   AND deleted = false
-ORDER BY channel.created;
+  AND (NOT channel.archived OR $2)
+ORDER BY category.pos NULLS FIRST, category.created, channel.pos, channel.created;
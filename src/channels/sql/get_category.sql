@@ -0,0 +1,3 @@
+SELECT channel_categories
+FROM channel_categories
+WHERE id = $1;
@@ -1,5 +1,17 @@
-SELECT c, cm
+SELECT c,
+       cm,
+       coalesce(rm.pos, 0.0),
+       count(m.id),
+       count(n.id)
 FROM channel_members cm
     INNER JOIN channels c ON cm.channel_id = c.id AND c.deleted = false
     INNER JOIN space_members sm ON cm.user_id = sm.user_id AND c.space_id = sm.space_id
-WHERE cm.user_id = $1 AND cm.is_joined;
+    LEFT JOIN channel_read_markers rm ON rm.user_id = cm.user_id AND rm.channel_id = cm.channel_id
+    LEFT JOIN messages m ON m.channel_id = cm.channel_id
+        AND m.pos > coalesce(rm.pos, 0.0)
+        AND m.deleted = false
+        AND m.sender_id <> cm.user_id
+        AND (m.whisper_to_users IS NULL OR cm.is_master OR cm.user_id = ANY (m.whisper_to_users))
+    LEFT JOIN notifications n ON n.message_id = m.id AND n.user_id = cm.user_id
+WHERE cm.user_id = $1 AND cm.is_joined
+GROUP BY c.id, cm.user_id, cm.channel_id, rm.pos;
@@ -0,0 +1,3 @@
+SELECT channel_invitations
+FROM channel_invitations
+WHERE channel_id = $1 AND user_id = $2;
@@ -0,0 +1,4 @@
+SELECT channel_invitations
+FROM channel_invitations
+WHERE channel_id = $1
+ORDER BY created;
@@ -0,0 +1,5 @@
+SELECT i, c
+FROM channel_invitations i
+         INNER JOIN channels c ON c.id = i.channel_id AND c.deleted = false
+WHERE i.user_id = $1
+ORDER BY i.created DESC;
@@ -0,0 +1 @@
+SELECT EXISTS(SELECT 1 FROM channel_allowed_users WHERE channel_id = $1 AND user_id = $2);
@@ -0,0 +1,5 @@
+UPDATE channel_categories
+SET pos = rational_intermediate(COALESCE($2::float, 0.0)::rational,
+                                COALESCE($3::float, ceil(COALESCE($2::float, 0.0) + 2.0))::rational)
+WHERE id = $1
+RETURNING channel_categories;
@@ -0,0 +1,6 @@
+UPDATE channels
+SET category_id = $2,
+    pos         = rational_intermediate(COALESCE($3::float, 0.0)::rational,
+                                        COALESCE($4::float, ceil(COALESCE($3::float, 0.0) + 2.0))::rational)
+WHERE id = $1
+RETURNING channels;
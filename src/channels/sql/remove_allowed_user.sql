@@ -0,0 +1,4 @@
+DELETE
+FROM channel_allowed_users
+WHERE channel_id = $1
+  AND user_id = $2;
@@ -0,0 +1,3 @@
+DELETE
+FROM channel_invitations
+WHERE channel_id = $1 AND user_id = $2;
@@ -0,0 +1,8 @@
+WITH removed AS (
+    DELETE FROM channel_allowed_users
+        WHERE channel_id = $1 AND user_id <> ALL ($2::uuid[])
+)
+INSERT
+INTO channel_allowed_users (channel_id, user_id)
+SELECT $1, unnest($2::uuid[])
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,4 @@
+UPDATE channels
+SET archived = $2
+WHERE id = $1 AND deleted = false
+RETURNING channels;
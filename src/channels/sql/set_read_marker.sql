@@ -0,0 +1,6 @@
+INSERT INTO channel_read_markers (user_id, channel_id, pos)
+VALUES ($1, $2, $3)
+ON CONFLICT (user_id, channel_id) DO UPDATE
+    SET pos      = greatest(channel_read_markers.pos, excluded.pos),
+        modified = (now() at time zone 'utc')
+RETURNING pos;
@@ -5,6 +5,11 @@ use once_cell::sync::OnceCell;
 static DEBUG: OnceCell<bool> = OnceCell::new();
 static SYSTEMD: OnceCell<bool> = OnceCell::new();
 static SECRET: OnceCell<String> = OnceCell::new();
+static MEDIA_REDIRECT: OnceCell<bool> = OnceCell::new();
+static USER_STORAGE_QUOTA: OnceCell<Option<u64>> = OnceCell::new();
+static SPACE_STORAGE_QUOTA: OnceCell<Option<u64>> = OnceCell::new();
+static SCAN_QUARANTINE: OnceCell<bool> = OnceCell::new();
+static WEBHOOK_ALLOW_LOCAL: OnceCell<bool> = OnceCell::new();
 
 fn env_bool<T: AsRef<str>>(s: T) -> bool {
     let s = s.as_ref().trim();
@@ -37,3 +42,41 @@ pub fn media_path() -> &'static Path {
     }
     MEDIA_PATH.get_or_init(|| path)
 }
+
+/// Redirect media downloads to the storage, if it supports presigned URLs.
+pub fn media_redirect() -> bool {
+    *MEDIA_REDIRECT.get_or_init(|| env::var("MEDIA_REDIRECT").map(env_bool).unwrap_or(false))
+}
+
+/// Read a size in MiB, zero means unlimited.
+fn env_quota(key: &str, default: u64) -> Option<u64> {
+    let megabytes = env::var(key)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(default);
+    if megabytes == 0 {
+        None
+    } else {
+        Some(megabytes * 1024 * 1024)
+    }
+}
+
+/// The maximum total size of uploads of a user, in bytes.
+pub fn user_storage_quota() -> Option<u64> {
+    *USER_STORAGE_QUOTA.get_or_init(|| env_quota("USER_STORAGE_QUOTA", 1024))
+}
+
+/// The maximum total size of media posted in a space, in bytes.
+pub fn space_storage_quota() -> Option<u64> {
+    *SPACE_STORAGE_QUOTA.get_or_init(|| env_quota("SPACE_STORAGE_QUOTA", 4096))
+}
+
+/// Keep files rejected by the scanner instead of deleting them.
+pub fn scan_quarantine() -> bool {
+    *SCAN_QUARANTINE.get_or_init(|| env::var("SCAN_QUARANTINE").map(env_bool).unwrap_or(false))
+}
+
+/// Let webhooks reach loopback and private addresses over plain HTTP, for developing receivers only.
+pub fn webhook_allow_local() -> bool {
+    *WEBHOOK_ALLOW_LOCAL.get_or_init(|| env::var("WEBHOOK_ALLOW_LOCAL").map(env_bool).unwrap_or(false))
+}
@@ -3,8 +3,8 @@ use std::collections::HashMap;
 use std::convert::{From, Into};
 use std::env;
 use std::hash::BuildHasher;
-pub use tokio_postgres::types::{ToSql, Type as SqlType};
-use tokio_postgres::{Row, Statement};
+pub use tokio_postgres::types::ToSql;
+use tokio_postgres::{Row, RowStream, Statement};
 
 use async_trait::async_trait;
 
@@ -155,6 +155,17 @@ impl Client {
         Ok(Transaction { transaction, prepared })
     }
 
+    /// Rows are received one by one instead of being collected, for large results.
+    pub async fn query_stream<T: Into<Sql>>(
+        &mut self,
+        source: T,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<RowStream, DbError> {
+        let statement = self.get_statement(source.into(), &[]).await?;
+        let stream = self.client.query_raw(&statement, params.iter().copied()).await;
+        self.check_broken(stream)
+    }
+
     async fn get_statement(&mut self, source: Sql, types: &[postgres_types::Type]) -> Result<Statement, DbError> {
         if let Some(statement) = self.prepared.get(&source) {
             Ok(statement.clone())
@@ -34,6 +34,8 @@ pub enum AppError {
     MethodNotAllowed,
     #[error("\"{0}\" already exists")]
     Conflict(String),
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
     #[error("An I/O error occurred")]
     Hyper {
         #[from]
@@ -58,6 +60,7 @@ impl AppError {
             Validation(_) | BadRequest(_) => StatusCode::BAD_REQUEST,
             MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
             Conflict(_) => StatusCode::CONFLICT,
+            TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -72,6 +75,7 @@ impl AppError {
             BadRequest(_) => "BAD_REQUEST",
             MethodNotAllowed => "METHOD_NOT_ALLOWED",
             Conflict(_) => "CONFLICT",
+            TooManyRequests(_) => "TOO_MANY_REQUESTS",
             _ => "UNEXPECTED",
         }
     }
@@ -1,5 +1,6 @@
 mod api;
 pub mod context;
+#[allow(clippy::module_inception)]
 mod events;
 mod handlers;
 mod models;
@@ -35,6 +35,17 @@ pub fn get_heartbeat_map() -> &'static HeartbeatMap {
     HEARTBEAT_MAP.get_or_init(|| Mutex::new(HashMap::new()))
 }
 
+static BLOCKS_CHANGED: OnceCell<broadcast::Sender<Uuid>> = OnceCell::new();
+
+/// Tell the open connections of the user to reload the blocked users.
+pub fn blocks_changed(user_id: Uuid) {
+    get_blocks_changed().send(user_id).ok();
+}
+
+pub fn get_blocks_changed() -> &'static broadcast::Sender<Uuid> {
+    BLOCKS_CHANGED.get_or_init(|| broadcast::channel(64).0)
+}
+
 pub async fn get_mailbox_broadcast_rx(id: &Uuid) -> broadcast::Receiver<Arc<SyncEvent>> {
     let broadcast_table = get_broadcast_table();
     let table = broadcast_table.read().await;
@@ -70,12 +81,12 @@ impl Cache {
 
     pub async fn try_mailbox(&self, mailbox_id: &Uuid) -> Option<Arc<Mutex<MailBoxCache>>> {
         let map = self.mailboxes.read().await;
-        map.get(&mailbox_id).cloned()
+        map.get(mailbox_id).cloned()
     }
 
     pub async fn mailbox(&self, mailbox_id: &Uuid) -> Arc<Mutex<MailBoxCache>> {
         let map = self.mailboxes.read().await;
-        if let Some(cache) = map.get(&mailbox_id) {
+        if let Some(cache) = map.get(mailbox_id) {
             cache.clone()
         } else {
             drop(map);
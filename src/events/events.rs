@@ -5,13 +5,14 @@ use crate::events::context;
 use crate::events::context::SyncEvent;
 use crate::events::preview::{Preview, PreviewPost};
 use crate::messages::Message;
+use crate::notifications::api::NotificationWithMessage;
 use crate::spaces::api::SpaceWithRelated;
 use crate::spaces::models::{space_users_status, StatusKind, UserStatus};
 use crate::utils::timestamp;
 use crate::{cache, database};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::spawn;
 use uuid::Uuid;
@@ -79,9 +80,20 @@ pub enum EventBody {
     },
     #[serde(rename_all = "camelCase")]
     SpaceUpdated {
-        space_with_related: SpaceWithRelated,
+        space_with_related: Box<SpaceWithRelated>,
     },
     AppUpdated,
+    /// Sent to the mailbox of the user who read the channel.
+    #[serde(rename_all = "camelCase")]
+    ReadMarker {
+        user_id: Uuid,
+        channel_id: Uuid,
+        pos: f64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Notification {
+        notification: Box<NotificationWithMessage>,
+    },
 }
 
 #[derive(Serialize, Debug)]
@@ -169,6 +181,18 @@ impl Event {
         });
     }
 
+    /// Sent to the mailbox of the user, to sync the devices.
+    pub fn read_marker(user_id: Uuid, channel_id: Uuid, pos: f64) {
+        Event::transient(user_id, EventBody::ReadMarker { user_id, channel_id, pos })
+    }
+
+    /// Sent to the mailbox of the recipient.
+    pub fn notification(notification: NotificationWithMessage) {
+        let mailbox = notification.notification.user_id;
+        let notification = Box::new(notification);
+        Event::transient(mailbox, EventBody::Notification { notification })
+    }
+
     pub fn channel_edited(channel: Channel) {
         let space_id = channel.space_id;
         let channel_id = channel.id;
@@ -179,7 +203,7 @@ impl Event {
         cache::make_key(b"mailbox", mailbox, b"events")
     }
 
-    pub async fn get_from_cache(mailbox: &Uuid) -> Vec<String> {
+    pub async fn get_cached(mailbox: &Uuid) -> Vec<Arc<SyncEvent>> {
         let cache = super::context::get_cache().try_mailbox(mailbox).await;
         if let Some(cache) = cache {
             let cache = cache.lock().await;
@@ -187,18 +211,36 @@ impl Event {
                 .events
                 .iter()
                 .chain(cache.preview_map.values())
-                .map(|event| event.encoded.clone())
+                .cloned()
                 .collect()
         } else {
             vec![]
         }
     }
 
+    pub async fn get_from_cache(mailbox: &Uuid) -> Vec<String> {
+        Event::get_cached(mailbox)
+            .await
+            .into_iter()
+            .map(|event| event.encoded.clone())
+            .collect()
+    }
+
+    /// The previews of blocked users are not sent to the blocker.
+    pub fn is_hidden_from(&self, blocked: &HashSet<Uuid>) -> bool {
+        match &self.body {
+            EventBody::MessagePreview { preview, .. } => blocked.contains(&preview.sender_id),
+            _ => false,
+        }
+    }
+
     pub fn space_updated(space_id: Uuid) {
         tokio::spawn(async move {
             match crate::spaces::handlers::space_related(&space_id).await {
                 Ok(space_with_related) => {
-                    let body = EventBody::SpaceUpdated { space_with_related };
+                    let body = EventBody::SpaceUpdated {
+                        space_with_related: Box::new(space_with_related),
+                    };
                     Event::transient(space_id, body);
                 }
                 Err(e) => log::error!(
@@ -210,6 +252,7 @@ impl Event {
     }
 
     async fn send(mailbox: Uuid, event: Arc<SyncEvent>) {
+        crate::webhooks::dispatch(mailbox, event.clone());
         let broadcast_table = context::get_broadcast_table();
         let table = broadcast_table.read().await;
         if let Some(tx) = table.get(&mailbox) {
@@ -5,11 +5,12 @@ use crate::cache::make_key;
 use crate::csrf::authenticate;
 use crate::database::Querist;
 use crate::error::{AppError, Find};
-use crate::events::context::get_mailbox_broadcast_rx;
+use crate::events::context::{get_blocks_changed, get_mailbox_broadcast_rx};
 use crate::events::events::ClientEvent;
 use crate::interface::{missing, ok_response, parse_query, Request, Response};
 use crate::spaces::models::StatusKind;
 use crate::spaces::{Space, SpaceMember};
+use crate::users::{User, UserBlock};
 use crate::utils::timestamp;
 use crate::websocket::{establish_web_socket, WsError, WsMessage};
 use crate::{cache, database};
@@ -17,6 +18,7 @@ use anyhow::anyhow;
 use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt, TryStreamExt};
 use hyper::upgrade::Upgraded;
+use std::collections::HashSet;
 use std::time::Duration;
 use tokio_stream::StreamExt as _;
 use tokio_tungstenite::tungstenite;
@@ -33,18 +35,30 @@ async fn check_space_perms<T: Querist>(
     if !space.allow_spectator {
         match user_id {
             Ok(user_id) => {
-                SpaceMember::get(db, &user_id, &space.id).await.or_no_permission()?;
+                SpaceMember::get(db, user_id, &space.id).await.or_no_permission()?;
             }
             Err(err) => {
                 log::warn!("Failed to verify session: {:?}", err);
-                return Err(AppError::Unauthenticated(format!("space do not allow spectator")));
+                return Err(AppError::Unauthenticated("space do not allow spectator".to_string()));
             }
         }
     }
     Ok(())
 }
 
-async fn push_events(mailbox: Uuid, outgoing: &mut Sender) -> Result<(), anyhow::Error> {
+async fn get_blocked<T: Querist>(db: &mut T, user_id: Option<&Uuid>) -> Result<HashSet<Uuid>, AppError> {
+    match user_id {
+        Some(user_id) => Ok(UserBlock::get_blocked_ids(db, user_id).await?.into_iter().collect()),
+        None => Ok(HashSet::new()),
+    }
+}
+
+async fn push_events(
+    mailbox: Uuid,
+    user_id: Option<Uuid>,
+    blocked: HashSet<Uuid>,
+    outgoing: &mut Sender,
+) -> Result<(), anyhow::Error> {
     use futures::channel::mpsc::channel;
     use tokio::sync::broadcast::error::RecvError;
     use tokio::time::interval;
@@ -64,11 +78,15 @@ async fn push_events(mailbox: Uuid, outgoing: &mut Sender) -> Result<(), anyhow:
 
     let push = async {
         let mut tx = tx.clone();
+        let mut blocked = blocked;
+        let mut blocks_changed_rx = get_blocks_changed().subscribe();
         let mut mailbox_rx = get_mailbox_broadcast_rx(&mailbox).await;
 
-        let cached_events = Event::get_from_cache(&mailbox).await;
+        let cached_events = Event::get_cached(&mailbox).await;
         for e in cached_events.into_iter() {
-            tx.send(WsMessage::Text(e)).await.ok();
+            if !e.event.is_hidden_from(&blocked) {
+                tx.send(WsMessage::Text(e.encoded.clone())).await.ok();
+            }
         }
         tx.send(WsMessage::Text(
             serde_json::to_string(&Event::initialized(mailbox)).unwrap(),
@@ -77,7 +95,23 @@ async fn push_events(mailbox: Uuid, outgoing: &mut Sender) -> Result<(), anyhow:
         .ok();
 
         loop {
-            let message = match mailbox_rx.recv().await {
+            let received = tokio::select! {
+                received = mailbox_rx.recv() => received,
+                changed = blocks_changed_rx.recv() => {
+                    let reload = match changed {
+                        Ok(changed) => Some(changed) == user_id,
+                        Err(RecvError::Lagged(_)) => true,
+                        Err(RecvError::Closed) => false,
+                    };
+                    if reload {
+                        let mut conn = database::get().await?;
+                        blocked = get_blocked(&mut *conn, user_id.as_ref()).await?;
+                    }
+                    continue;
+                }
+            };
+            let message = match received {
+                Ok(event) if event.event.is_hidden_from(&blocked) => continue,
                 Ok(event) => WsMessage::Text(event.encoded.clone()),
                 Err(RecvError::Lagged(lagged)) => {
                     log::warn!("lagged {} at {}", lagged, mailbox);
@@ -114,7 +148,7 @@ async fn handle_client_event(mailbox: Uuid, user_id: Option<Uuid>, message: Stri
     let event = event.unwrap();
     match event {
         ClientEvent::Preview { preview } => {
-            let user_id = user_id.ok_or(AppError::Unauthenticated(format!("user id is empty")))?;
+            let user_id = user_id.ok_or_else(|| AppError::Unauthenticated("user id is empty".to_string()))?;
             preview.broadcast(mailbox, user_id).await?;
         }
         ClientEvent::Status { kind, focus } => {
@@ -151,13 +185,17 @@ async fn connect(req: Request) -> Result<Response, anyhow::Error> {
     let space = Space::get_by_id(db, &mailbox).await?;
     if let Some(space) = space.as_ref() {
         check_space_perms(db, space, &user_id).await?;
+    } else if User::get_by_id(db, &mailbox).await?.is_some() && user_id.as_ref().ok() != Some(&mailbox) {
+        // The mailbox of a user is private.
+        return Err(AppError::NoPermission("Not your mailbox".to_string()).into());
     }
     let user_id = user_id.ok();
+    let blocked = get_blocked(db, user_id.as_ref()).await?;
     establish_web_socket(req, move |ws_stream| async move {
         let (mut outgoing, incoming) = ws_stream.split();
 
         let server_push_events = async move {
-            if let Err(e) = push_events(mailbox, &mut outgoing).await {
+            if let Err(e) = push_events(mailbox, user_id, blocked, &mut outgoing).await {
                 log::warn!("Failed to push events: {}", e);
             }
             outgoing.close().await.ok();
@@ -1,4 +1,4 @@
-use crate::channels::ChannelMember;
+use crate::channels::{Channel, ChannelMember};
 use crate::database;
 use crate::error::AppError;
 use crate::events::Event;
@@ -77,6 +77,10 @@ impl PreviewPost {
             .await
             .or_no_permission()?
             .is_master;
+        Channel::get_by_id(db, &channel_id)
+            .await
+            .or_not_found()?
+            .check_writable()?;
         let whisper_to_users = None;
         let preview = Box::new(Preview {
             id,
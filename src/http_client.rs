@@ -0,0 +1,188 @@
+//! A shared HTTP(S) client for talking to external services.
+use anyhow::Context;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tokio::net::TcpStream;
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+pub fn client() -> &'static HttpClient {
+    static CLIENT: OnceCell<HttpClient> = OnceCell::new();
+    CLIENT.get_or_init(|| Client::builder().build(HttpsConnector::with_native_roots()))
+}
+
+/// A client for URLs given by users, which must not reach into the network of the server.
+pub type PublicClient = Client<HttpsConnector<PublicConnector>>;
+
+pub fn public_client(allow_local: bool) -> PublicClient {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    let mut tls = rustls::ClientConfig::new();
+    tls.root_store = rustls_native_certs::load_native_certs().unwrap_or_else(|(partial, e)| {
+        log::warn!("Failed to load some native certificates: {}", e);
+        partial.unwrap_or_else(rustls::RootCertStore::empty)
+    });
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let connector = PublicConnector { http, allow_local };
+    Client::builder().build(HttpsConnector::from((connector, tls)))
+}
+
+/// Whether the address may be reached from the outside.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    let shared = a == 100 && (64..128).contains(&b);
+    !(a == 0
+        || shared
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation())
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    if segments[..5] == [0; 5] && segments[5] == 0xffff {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    let unique_local = segments[0] & 0xfe00 == 0xfc00;
+    let link_local = segments[0] & 0xffc0 == 0xfe80;
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+}
+
+/// Resolves the host once and connects to the address it checked, so the answer can't change in between.
+#[derive(Clone)]
+pub struct PublicConnector {
+    http: HttpConnector,
+    allow_local: bool,
+}
+
+/// Refused if any address of the host is not public.
+async fn resolve_public(uri: &Uri, allow_local: bool) -> Result<SocketAddr, anyhow::Error> {
+    let host = uri.host().ok_or_else(|| anyhow::anyhow!("no host in {}", uri))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let default_port = if uri.scheme_str() == Some("http") { 80 } else { 443 };
+    let port = uri.port_u16().unwrap_or(default_port);
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+    if let Some(address) = addresses
+        .iter()
+        .find(|address| !allow_local && !is_public(address.ip()))
+    {
+        return Err(anyhow::anyhow!("refused to connect to {}", address.ip()));
+    }
+    addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{} has no address", host))
+}
+
+impl Service<Uri> for PublicConnector {
+    type Response = TcpStream;
+    type Error = anyhow::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, anyhow::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let mut http = self.http.clone();
+        let allow_local = self.allow_local;
+        Box::pin(async move {
+            let address = resolve_public(&uri, allow_local).await?;
+            // An IP address in the URI is used as it is, without resolving again.
+            let resolved = Uri::builder()
+                .scheme(uri.scheme_str().unwrap_or("https"))
+                .authority(&*address.to_string())
+                .path_and_query("/")
+                .build()?;
+            Ok(http.call(resolved).await?)
+        })
+    }
+}
+
+async fn read_json<T: DeserializeOwned>(request: Request<Body>) -> Result<T, anyhow::Error> {
+    let uri = request.uri().clone();
+    let response = client().request(request).await?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    if status != StatusCode::OK {
+        return Err(anyhow::anyhow!(
+            "{} responded {}: {}",
+            uri,
+            status,
+            String::from_utf8_lossy(&*body)
+        ));
+    }
+    serde_json::from_slice(&*body).with_context(|| format!("Failed to parse the response of {}", uri))
+}
+
+pub async fn get_json<T: DeserializeOwned>(uri: &str) -> Result<T, anyhow::Error> {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .header(hyper::header::ACCEPT, "application/json")
+        .body(Body::empty())?;
+    read_json(request).await
+}
+
+pub async fn post_form<T: DeserializeOwned, F: Serialize>(uri: &str, form: &F) -> Result<T, anyhow::Error> {
+    let body = serde_urlencoded::to_string(form)?;
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(hyper::header::ACCEPT, "application/json")
+        .header(hyper::header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(body))?;
+    read_json(request).await
+}
+
+#[test]
+fn is_public_test() {
+    let public = |ip: &str| is_public(ip.parse().unwrap());
+    assert!(public("93.184.216.34"));
+    assert!(public("2606:2800:220:1:248:1893:25c8:1946"));
+    assert!(!public("127.0.0.1"));
+    assert!(!public("10.1.2.3"));
+    assert!(!public("172.16.0.1"));
+    assert!(!public("192.168.1.1"));
+    assert!(!public("169.254.169.254"));
+    assert!(!public("100.64.0.1"));
+    assert!(!public("0.0.0.0"));
+    assert!(!public("::1"));
+    assert!(!public("::"));
+    assert!(!public("fe80::1"));
+    assert!(!public("fd00::1"));
+    assert!(!public("::ffff:127.0.0.1"));
+}
+
+#[tokio::test]
+async fn resolve_public_test() {
+    let resolve =
+        |uri: &'static str, allow_local| async move { resolve_public(&uri.parse().unwrap(), allow_local).await };
+    assert!(resolve("https://127.0.0.1/hook", false).await.is_err());
+    assert!(resolve("https://169.254.169.254:8080/latest", false).await.is_err());
+    assert!(resolve("https://[::1]/hook", false).await.is_err());
+    assert!(resolve("https://localhost/hook", false).await.is_err());
+    let address = resolve("http://127.0.0.1:8080/hook", true).await.unwrap();
+    assert_eq!(address, "127.0.0.1:8080".parse().unwrap());
+}
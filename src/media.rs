@@ -1,7 +1,15 @@
 mod api;
 mod handlers;
+mod images;
 mod models;
+mod policy;
+mod range;
+mod resumable;
+mod s3;
+mod scan;
+mod storage;
+pub mod tasks;
 
-pub use api::Upload;
-pub use handlers::{router, upload, upload_params};
+pub use handlers::{check_space_quota, content_disposition, router, upload, upload_params};
 pub use models::Media;
+pub use storage::storage;
@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use super::policy::UploadPolicy;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Deserialize)]
@@ -6,6 +8,8 @@ use uuid::Uuid;
 pub struct Upload {
     pub filename: String,
     pub mime_type: Option<String>,
+    /// The space which the file will be posted in, counted in its quota.
+    pub space_id: Option<Uuid>,
 }
 
 #[derive(Deserialize)]
@@ -15,4 +19,84 @@ pub struct MediaQuery {
     pub id: Option<Uuid>,
     #[serde(default)]
     pub download: bool,
+    /// Serve a thumbnail which fits in this size, for images.
+    pub size: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageUsage {
+    /// In bytes.
+    pub used: i64,
+    /// In bytes, `None` means unlimited.
+    pub quota: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewResumableUpload {
+    pub filename: String,
+    pub mime_type: Option<String>,
+    pub space_id: Option<Uuid>,
+    /// The size of the whole file.
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkQuery {
+    pub id: Uuid,
+    /// Where the chunk starts in the file.
+    pub offset: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadProgress {
+    pub id: Uuid,
+    pub size: u64,
+    /// How many bytes have been received, the next chunk should start here.
+    pub offset: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Gallery {
+    pub space_id: Uuid,
+    pub channel_id: Option<Uuid>,
+    pub uploader_id: Option<Uuid>,
+    /// e.g. `image` or `image/png`
+    pub mime_type: Option<String>,
+    #[serde(default, with = "crate::date_format::option")]
+    pub before: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditMedia {
+    pub id: Uuid,
+    pub description: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyQuery {
+    pub space_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadPolicies {
+    pub deployment: UploadPolicy,
+    /// Further restrictions of the space.
+    pub space: Option<UploadPolicy>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditSpacePolicy {
+    pub space_id: Uuid,
+    /// `None` to remove the policy of the space.
+    pub policy: Option<UploadPolicy>,
 }
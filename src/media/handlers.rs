@@ -1,78 +1,252 @@
 use super::api::Upload;
 use super::models::Media;
+use crate::context::{media_redirect, space_storage_quota, user_storage_quota};
 use crate::csrf::authenticate;
-use crate::database;
-use crate::error::{AppError, Find, ValidationFailed};
-use crate::interface::{missing, ok_response, parse_query, Response};
-use crate::media::api::MediaQuery;
+use crate::database::{self, Querist};
+use crate::error::{AppError, Find};
+use crate::interface::{self, missing, ok_response, parse_query, IdQuery, Response};
+use crate::media::api::{
+    ChunkQuery, EditMedia, EditSpacePolicy, Gallery, MediaQuery, NewResumableUpload, PolicyQuery, StorageUsage,
+    UploadPolicies, UploadProgress,
+};
+use crate::media::images;
 use crate::media::models::MediaFile;
+use crate::media::policy::{upload_policy, Policies, UploadPolicy};
+use crate::media::range::{self, ByteRange, Ranges};
+use crate::media::resumable::{self, Progress, UploadSession};
+use crate::media::scan;
+use crate::media::storage::{storage, ResponseHeaders};
+use crate::spaces::permissions::{self, Permission};
+use crate::spaces::SpaceMember;
 use crate::utils;
+use chrono::naive::NaiveDateTime;
 use futures::StreamExt;
-use hyper::header::{self, HeaderValue};
+use hyper::header::{self, HeaderMap, HeaderValue};
 use hyper::{Body, Request, Uri};
-use std::path::PathBuf;
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
 
-fn content_disposition(attachment: bool, filename: &str) -> HeaderValue {
+pub fn content_disposition(attachment: bool, filename: &str) -> HeaderValue {
     use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
     let kind = if attachment { "attachment" } else { "inline" };
-    const SET: &AsciiSet = &NON_ALPHANUMERIC;
+    const SET: &AsciiSet = NON_ALPHANUMERIC;
     let filename = utf8_percent_encode(filename, SET).to_string();
     HeaderValue::from_str(&*format!("{}; filename*=utf-8''{}", kind, filename)).unwrap()
 }
 
+/// Types which browsers can't run scripts in, others are always downloaded.
+const INLINE_MIME_TYPES: &[&str] = &[
+    "audio/aac",
+    "audio/flac",
+    "audio/mpeg",
+    "audio/ogg",
+    "audio/wav",
+    "audio/webm",
+    "text/plain",
+    "video/mp4",
+    "video/ogg",
+    "video/webm",
+];
+
+fn inline_allowed(mime_type: &str) -> bool {
+    images::format_from_mime_type(mime_type).is_some() || INLINE_MIME_TYPES.contains(&mime_type)
+}
+
+/// How long redirected URLs stay valid at least.
+const REDIRECT_EXPIRES: Duration = Duration::from_secs(60 * 60);
+
 fn filename_sanitizer(filename: String) -> String {
     let filename_replace = regex!(r#"[/?*:|<>\\]"#);
     filename_replace.replace_all(&filename, "_").to_string()
 }
 
 pub fn upload_params(uri: &Uri) -> Result<Upload, AppError> {
-    let Upload { filename, mime_type } = parse_query(uri)?;
-    if filename.len() > 200 {
-        return Err(ValidationFailed("File Name is too long").into());
-    }
+    let Upload {
+        filename,
+        mime_type,
+        space_id,
+    } = parse_query(uri)?;
     let filename = filename_sanitizer(filename);
-    Ok(Upload { filename, mime_type })
+    Ok(Upload {
+        filename,
+        mime_type,
+        space_id,
+    })
+}
+
+fn quota_exceeded() -> AppError {
+    AppError::BadRequest("The storage quota has been exceeded.".to_string())
+}
+
+/// Files can be uploaded into the library of a space by its members who have the permission.
+async fn check_upload_permission(user_id: &Uuid, space_id: Option<&Uuid>) -> Result<(), AppError> {
+    if let Some(space_id) = space_id {
+        let mut conn = database::get().await?;
+        permissions::require(&mut *conn, user_id, space_id, Permission::UploadMedia).await?;
+    }
+    Ok(())
+}
+
+async fn upload_policies(space_id: Option<&Uuid>) -> Result<Policies, AppError> {
+    let mut conn = database::get().await?;
+    Policies::get(&mut *conn, space_id).await.map_err(Into::into)
+}
+
+/// How many bytes the user can still upload, `None` means unlimited.
+async fn remaining_quota(user_id: &Uuid, space_id: Option<&Uuid>) -> Result<Option<u64>, AppError> {
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let mut remaining = None;
+    if let Some(quota) = user_storage_quota() {
+        let used = Media::user_usage(db, user_id).await? as u64;
+        remaining = Some(quota.saturating_sub(used));
+    }
+    if let (Some(quota), Some(space_id)) = (space_storage_quota(), space_id) {
+        let used = Media::space_usage(db, space_id).await? as u64;
+        let space_remaining = quota.saturating_sub(used);
+        remaining = Some(remaining.map_or(space_remaining, |remaining: u64| remaining.min(space_remaining)));
+    }
+    Ok(remaining)
 }
 
-pub async fn upload(req: Request<Body>, params: Upload, max_size: usize) -> Result<MediaFile, AppError> {
-    let Upload { filename, mime_type } = params;
+/// Media uploaded without a space count towards the quota of the space they are posted in.
+pub async fn check_space_quota<T: Querist>(db: &mut T, media_id: &Uuid, space_id: &Uuid) -> Result<(), AppError> {
+    let quota = match space_storage_quota() {
+        Some(quota) => quota,
+        None => return Ok(()),
+    };
+    let media = Media::get_by_id(db, media_id).await.or_not_found()?;
+    if media.space_id.is_some() {
+        return Ok(());
+    }
+    let used = Media::space_usage(db, space_id).await? as u64;
+    if used + media.size as u64 > quota {
+        return Err(quota_exceeded());
+    }
+    Ok(())
+}
+
+/// `max_size` is a limit in addition to upload policies.
+pub async fn upload(
+    req: Request<Body>,
+    params: Upload,
+    max_size: Option<u64>,
+    user_id: &Uuid,
+) -> Result<MediaFile, AppError> {
+    let Upload {
+        filename,
+        mime_type,
+        space_id,
+    } = params;
+    check_upload_permission(user_id, space_id.as_ref()).await?;
+    let policies = upload_policies(space_id.as_ref()).await?;
+    policies.check_filename(&*filename)?;
+    let claimed_max_size = policies.max_size_of(mime_type.as_deref().unwrap_or(""));
+    let max_size = max_size.map_or(claimed_max_size, |max_size| max_size.min(claimed_max_size));
+    let remaining = remaining_quota(user_id, space_id.as_ref()).await?;
+    if remaining == Some(0) {
+        return Err(quota_exceeded());
+    }
     let id = utils::id();
-    let temp_filename = format!("{}_{}", id, filename);
+    // The name given by the user may be longer than the file system allows.
+    let temp_filename = format!("{}_upload", id);
 
     let path = Media::path(&*temp_filename);
     let mut file = File::create(&path).await?;
     let mut body = req.into_body();
     let mut hasher = blake3::Hasher::new();
     let mut size: usize = 0;
+    // Enough to sniff the format.
+    let mut head: Vec<u8> = Vec::with_capacity(32);
     while let Some(bytes) = body.next().await {
         let bytes = bytes?;
         size += bytes.len();
-        if size > max_size {
+        if size as u64 > max_size {
             tokio::fs::remove_file(&*path).await.ok();
             return Err(AppError::BadRequest(
                 "The maximum file size has been exceeded.".to_string(),
             ));
         }
+        if remaining.map_or(false, |remaining| size as u64 > remaining) {
+            tokio::fs::remove_file(&*path).await.ok();
+            return Err(quota_exceeded());
+        }
+        if head.len() < head.capacity() {
+            head.extend(bytes.iter().take(head.capacity() - head.len()));
+        }
         hasher.update(&bytes);
         file.write_all(&bytes).await?;
     }
+    file.flush().await?;
+    drop(file);
+    let hash = hasher.finalize().to_hex().to_string();
+    store(path, filename, mime_type, space_id, hash, size, &*head, &policies).await
+}
 
-    let hash = hasher.finalize();
-    let hash = hash.to_hex().to_string();
-    let ext = path.extension().map(|s| s.to_str()).flatten().unwrap_or("");
+/// Process a received temporary file, and move it into the storage.
+///
+/// `head` is the beginning of the file, to sniff the format.
+#[allow(clippy::too_many_arguments)]
+async fn store(
+    path: PathBuf,
+    filename: String,
+    mime_type: Option<String>,
+    space_id: Option<Uuid>,
+    mut hash: String,
+    mut size: usize,
+    head: &[u8],
+    policies: &Policies,
+) -> Result<MediaFile, AppError> {
+    let mut image = None;
+    if let Some(format) = images::sniff(head) {
+        let temp_path = path.clone();
+        let processed = tokio::task::spawn_blocking(move || images::process(&temp_path, format))
+            .await
+            .map_err(error_unexpected!())?
+            .map_err(AppError::Unexpected)?;
+        if let Some(processed) = processed {
+            if let Some(stripped) = processed.stripped.as_ref() {
+                hash = blake3::hash(stripped).to_hex().to_string();
+                size = stripped.len();
+            }
+            image = Some((format, processed));
+        }
+    }
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
 
     let new_filename = format!("{}.{}", hash, ext);
-    let new_path = Media::path(&*new_filename);
-    let duplicate = new_path.exists();
+    let mime_type = match (&image, mime_type) {
+        (Some((format, _)), _) => images::mime_type(*format).to_string(),
+        // Don't trust the client, e.g. a SVG may contain scripts.
+        (None, Some(mime_type)) if mime_type.starts_with("image/") => "application/octet-stream".to_string(),
+        (None, mime_type) => mime_type.unwrap_or_default(),
+    };
+    if let Err(e) = policies.check(&*mime_type, size as u64) {
+        tokio::fs::remove_file(path).await.ok();
+        return Err(e);
+    }
+    scan::check(&path).await?;
+    let duplicate = storage().exists(&*new_filename).await.map_err(AppError::Unexpected)?;
     if duplicate {
         tokio::fs::remove_file(path).await?;
-    } else {
-        tokio::fs::rename(path, new_path).await?;
+    } else if let Err(e) = storage().put_file(&*new_filename, &path, &*mime_type).await {
+        tokio::fs::remove_file(path).await.ok();
+        return Err(AppError::Unexpected(e));
     }
 
-    let mime_type = mime_type.unwrap_or_default();
+    if let Some((format, _)) = image {
+        let filename = new_filename.clone();
+        let hash = hash.clone();
+        tokio::spawn(async move {
+            if let Err(e) = images::generate_thumbnails(&*filename, format, &*hash).await {
+                log::warn!("Failed to generate thumbnails of {}: {}", hash, e);
+            }
+        });
+    }
     let media_file = MediaFile {
         mime_type,
         filename: new_filename,
@@ -80,6 +254,9 @@ pub async fn upload(req: Request<Body>, params: Upload, max_size: usize) -> Resu
         hash,
         size,
         duplicate,
+        width: image.as_ref().map(|(_, processed)| processed.width as i32),
+        height: image.as_ref().map(|(_, processed)| processed.height as i32),
+        space_id,
     };
     Ok(media_file)
 }
@@ -87,7 +264,7 @@ pub async fn upload(req: Request<Body>, params: Upload, max_size: usize) -> Resu
 async fn media_upload(req: Request<Body>) -> Result<Media, AppError> {
     let session = authenticate(&req).await?;
     let params = upload_params(req.uri())?;
-    let media_file = upload(req, params, 1024 * 1024 * 16).await?;
+    let media_file = upload(req, params, None, &session.user_id).await?;
     let mut conn = database::get().await?;
     media_file
         .create(&mut *conn, session.user_id, "")
@@ -95,24 +272,78 @@ async fn media_upload(req: Request<Body>) -> Result<Media, AppError> {
         .map_err(Into::into)
 }
 
-async fn send_file(path: PathBuf, mut sender: hyper::body::Sender) -> Result<(), anyhow::Error> {
-    use bytes::BytesMut;
-    use tokio::io::AsyncReadExt;
+/// A piece of the response body.
+enum Chunk {
+    Text(String),
+    File(ByteRange),
+}
 
-    let mut file = File::open(path).await?;
-    let mut buffer = BytesMut::with_capacity(1024);
-    while let Ok(read) = file.read_buf(&mut buffer).await {
-        if read == 0 {
-            break;
+async fn send_file(filename: String, mut sender: hyper::body::Sender, chunks: Vec<Chunk>) -> Result<(), anyhow::Error> {
+    for chunk in chunks {
+        match chunk {
+            Chunk::Text(text) => sender.send_data(text.into()).await?,
+            Chunk::File(range) => storage().send_range(&*filename, range, &mut sender).await?,
         }
-        sender.send_data(buffer.clone().freeze()).await?;
-        buffer.clear();
     }
     Ok(())
 }
 
+fn http_date(date: &NaiveDateTime) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn etag_matches(header: &HeaderValue, etag: &str) -> bool {
+    let header = header.to_str().unwrap_or("");
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn not_modified(headers: &HeaderMap, etag: &str, modified: &NaiveDateTime) -> bool {
+    use chrono::DateTime;
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        etag_matches(if_none_match, etag)
+    } else if let Some(since) = headers.get(header::IF_MODIFIED_SINCE) {
+        let since = since
+            .to_str()
+            .ok()
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok());
+        since.map_or(false, |since| modified.timestamp() <= since.timestamp())
+    } else {
+        false
+    }
+}
+
+/// The smallest thumbnail not smaller than the requested size.
+///
+/// Returns `None` if the original image is small enough.
+async fn thumbnail(media: &Media, format: ImageFormat, requested: u32) -> Option<(String, u32)> {
+    let size = *images::THUMBNAIL_SIZES.iter().find(|size| **size >= requested)?;
+    if let (Some(width), Some(height)) = (media.width, media.height) {
+        if width.max(height) as u32 <= size {
+            return None;
+        }
+    }
+    match images::thumbnail_file(&*media.filename, format, &*media.hash, size).await {
+        Ok(filename) => filename.map(|filename| (filename, size)),
+        Err(e) => {
+            // Fall back to the original image.
+            log::warn!("Failed to generate the thumbnail of {}: {}", media.id, e);
+            None
+        }
+    }
+}
+
 async fn get(req: Request<Body>) -> Result<Response, AppError> {
-    let MediaQuery { id, filename, download } = parse_query(req.uri())?;
+    use hyper::StatusCode;
+
+    let MediaQuery {
+        id,
+        filename,
+        download,
+        size: thumbnail_size,
+    } = parse_query(req.uri())?;
     let method = req.method().clone();
 
     let mut conn = database::get().await?;
@@ -123,44 +354,461 @@ async fn get(req: Request<Body>) -> Result<Response, AppError> {
     } else if let Some(filename) = filename {
         media = Some(Media::get_by_filename(db, &*filename).await.or_not_found()?);
     }
+    drop(conn);
     let media = media.ok_or_else(|| AppError::BadRequest("Filename or media id must be specified.".to_string()))?;
-    let path = Media::path(&*media.filename);
-    let size = std::fs::metadata(&path)
-        .map(|metadata| metadata.len())
-        .map_err(|_| AppError::NotFound("Failed to read file information."))?;
+    let mut filename = media.filename.clone();
+    // The content never changes, the hash is a strong validator.
+    let mut etag = format!("\"{}\"", media.hash);
+    let mut mime_type = media.mime_type.clone();
+    let format = images::format_from_mime_type(&*media.mime_type);
+    if let (Some(thumbnail_size), Some(format)) = (thumbnail_size, format) {
+        if let Some((thumbnail_filename, thumbnail_size)) = thumbnail(&media, format, thumbnail_size).await {
+            filename = thumbnail_filename;
+            etag = format!("\"{}-{}\"", media.hash, thumbnail_size);
+            mime_type = images::thumbnail_mime_type(format).to_string();
+        }
+    }
+    let attachment = download || !inline_allowed(&*mime_type);
+    let content_disposition = content_disposition(attachment, &*media.original_filename);
+
+    if media_redirect() {
+        let headers = ResponseHeaders {
+            content_type: &*mime_type,
+            content_disposition: content_disposition.to_str().unwrap_or(""),
+        };
+        if let Some(url) = storage().presigned_url(&*filename, REDIRECT_EXPIRES, headers) {
+            return hyper::Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, url)
+                .header(
+                    header::CACHE_CONTROL,
+                    format!("private, max-age={}", REDIRECT_EXPIRES.as_secs()),
+                )
+                .body(Body::empty())
+                .map_err(error_unexpected!());
+        }
+    }
+
+    let size = storage()
+        .size(&*filename)
+        .await
+        .map_err(AppError::Unexpected)?
+        .ok_or(AppError::NotFound("Failed to read file information."))?;
+
+    let last_modified = http_date(&media.created);
+    let mut response_builder = hyper::Response::builder()
+        .header(header::ETAG, &*etag)
+        .header(header::LAST_MODIFIED, &*last_modified)
+        .header(header::CACHE_CONTROL, HeaderValue::from_static("max-age=31536000")) // for year
+        .header(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    if not_modified(req.headers(), &*etag, &media.created) {
+        return response_builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .map_err(error_unexpected!());
+    }
+
+    let if_range = req
+        .headers()
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok());
+    let range_valid = if_range.map_or(true, |value| value == etag || value == last_modified);
+    let ranges = match req.headers().get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(range) if range_valid => range::parse(range, size),
+        _ => Ranges::Full,
+    };
+
+    response_builder = response_builder
+        .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"))
+        .header(header::CONTENT_DISPOSITION, content_disposition);
+    let mime_type_header = if mime_type.is_empty() {
+        None
+    } else {
+        Some(HeaderValue::from_str(&*mime_type).map_err(error_unexpected!())?)
+    };
+    let chunks = match ranges {
+        Ranges::Unsatisfiable => {
+            return response_builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty())
+                .map_err(error_unexpected!());
+        }
+        Ranges::Full => {
+            if let Some(mime_type) = mime_type_header {
+                response_builder = response_builder.header(header::CONTENT_TYPE, mime_type);
+            }
+            response_builder = response_builder.header(header::CONTENT_LENGTH, HeaderValue::from(size));
+            if size == 0 {
+                vec![]
+            } else {
+                vec![Chunk::File(ByteRange {
+                    start: 0,
+                    end: size - 1,
+                })]
+            }
+        }
+        Ranges::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            if let Some(mime_type) = mime_type_header {
+                response_builder = response_builder.header(header::CONTENT_TYPE, mime_type);
+            }
+            response_builder = response_builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, range.content_range(size))
+                .header(header::CONTENT_LENGTH, HeaderValue::from(range.len()));
+            vec![Chunk::File(range)]
+        }
+        Ranges::Partial(ranges) => {
+            let boundary = utils::id().to_simple().to_string();
+            let mut chunks = Vec::with_capacity(ranges.len() * 2 + 1);
+            let mut length: u64 = 0;
+            for range in ranges {
+                let part_header = range::part_header(&*boundary, &*mime_type, &range, size);
+                length += part_header.len() as u64 + range.len();
+                chunks.push(Chunk::Text(part_header));
+                chunks.push(Chunk::File(range));
+            }
+            let closing = range::closing_delimiter(&*boundary);
+            length += closing.len() as u64;
+            chunks.push(Chunk::Text(closing));
+            response_builder = response_builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={}", boundary),
+                )
+                .header(header::CONTENT_LENGTH, HeaderValue::from(length));
+            chunks
+        }
+    };
 
     let body = if method == hyper::Method::HEAD {
         Body::empty()
     } else {
         let (sender, body) = Body::channel();
         tokio::spawn(async move {
-            if let Err(e) = send_file(path, sender).await {
+            if let Err(e) = send_file(filename, sender, chunks).await {
                 log::error!("Failed to send file: {}", e);
             }
         });
         body
     };
-
-    let mut response_builder = hyper::Response::builder()
-        .header(header::ACCEPT_RANGES, HeaderValue::from_static("none"))
-        .header(header::CONTENT_LENGTH, HeaderValue::from(size))
-        .header(header::CACHE_CONTROL, HeaderValue::from_static("max-age=31536000")) // for year
-        .header(
-            header::CONTENT_DISPOSITION,
-            content_disposition(download, &*media.original_filename),
-        );
-    if !media.mime_type.is_empty() {
-        response_builder = response_builder.header(
-            header::CONTENT_TYPE,
-            HeaderValue::from_str(&*media.mime_type).map_err(error_unexpected!())?,
-        );
-    }
     let response = response_builder.body(body).map_err(error_unexpected!())?;
     Ok(response)
 }
 
-async fn delete(_req: Request<Body>) -> Result<(), AppError> {
-    todo!()
+async fn delete(req: Request<Body>) -> Result<Media, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let media = Media::get_by_id(db, &id).await.or_not_found()?;
+    if media.uploader_id != session.user_id && !Media::is_space_admin(db, &id, &session.user_id).await? {
+        return Err(AppError::NoPermission(
+            "Only the uploader or space admins can delete media".to_string(),
+        ));
+    }
+    let media = Media::delete(db, &id).await.or_not_found()?;
+    let shared = Media::count_by_filename(db, &*media.filename).await? > 0;
+    trans.commit().await?;
+    if !shared {
+        images::remove_files(&media).await;
+    }
+    log::info!("The media {} was deleted by {}", media.id, session.user_id);
+    Ok(media)
+}
+
+async fn received_size(path: &Path) -> Result<u64, AppError> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_upload_session(id: &Uuid, user_id: &Uuid) -> Result<UploadSession, AppError> {
+    match UploadSession::get(id).await? {
+        Some(upload_session) if upload_session.user_id == *user_id => Ok(upload_session),
+        _ => Err(AppError::NotFound("upload session")),
+    }
+}
+
+async fn create_resumable(req: Request<Body>) -> Result<UploadProgress, AppError> {
+    let session = authenticate(&req).await?;
+    let NewResumableUpload {
+        filename,
+        mime_type,
+        space_id,
+        size,
+    } = parse_query(req.uri())?;
+    let filename = filename_sanitizer(filename);
+    check_upload_permission(&session.user_id, space_id.as_ref()).await?;
+    if size > resumable::MAX_SIZE {
+        return Err(AppError::BadRequest(
+            "The maximum file size has been exceeded.".to_string(),
+        ));
+    }
+    // Checked again with the sniffed type once finished.
+    let policies = upload_policies(space_id.as_ref()).await?;
+    policies.check_filename(&*filename)?;
+    policies.check(mime_type.as_deref().unwrap_or(""), size)?;
+    let pending = UploadSession::get_by_user(&session.user_id).await?;
+    if pending.len() >= resumable::MAX_SESSIONS_PER_USER {
+        return Err(AppError::TooManyRequests(
+            "Too many uploads in progress, please finish or cancel some of them first".to_string(),
+        ));
+    }
+    // Unfinished uploads already hold their part of the quota.
+    let pending_size: u64 = pending.iter().map(|upload_session| upload_session.size).sum();
+    if remaining_quota(&session.user_id, space_id.as_ref())
+        .await?
+        .map_or(false, |remaining| size + pending_size > remaining)
+    {
+        return Err(quota_exceeded());
+    }
+    let upload_session = UploadSession {
+        id: utils::id(),
+        user_id: session.user_id,
+        filename,
+        mime_type,
+        space_id,
+        size,
+    };
+    upload_session.save().await?;
+    Ok(UploadProgress {
+        id: upload_session.id,
+        size,
+        offset: 0,
+    })
+}
+
+async fn resumable_progress(req: Request<Body>) -> Result<UploadProgress, AppError> {
+    let session = crate::session::authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let upload_session = get_upload_session(&id, &session.user_id).await?;
+    Ok(UploadProgress {
+        id,
+        size: upload_session.size,
+        offset: received_size(&upload_session.temp_path()).await?,
+    })
+}
+
+async fn upload_chunk(req: Request<Body>) -> Result<UploadProgress, AppError> {
+    use tokio::fs::OpenOptions;
+
+    let session = authenticate(&req).await?;
+    let ChunkQuery { id, offset } = parse_query(req.uri())?;
+    let upload_session = get_upload_session(&id, &session.user_id).await?;
+    let path = upload_session.temp_path();
+    let progress = Progress::get(&id);
+    // Chunks of an upload are written one by one.
+    let mut progress = progress.lock().await;
+    let mut received = received_size(&path).await?;
+    if offset != received {
+        return Err(AppError::BadRequest(format!(
+            "The chunk should start at offset {}.",
+            received
+        )));
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path).await?;
+    let mut body = req.into_body();
+    while let Some(bytes) = body.next().await {
+        let bytes = bytes?;
+        if received + bytes.len() as u64 > upload_session.size {
+            return Err(AppError::BadRequest(
+                "The chunk exceeds the size of the file.".to_string(),
+            ));
+        }
+        file.write_all(&bytes).await?;
+        progress.update(received, &bytes);
+        received += bytes.len() as u64;
+    }
+    file.flush().await?;
+    upload_session.save().await?;
+    Ok(UploadProgress {
+        id,
+        size: upload_session.size,
+        offset: received,
+    })
+}
+
+async fn finish_resumable(req: Request<Body>) -> Result<Media, AppError> {
+    use tokio::io::AsyncReadExt;
+
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let upload_session = get_upload_session(&id, &session.user_id).await?;
+    let path = upload_session.temp_path();
+    let progress = Progress::get(&id);
+    let progress = progress.lock().await;
+    let size = upload_session.size;
+    if received_size(&path).await? != size {
+        return Err(AppError::BadRequest("The upload is not complete.".to_string()));
+    }
+    if remaining_quota(&session.user_id, upload_session.space_id.as_ref())
+        .await?
+        .map_or(false, |remaining| size > remaining)
+    {
+        return Err(quota_exceeded());
+    }
+    let hash = match progress.hash(size) {
+        Some(hash) => hash,
+        None => {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || -> Result<String, std::io::Error> {
+                let mut hasher = blake3::Hasher::new();
+                std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+                Ok(hasher.finalize().to_hex().to_string())
+            })
+            .await
+            .map_err(error_unexpected!())??
+        }
+    };
+    let mut head = Vec::with_capacity(32);
+    File::open(&path).await?.take(32).read_to_end(&mut head).await?;
+
+    let UploadSession {
+        filename,
+        mime_type,
+        space_id,
+        ..
+    } = upload_session;
+    let policies = upload_policies(space_id.as_ref()).await?;
+    let media_file = store(
+        path,
+        filename,
+        mime_type,
+        space_id,
+        hash,
+        size as usize,
+        &*head,
+        &policies,
+    )
+    .await?;
+    let mut conn = database::get().await?;
+    let media = media_file.create(&mut *conn, session.user_id, "").await?;
+    // Kept until the media is created, so a failed upload can be finished again or cancelled.
+    UploadSession::remove(&id, &session.user_id).await?;
+    drop(progress);
+    Ok(media)
+}
+
+async fn cancel_resumable(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let upload_session = get_upload_session(&id, &session.user_id).await?;
+    let progress = Progress::get(&id);
+    let _progress = progress.lock().await;
+    UploadSession::remove(&id, &session.user_id).await?;
+    tokio::fs::remove_file(upload_session.temp_path()).await.ok();
+    Ok(true)
+}
+
+async fn gallery(req: Request<Body>) -> Result<Vec<Media>, AppError> {
+    let session = crate::session::authenticate(&req).await?;
+    let Gallery {
+        space_id,
+        channel_id,
+        uploader_id,
+        mime_type,
+        before,
+        limit,
+    } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    SpaceMember::get(db, &session.user_id, &space_id)
+        .await
+        .or_no_permission()?;
+    // "image" matches all images.
+    let mime_type = mime_type.map(|mime_type| {
+        if mime_type.contains('/') {
+            mime_type
+        } else {
+            format!("{}/%", mime_type)
+        }
+    });
+    let limit = limit.unwrap_or(64).min(256);
+    Media::gallery(
+        db,
+        &space_id,
+        &session.user_id,
+        channel_id.as_ref(),
+        uploader_id.as_ref(),
+        mime_type.as_deref(),
+        before.as_ref(),
+        limit,
+    )
+    .await
+    .map_err(Into::into)
+}
+
+async fn edit(req: Request<Body>) -> Result<Media, AppError> {
+    let session = authenticate(&req).await?;
+    let EditMedia { id, description } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let media = Media::get_by_id(db, &id).await.or_not_found()?;
+    if media.uploader_id != session.user_id {
+        let space_id = media
+            .space_id
+            .ok_or_else(|| AppError::NoPermission("Only the uploader can edit the media".to_string()))?;
+        SpaceMember::get(db, &session.user_id, &space_id)
+            .await
+            .or_no_permission()?;
+    }
+    Media::set_description(db, &id, description.trim())
+        .await?
+        .or_not_found()
+}
+
+async fn usage(req: Request<Body>) -> Result<StorageUsage, AppError> {
+    let session = crate::session::authenticate(&req).await?;
+    let mut conn = database::get().await?;
+    let used = Media::user_usage(&mut *conn, &session.user_id).await?;
+    Ok(StorageUsage {
+        used,
+        quota: user_storage_quota(),
+    })
+}
+
+async fn space_usage(req: Request<Body>) -> Result<StorageUsage, AppError> {
+    let session = crate::session::authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    SpaceMember::get(db, &session.user_id, &id).await.or_no_permission()?;
+    let used = Media::space_usage(db, &id).await?;
+    Ok(StorageUsage {
+        used,
+        quota: space_storage_quota(),
+    })
+}
+
+async fn policy(req: Request<Body>) -> Result<UploadPolicies, AppError> {
+    let PolicyQuery { space_id } = parse_query(req.uri())?;
+    let space = match space_id {
+        Some(space_id) => {
+            let mut conn = database::get().await?;
+            UploadPolicy::get_by_space(&mut *conn, &space_id).await?
+        }
+        None => None,
+    };
+    Ok(UploadPolicies {
+        deployment: upload_policy().clone(),
+        space,
+    })
+}
+
+async fn edit_space_policy(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let EditSpacePolicy { space_id, policy } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    permissions::require_admin(db, &session.user_id, &space_id).await?;
+    UploadPolicy::set_for_space(db, &space_id, policy.as_ref()).await?;
+    Ok(true)
 }
 
 pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError> {
@@ -170,6 +818,18 @@ pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError
         ("/get", Method::GET) => get(req).await,
         ("/get", Method::HEAD) => get(req).await,
         ("/upload", Method::POST) => media_upload(req).await.map(ok_response),
+        ("/delete", Method::POST) => delete(req).await.map(ok_response),
+        ("/resumable", Method::POST) => create_resumable(req).await.map(ok_response),
+        ("/resumable", Method::GET) => resumable_progress(req).await.map(ok_response),
+        ("/resumable_chunk", Method::PUT) => upload_chunk(req).await.map(ok_response),
+        ("/resumable_finish", Method::POST) => finish_resumable(req).await.map(ok_response),
+        ("/resumable_cancel", Method::POST) => cancel_resumable(req).await.map(ok_response),
+        ("/gallery", Method::GET) => gallery(req).await.map(ok_response),
+        ("/edit", Method::POST) => edit(req).await.map(ok_response),
+        ("/usage", Method::GET) => usage(req).await.map(ok_response),
+        ("/space_usage", Method::GET) => space_usage(req).await.map(ok_response),
+        ("/policy", Method::GET) => policy(req).await.map(ok_response),
+        ("/edit_space_policy", Method::POST) => edit_space_policy(req).await.map(ok_response),
         _ => missing(),
     }
 }
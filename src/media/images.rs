@@ -0,0 +1,379 @@
+//! Sniffing, metadata stripping and thumbnails of uploaded images.
+use super::models::Media;
+use super::storage::{self, storage};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The longest side of thumbnails.
+pub const THUMBNAIL_SIZES: &[u32] = &[320, 640, 1280];
+/// Images with more pixels are not decoded, to avoid decompression bombs.
+const MAX_PIXELS: u64 = 64 * 1024 * 1024;
+
+/// Only formats that we can handle are recognized.
+pub fn sniff(data: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(data).ok()? {
+        ImageFormat::Png => Some(ImageFormat::Png),
+        ImageFormat::Jpeg => Some(ImageFormat::Jpeg),
+        ImageFormat::Gif => Some(ImageFormat::Gif),
+        ImageFormat::WebP => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+pub fn mime_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::WebP => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn format_from_mime_type(mime_type: &str) -> Option<ImageFormat> {
+    match mime_type {
+        "image/png" => Some(ImageFormat::Png),
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+pub fn dimensions(data: &[u8], format: ImageFormat) -> Option<(u32, u32)> {
+    image::io::Reader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .ok()
+}
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = [*data.get(offset)?, *data.get(offset + 1)?];
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = [
+        *data.get(offset)?,
+        *data.get(offset + 1)?,
+        *data.get(offset + 2)?,
+        *data.get(offset + 3)?,
+    ];
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Read the orientation tag from the payload of an EXIF APP1 segment.
+fn exif_orientation(payload: &[u8]) -> Option<u16> {
+    let tiff = payload.strip_prefix(b"Exif\0\0")?;
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let ifd = read_u32(tiff, 4, big_endian)? as usize;
+    let count = read_u16(tiff, ifd, big_endian)? as usize;
+    (0..count)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|entry| read_u16(tiff, *entry, big_endian) == Some(0x0112))
+        .and_then(|entry| read_u16(tiff, entry + 8, big_endian))
+}
+
+/// Drop APP1 (EXIF, XMP), APP13 (IPTC) and comment segments, returns the orientation in EXIF.
+fn strip_jpeg(data: &[u8]) -> Option<(Vec<u8>, u16)> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    let mut orientation = 1;
+    let mut i = 2;
+    loop {
+        if *data.get(i)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(i + 1)?;
+        match marker {
+            // Fill bytes
+            0xFF => {
+                i += 1;
+                continue;
+            }
+            // Start of scan, the rest is the entropy-coded data.
+            0xDA | 0xD9 => {
+                output.extend_from_slice(&data[i..]);
+                return Some((output, orientation));
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                output.extend_from_slice(&data[i..i + 2]);
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let length = read_u16(data, i + 2, true)? as usize;
+        let segment = data.get(i..i + 2 + length).filter(|_| length >= 2)?;
+        match marker {
+            0xE1 => {
+                if let Some(value) = exif_orientation(&segment[4..]) {
+                    orientation = value;
+                }
+            }
+            0xED | 0xFE => {}
+            _ => output.extend_from_slice(segment),
+        }
+        i += 2 + length;
+    }
+}
+
+/// Drop the EXIF and textual chunks.
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !data.starts_with(SIGNATURE) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(SIGNATURE);
+    let mut i = SIGNATURE.len();
+    while i < data.len() {
+        let length = read_u32(data, i, true)? as usize;
+        // length, type, data and CRC
+        let chunk = data.get(i..i + 12 + length)?;
+        match &chunk[4..8] {
+            b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME" => {}
+            _ => output.extend_from_slice(chunk),
+        }
+        i += 12 + length;
+    }
+    Some(output)
+}
+
+/// Drop the EXIF and XMP chunks, and clear their flags.
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(0..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..12]);
+    let mut i = 12;
+    while i < data.len() {
+        let length = read_u32(data, i + 4, false)? as usize;
+        let padded = length + (length & 1);
+        let chunk = data.get(i..(i + 8 + padded).min(data.len()))?;
+        match &chunk[0..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let start = output.len();
+                output.extend_from_slice(chunk);
+                if let Some(flags) = output.get_mut(start + 8) {
+                    *flags &= !(0x08 | 0x04);
+                }
+            }
+            _ => output.extend_from_slice(chunk),
+        }
+        i += 8 + padded;
+    }
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(output)
+}
+
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, image::ImageError> {
+    let mut output = Vec::new();
+    if format == ImageFormat::Jpeg {
+        JpegEncoder::new_with_quality(&mut output, 90).encode_image(image)?;
+    } else {
+        image.write_to(&mut output, ImageOutputFormat::Png)?;
+    }
+    Ok(output)
+}
+
+fn too_large(data: &[u8], format: ImageFormat) -> bool {
+    dimensions(data, format).map_or(true, |(width, height)| width as u64 * height as u64 > MAX_PIXELS)
+}
+
+/// Remove EXIF (including GPS) and other metadata.
+///
+/// JPEG photos are rotated as their EXIF orientation says, since the tag is gone.
+/// Returns `None` if the data is unchanged.
+pub fn strip_metadata(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let stripped = match format {
+        ImageFormat::Jpeg => {
+            let (stripped, orientation) = strip_jpeg(data)?;
+            if orientation != 1 && !too_large(&*stripped, format) {
+                let image = image::load_from_memory_with_format(&*stripped, format).ok()?;
+                encode(&orient(image, orientation), format).ok()?
+            } else {
+                stripped
+            }
+        }
+        ImageFormat::Png => strip_png(data)?,
+        ImageFormat::WebP => strip_webp(data)?,
+        _ => return None,
+    };
+    if stripped.len() == data.len() && stripped == data {
+        None
+    } else {
+        Some(stripped)
+    }
+}
+
+/// JPEG thumbnails for JPEG images, PNG thumbnails for everything else.
+pub fn thumbnail_filename(hash: &str, format: ImageFormat, size: u32) -> String {
+    let ext = if format == ImageFormat::Jpeg { "jpg" } else { "png" };
+    format!("{}_{}.{}", hash, size, ext)
+}
+
+pub fn thumbnail_mime_type(format: ImageFormat) -> &'static str {
+    if format == ImageFormat::Jpeg {
+        mime_type(ImageFormat::Jpeg)
+    } else {
+        mime_type(ImageFormat::Png)
+    }
+}
+
+/// Returns `None` if the image is small enough already.
+pub fn thumbnail(data: &[u8], format: ImageFormat, size: u32) -> Result<Option<Vec<u8>>, image::ImageError> {
+    let fits = dimensions(data, format).map_or(true, |(width, height)| width.max(height) <= size);
+    if fits || too_large(data, format) {
+        return Ok(None);
+    }
+    let image = image::load_from_memory_with_format(data, format)?;
+    encode(&image.thumbnail(size, size), format).map(Some)
+}
+
+pub struct Processed {
+    pub width: u32,
+    pub height: u32,
+    /// The new content if any metadata was stripped.
+    pub stripped: Option<Vec<u8>>,
+}
+
+/// Strip the metadata of an uploaded image in place.
+///
+/// Returns `None` if the file is not a readable image after all.
+pub fn process(path: &Path, format: ImageFormat) -> Result<Option<Processed>, anyhow::Error> {
+    let data = std::fs::read(path)?;
+    let stripped = strip_metadata(&*data, format);
+    let (width, height) = match dimensions(stripped.as_deref().unwrap_or(&*data), format) {
+        Some(dimensions) => dimensions,
+        None => return Ok(None),
+    };
+    if let Some(stripped) = stripped.as_ref() {
+        std::fs::write(path, stripped)?;
+    }
+    Ok(Some(Processed {
+        width,
+        height,
+        stripped,
+    }))
+}
+
+/// Returns the filename of a thumbnail, and generates it if missing.
+///
+/// Returns `None` if the image is small enough already.
+pub async fn thumbnail_file(
+    filename: &str,
+    format: ImageFormat,
+    hash: &str,
+    size: u32,
+) -> Result<Option<String>, anyhow::Error> {
+    let thumbnail_filename = thumbnail_filename(hash, format, size);
+    if storage().exists(&*thumbnail_filename).await? {
+        return Ok(Some(thumbnail_filename));
+    }
+    let data = storage().read(filename).await?;
+    match tokio::task::spawn_blocking(move || thumbnail(&*data, format, size)).await?? {
+        Some(thumbnail) => {
+            storage::put(&*thumbnail_filename, thumbnail, thumbnail_mime_type(format)).await?;
+            Ok(Some(thumbnail_filename))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn generate_thumbnails(filename: &str, format: ImageFormat, hash: &str) -> Result<(), anyhow::Error> {
+    let data = Arc::new(storage().read(filename).await?);
+    for size in THUMBNAIL_SIZES.iter().copied() {
+        let thumbnail_filename = thumbnail_filename(hash, format, size);
+        if storage().exists(&*thumbnail_filename).await? {
+            continue;
+        }
+        let data = data.clone();
+        match tokio::task::spawn_blocking(move || thumbnail(&*data, format, size)).await?? {
+            Some(thumbnail) => storage::put(&*thumbnail_filename, thumbnail, thumbnail_mime_type(format)).await?,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Remove the file of a media and its thumbnails, nobody else should use the file.
+pub async fn remove_files(media: &Media) {
+    if let Err(e) = storage().remove(&*media.filename).await {
+        log::warn!("Failed to remove the file {}: {}", media.filename, e);
+    }
+    // Thumbnails are generated again on demand if another media has the same content.
+    if let Some(format) = format_from_mime_type(&*media.mime_type) {
+        for size in THUMBNAIL_SIZES {
+            storage()
+                .remove(&*thumbnail_filename(&*media.hash, format, *size))
+                .await
+                .ok();
+        }
+    }
+}
+
+#[test]
+fn images_test() {
+    use image::{ImageBuffer, Rgb};
+
+    let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(400, 200, Rgb([255u8, 0, 0])));
+    let jpeg = encode(&image, ImageFormat::Jpeg).unwrap();
+    assert_eq!(sniff(&*jpeg), Some(ImageFormat::Jpeg));
+    assert_eq!(sniff(b"<svg></svg>"), None);
+    assert_eq!(dimensions(&*jpeg, ImageFormat::Jpeg), Some((400, 200)));
+
+    // Insert an EXIF segment which says "rotate 90 degrees".
+    let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+    exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+    let mut with_exif = jpeg[..2].to_vec();
+    with_exif.extend_from_slice(&[0xFF, 0xE1]);
+    with_exif.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+    with_exif.extend_from_slice(&*exif);
+    with_exif.extend_from_slice(&jpeg[2..]);
+    let (_, orientation) = strip_jpeg(&*with_exif).unwrap();
+    assert_eq!(orientation, 6);
+    let stripped = strip_metadata(&*with_exif, ImageFormat::Jpeg).unwrap();
+    assert!(!stripped.windows(4).any(|window| window == b"Exif"));
+    assert_eq!(dimensions(&*stripped, ImageFormat::Jpeg), Some((200, 400)));
+
+    let png = encode(&image, ImageFormat::Png).unwrap();
+    assert!(strip_metadata(&*png, ImageFormat::Png).is_none());
+    let thumbnail = thumbnail(&*png, ImageFormat::Png, 320).unwrap().unwrap();
+    assert_eq!(dimensions(&*thumbnail, ImageFormat::Png), Some((320, 160)));
+    assert!(self::thumbnail(&*png, ImageFormat::Png, 640).unwrap().is_none());
+}
@@ -1,5 +1,6 @@
-use crate::error::DbError;
+use crate::error::{DbError, ModelError};
 use crate::utils::inner_result_map;
+use crate::validators::DESCRIPTION;
 use crate::{context::media_path, database::Querist};
 use chrono::naive::NaiveDateTime;
 use postgres_types::FromSql;
@@ -14,6 +15,9 @@ pub struct MediaFile {
     pub hash: String,
     pub size: usize,
     pub duplicate: bool,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub space_id: Option<Uuid>,
 }
 
 impl MediaFile {
@@ -27,6 +31,9 @@ impl MediaFile {
             self.hash,
             self.size as i32,
             source,
+            self.width,
+            self.height,
+            self.space_id.as_ref(),
         )
         .await
     }
@@ -47,9 +54,14 @@ pub struct Media {
     pub source: String,
     #[serde(with = "crate::date_format")]
     pub created: NaiveDateTime,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub space_id: Option<Uuid>,
+    pub channel_id: Option<Uuid>,
 }
 
 impl Media {
+    /// A path on the local disk, uploads are written here before being moved into the storage.
     pub fn path(filename: &str) -> PathBuf {
         let mut path = media_path().to_owned();
         path.push(filename);
@@ -68,6 +80,114 @@ impl Media {
         inner_result_map(result, |row| row.try_get(0))
     }
 
+    pub async fn get_by_uploader<T: Querist>(db: &mut T, uploader_id: &Uuid) -> Result<Vec<Media>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_by_uploader.sql"), &[uploader_id])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    /// References in messages are cleared, and avatars are cleared by the foreign key.
+    pub async fn delete<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<Media>, DbError> {
+        let result = db.query_one(include_str!("sql/delete.sql"), &[id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    /// Uploads are deduplicated by hash, so a file may be shared by several media.
+    pub async fn count_by_filename<T: Querist>(db: &mut T, filename: &str) -> Result<i64, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/count_by_filename.sql"), &[&filename])
+            .await?;
+        row.try_get(0)
+    }
+
+    /// Whether the user is an admin of a space where the media is used.
+    pub async fn is_space_admin<T: Querist>(db: &mut T, id: &Uuid, user_id: &Uuid) -> Result<bool, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/is_space_admin.sql"), &[id, user_id])
+            .await?;
+        row.try_get(0)
+    }
+
+    /// Record where the media was posted, if it's not in the library of another space.
+    pub async fn link<T: Querist>(db: &mut T, id: &Uuid, space_id: &Uuid, channel_id: &Uuid) -> Result<(), DbError> {
+        db.execute(include_str!("sql/link.sql"), &[id, space_id, channel_id])
+            .await?;
+        Ok(())
+    }
+
+    /// Media in the library of a space which the user can see, newest first.
+    ///
+    /// `mime_type` is a pattern of `LIKE`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn gallery<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        user_id: &Uuid,
+        channel_id: Option<&Uuid>,
+        uploader_id: Option<&Uuid>,
+        mime_type: Option<&str>,
+        before: Option<&NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<Media>, DbError> {
+        let rows = db
+            .query(
+                include_str!("sql/gallery.sql"),
+                &[
+                    space_id,
+                    user_id,
+                    &channel_id,
+                    &uploader_id,
+                    &mime_type,
+                    &before,
+                    &limit,
+                ],
+            )
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn set_description<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        description: &str,
+    ) -> Result<Option<Media>, ModelError> {
+        DESCRIPTION.run(description)?;
+        let result = db
+            .query_one(include_str!("sql/set_description.sql"), &[id, &description])
+            .await;
+        inner_result_map(result, |row| row.try_get(0)).map_err(Into::into)
+    }
+
+    /// Media which are not used by any message, avatar or space.
+    pub async fn delete_orphans<T: Querist>(
+        db: &mut T,
+        created_before: &NaiveDateTime,
+        limit: i64,
+    ) -> Result<Vec<Media>, DbError> {
+        let rows = db
+            .query(include_str!("sql/delete_orphans.sql"), &[created_before, &limit])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    /// Total size of uploads of the user, in bytes.
+    pub async fn user_usage<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<i64, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/user_usage.sql"), &[user_id])
+            .await?;
+        row.try_get(0)
+    }
+
+    /// Total size of media in the library of the space, in bytes.
+    pub async fn space_usage<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<i64, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/space_usage.sql"), &[space_id])
+            .await?;
+        row.try_get(0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn create<T: Querist>(
         db: &mut T,
         mime_type: &str,
@@ -77,6 +197,9 @@ impl Media {
         hash: String,
         size: i32,
         source: &str,
+        width: Option<i32>,
+        height: Option<i32>,
+        space_id: Option<&Uuid>,
     ) -> Result<Media, DbError> {
         let row = db
             .query_exactly_one(
@@ -89,9 +212,12 @@ impl Media {
                     &hash,
                     &size,
                     &source,
+                    &width,
+                    &height,
+                    &space_id,
                 ],
             )
             .await?;
-        Ok(row.try_get(0)?)
+        row.try_get(0)
     }
 }
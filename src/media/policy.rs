@@ -0,0 +1,170 @@
+//! What can be uploaded, set for the deployment by `UPLOAD_POLICY` and optionally restricted further by spaces.
+use crate::database::Querist;
+use crate::error::{AppError, DbError, ValidationFailed};
+use crate::utils::inner_result_map;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadPolicy {
+    /// Families like `image`, or types like `application/pdf`. Everything is allowed if empty.
+    #[serde(default)]
+    pub allowed_types: Vec<String>,
+    /// In bytes.
+    pub max_size: u64,
+    /// Limits of families or types which differ from `max_size`, e.g. `{"video": 536870912}`.
+    #[serde(default)]
+    pub max_sizes: HashMap<String, u64>,
+    pub max_filename_length: usize,
+}
+
+fn family(mime_type: &str) -> &str {
+    mime_type.split('/').next().unwrap_or("")
+}
+
+impl Default for UploadPolicy {
+    fn default() -> UploadPolicy {
+        UploadPolicy {
+            allowed_types: vec![],
+            max_size: 1024 * 1024 * 16,
+            max_sizes: HashMap::new(),
+            max_filename_length: 200,
+        }
+    }
+}
+
+impl UploadPolicy {
+    pub fn is_allowed(&self, mime_type: &str) -> bool {
+        self.allowed_types.is_empty()
+            || self
+                .allowed_types
+                .iter()
+                .any(|allowed| allowed == mime_type || allowed == family(mime_type))
+    }
+
+    pub fn max_size_of(&self, mime_type: &str) -> u64 {
+        self.max_sizes
+            .get(mime_type)
+            .or_else(|| self.max_sizes.get(family(mime_type)))
+            .copied()
+            .unwrap_or(self.max_size)
+    }
+
+    pub async fn get_by_space<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<Option<UploadPolicy>, DbError> {
+        let result = db
+            .query_one(include_str!("sql/get_space_policy.sql"), &[space_id])
+            .await;
+        let policy: Option<serde_json::Value> = inner_result_map(result, |row| row.try_get(0))?;
+        Ok(policy.and_then(|policy| match serde_json::from_value(policy) {
+            Ok(policy) => Some(policy),
+            Err(e) => {
+                log::warn!("The upload policy of space {} is malformed: {}", space_id, e);
+                None
+            }
+        }))
+    }
+
+    pub async fn set_for_space<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        policy: Option<&UploadPolicy>,
+    ) -> Result<(), DbError> {
+        match policy {
+            Some(policy) => {
+                let policy = serde_json::to_value(policy).expect("failed to serialize upload policy");
+                db.execute(include_str!("sql/set_space_policy.sql"), &[space_id, &policy])
+                    .await?;
+            }
+            None => {
+                db.execute(include_str!("sql/remove_space_policy.sql"), &[space_id])
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The policy of the deployment.
+pub fn upload_policy() -> &'static UploadPolicy {
+    static POLICY: OnceCell<UploadPolicy> = OnceCell::new();
+    POLICY.get_or_init(|| match std::env::var("UPLOAD_POLICY") {
+        Ok(policy) if !policy.trim().is_empty() => {
+            serde_json::from_str(&*policy).expect("environment variable `UPLOAD_POLICY` is invalid")
+        }
+        _ => UploadPolicy::default(),
+    })
+}
+
+/// Policies which apply to an upload, all of them must be satisfied.
+pub struct Policies(Vec<UploadPolicy>);
+
+impl Policies {
+    pub async fn get<T: Querist>(db: &mut T, space_id: Option<&Uuid>) -> Result<Policies, DbError> {
+        let mut policies = vec![upload_policy().clone()];
+        if let Some(space_id) = space_id {
+            policies.extend(UploadPolicy::get_by_space(db, space_id).await?);
+        }
+        Ok(Policies(policies))
+    }
+
+    pub fn max_size_of(&self, mime_type: &str) -> u64 {
+        self.0
+            .iter()
+            .map(|policy| policy.max_size_of(mime_type))
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn check_filename(&self, filename: &str) -> Result<(), AppError> {
+        if self
+            .0
+            .iter()
+            .any(|policy| filename.chars().count() > policy.max_filename_length)
+        {
+            return Err(ValidationFailed("File Name is too long").into());
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, mime_type: &str, size: u64) -> Result<(), AppError> {
+        if !self.0.iter().all(|policy| policy.is_allowed(mime_type)) {
+            return Err(AppError::BadRequest(format!(
+                "Files of type \"{}\" are not allowed.",
+                mime_type
+            )));
+        }
+        if size > self.max_size_of(mime_type) {
+            return Err(AppError::BadRequest(
+                "The maximum file size has been exceeded.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn policy_test() {
+    let deployment = UploadPolicy {
+        max_sizes: vec![("video".to_string(), 1024)].into_iter().collect(),
+        max_size: 16,
+        ..UploadPolicy::default()
+    };
+    let space = UploadPolicy {
+        allowed_types: vec!["image".to_string(), "video/mp4".to_string()],
+        max_size: 512,
+        ..UploadPolicy::default()
+    };
+    assert_eq!(deployment.max_size_of("video/webm"), 1024);
+    assert_eq!(deployment.max_size_of("text/plain"), 16);
+    let policies = Policies(vec![deployment, space]);
+    assert!(policies.check("image/png", 16).is_ok());
+    assert!(policies.check("image/png", 17).is_err());
+    assert!(policies.check("video/mp4", 512).is_ok());
+    assert!(policies.check("video/webm", 1).is_err());
+    assert!(policies.check("text/plain", 1).is_err());
+    assert!(policies.check_filename("madoka.png").is_ok());
+    assert!(policies.check_filename(&*"a".repeat(201)).is_err());
+}
@@ -0,0 +1,120 @@
+//! `Range` requests ([RFC 7233](https://tools.ietf.org/html/rfc7233)).
+
+/// Serve the whole file when a client asks for more ranges than this.
+pub const MAX_RANGES: usize = 16;
+
+/// An inclusive byte range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The header is malformed or not worth it, serve the whole file.
+    Full,
+    Partial(Vec<ByteRange>),
+    Unsatisfiable,
+}
+
+pub fn parse(header: &str, size: u64) -> Ranges {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return Ranges::Full,
+    };
+    let mut ranges = Vec::new();
+    let mut any_spec = false;
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        any_spec = true;
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => return Ranges::Full,
+        };
+        if start.is_empty() {
+            // The last N bytes.
+            let suffix: u64 = match end.parse() {
+                Ok(suffix) => suffix,
+                Err(_) => return Ranges::Full,
+            };
+            if suffix > 0 && size > 0 {
+                ranges.push(ByteRange {
+                    start: size.saturating_sub(suffix),
+                    end: size - 1,
+                });
+            }
+            continue;
+        }
+        let start: u64 = match start.parse() {
+            Ok(start) => start,
+            Err(_) => return Ranges::Full,
+        };
+        let end: Option<u64> = match end {
+            "" => None,
+            end => match end.parse() {
+                Ok(end) if end >= start => Some(end),
+                _ => return Ranges::Full,
+            },
+        };
+        if start < size {
+            let end = end.map_or(size - 1, |end| end.min(size - 1));
+            ranges.push(ByteRange { start, end });
+        }
+    }
+    if !any_spec || ranges.len() > MAX_RANGES {
+        Ranges::Full
+    } else if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Partial(ranges)
+    }
+}
+
+/// The header of a part in a `multipart/byteranges` body.
+pub fn part_header(boundary: &str, mime_type: &str, range: &ByteRange, size: u64) -> String {
+    let mut header = format!("\r\n--{}\r\n", boundary);
+    if !mime_type.is_empty() {
+        header.push_str(&*format!("Content-Type: {}\r\n", mime_type));
+    }
+    header.push_str(&*format!("Content-Range: {}\r\n\r\n", range.content_range(size)));
+    header
+}
+
+pub fn closing_delimiter(boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
+
+#[test]
+fn range_test() {
+    let range = |start, end| ByteRange { start, end };
+    assert_eq!(parse("bytes=0-499", 1000), Ranges::Partial(vec![range(0, 499)]));
+    assert_eq!(parse("bytes=500-", 1000), Ranges::Partial(vec![range(500, 999)]));
+    assert_eq!(parse("bytes=-200", 1000), Ranges::Partial(vec![range(800, 999)]));
+    assert_eq!(parse("bytes=-2000", 1000), Ranges::Partial(vec![range(0, 999)]));
+    assert_eq!(parse("bytes=900-1999", 1000), Ranges::Partial(vec![range(900, 999)]));
+    assert_eq!(
+        parse("bytes=0-0, -1", 1000),
+        Ranges::Partial(vec![range(0, 0), range(999, 999)])
+    );
+    assert_eq!(parse("bytes=1000-", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse("bytes=-0", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse("bytes=0-", 0), Ranges::Unsatisfiable);
+    assert_eq!(parse("bytes=500-100", 1000), Ranges::Full);
+    assert_eq!(parse("bytes=a-b", 1000), Ranges::Full);
+    assert_eq!(parse("bytes=", 1000), Ranges::Full);
+    assert_eq!(parse("items=0-1", 1000), Ranges::Full);
+    let many = format!("bytes={}", vec!["0-1"; MAX_RANGES + 1].join(","));
+    assert_eq!(parse(&*many, 1000), Ranges::Full);
+    assert_eq!(range(0, 499).len(), 500);
+    assert_eq!(range(0, 499).content_range(1000), "bytes 0-499/1000");
+}
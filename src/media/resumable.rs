@@ -0,0 +1,171 @@
+//! Resumable uploads, the file is sent in chunks and the upload can be continued after the connection is lost.
+//!
+//! Chunks are appended to a temporary file on the local disk, so all requests of an upload must reach the same
+//! server. Abandoned temporary files are removed by `media::tasks`.
+use super::models::Media;
+use crate::cache::{self, make_key};
+use crate::error::CacheError;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// A session expires if no chunk is received for this long.
+pub const EXPIRE_SECONDS: usize = 60 * 60 * 24;
+pub const MAX_SIZE: u64 = 1024 * 1024 * 512;
+/// Uploads a user can have in progress at once, each of them may take `MAX_SIZE` of the local disk.
+pub const MAX_SESSIONS_PER_USER: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub filename: String,
+    pub mime_type: Option<String>,
+    pub space_id: Option<Uuid>,
+    pub size: u64,
+}
+
+fn session_key(id: &Uuid) -> Vec<u8> {
+    make_key(b"upload", id, b"session")
+}
+
+fn user_sessions_key(user_id: &Uuid) -> Vec<u8> {
+    make_key(b"upload", user_id, b"sessions")
+}
+
+impl UploadSession {
+    pub fn temp_path(&self) -> PathBuf {
+        Media::path(&*format!("{}_upload", self.id))
+    }
+
+    /// Save the session, and postpone its expiration.
+    pub async fn save(&self) -> Result<(), CacheError> {
+        use cache::AsyncCommands;
+        let value = serde_json::to_vec(self).expect("failed to serialize upload session");
+        let mut cache = cache::conn().await;
+        cache
+            .set_with_expiration(&*session_key(&self.id), &*value, EXPIRE_SECONDS)
+            .await?;
+        let key = user_sessions_key(&self.user_id);
+        cache.inner.sadd(&*key, self.id.as_bytes().to_vec()).await?;
+        cache.inner.expire(&*key, EXPIRE_SECONDS).await
+    }
+
+    pub async fn get(id: &Uuid) -> Result<Option<UploadSession>, CacheError> {
+        let value = cache::conn().await.get(&*session_key(id)).await?;
+        Ok(value.and_then(|bytes| serde_json::from_slice(&*bytes).ok()))
+    }
+
+    /// Uploads of the user which are neither finished nor expired.
+    pub async fn get_by_user(user_id: &Uuid) -> Result<Vec<UploadSession>, CacheError> {
+        use cache::AsyncCommands;
+        let key = user_sessions_key(user_id);
+        let mut cache = cache::conn().await;
+        let ids: Vec<Vec<u8>> = cache.inner.smembers(&*key).await?;
+        let mut sessions = Vec::with_capacity(ids.len());
+        for id in ids {
+            match Uuid::from_slice(&*id) {
+                Ok(id) => match UploadSession::get(&id).await? {
+                    Some(upload_session) => sessions.push(upload_session),
+                    None => cache.inner.srem(&*key, id.as_bytes().to_vec()).await?,
+                },
+                Err(_) => cache.inner.srem(&*key, id).await?,
+            }
+        }
+        Ok(sessions)
+    }
+
+    pub async fn remove(id: &Uuid, user_id: &Uuid) -> Result<(), CacheError> {
+        use cache::AsyncCommands;
+        PROGRESS.get_or_init(Default::default).lock().unwrap().remove(id);
+        let mut cache = cache::conn().await;
+        cache.remove(&*session_key(id)).await?;
+        cache
+            .inner
+            .srem(&*user_sessions_key(user_id), id.as_bytes().to_vec())
+            .await
+    }
+}
+
+/// The state of an upload on this server, chunks of an upload are received one by one.
+pub struct Progress {
+    /// The hash of the first `hashed` bytes, `None` if the state was lost, e.g. the server restarted.
+    hasher: Option<blake3::Hasher>,
+    hashed: u64,
+    touched: Instant,
+}
+
+type ProgressMap = Mutex<HashMap<Uuid, Arc<tokio::sync::Mutex<Progress>>>>;
+
+static PROGRESS: OnceCell<ProgressMap> = OnceCell::new();
+
+impl Progress {
+    pub fn get(id: &Uuid) -> Arc<tokio::sync::Mutex<Progress>> {
+        let mut map = PROGRESS.get_or_init(Default::default).lock().unwrap();
+        map.entry(*id)
+            .or_insert_with(|| {
+                Arc::new(tokio::sync::Mutex::new(Progress {
+                    hasher: None,
+                    hashed: 0,
+                    touched: Instant::now(),
+                }))
+            })
+            .clone()
+    }
+
+    /// Data was appended to the file at `offset`.
+    pub fn update(&mut self, offset: u64, data: &[u8]) {
+        self.touched = Instant::now();
+        if offset == 0 {
+            self.hasher = Some(blake3::Hasher::new());
+            self.hashed = 0;
+        }
+        match self.hasher.as_mut() {
+            Some(hasher) if self.hashed == offset => {
+                hasher.update(data);
+                self.hashed += data.len() as u64;
+            }
+            _ => self.hasher = None,
+        }
+    }
+
+    /// Returns `None` if the hash has to be computed from the file again.
+    pub fn hash(&self, size: u64) -> Option<String> {
+        match self.hasher.as_ref() {
+            Some(hasher) if self.hashed == size => Some(hasher.finalize().to_hex().to_string()),
+            _ => None,
+        }
+    }
+
+    /// Forget uploads which were abandoned.
+    pub fn clean() {
+        let expire = Duration::from_secs(EXPIRE_SECONDS as u64);
+        let mut map = PROGRESS.get_or_init(Default::default).lock().unwrap();
+        map.retain(|_, progress| match progress.try_lock() {
+            Ok(progress) => progress.touched.elapsed() < expire,
+            Err(_) => true,
+        });
+    }
+}
+
+#[test]
+fn progress_test() {
+    let mut progress = Progress {
+        hasher: None,
+        hashed: 0,
+        touched: Instant::now(),
+    };
+    progress.update(0, b"madoka ");
+    progress.update(7, b"homura");
+    assert_eq!(
+        progress.hash(13).unwrap(),
+        blake3::hash(b"madoka homura").to_hex().to_string()
+    );
+    assert!(progress.hash(7).is_none());
+    progress.update(42, b"sayaka");
+    assert!(progress.hash(13).is_none());
+}
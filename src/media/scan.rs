@@ -0,0 +1,136 @@
+//! Check uploaded files before they are moved into the storage.
+use super::storage::BUFFER_SIZE;
+use crate::context::{media_path, scan_quarantine};
+use crate::error::AppError;
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+pub enum Verdict {
+    Clean,
+    /// The file is deleted.
+    Reject(String),
+    /// The file is rejected too, but kept under `MEDIA_PATH/quarantine` for inspection.
+    Quarantine(String),
+}
+
+#[async_trait]
+pub trait Scanner: Send + Sync + 'static {
+    async fn scan(&self, path: &Path) -> Result<Verdict, anyhow::Error>;
+}
+
+pub struct NoScan;
+
+#[async_trait]
+impl Scanner for NoScan {
+    async fn scan(&self, _path: &Path) -> Result<Verdict, anyhow::Error> {
+        Ok(Verdict::Clean)
+    }
+}
+
+/// Streams files to clamd over its local socket.
+pub struct ClamAv {
+    socket: PathBuf,
+    quarantine: bool,
+}
+
+impl ClamAv {
+    pub fn new(socket: &Path, quarantine: bool) -> ClamAv {
+        ClamAv {
+            socket: socket.to_owned(),
+            quarantine,
+        }
+    }
+}
+
+/// The reply of clamd is like `stream: OK` or `stream: Eicar-Signature FOUND`.
+fn parse_reply(reply: &str) -> Result<Option<&str>, anyhow::Error> {
+    let reply = reply.trim_end_matches(|c| c == '\0' || c == '\n');
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        Ok(None)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Some(signature))
+    } else {
+        Err(anyhow::anyhow!("unexpected reply from clamd: {}", reply))
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamAv {
+    async fn scan(&self, path: &Path) -> Result<Verdict, anyhow::Error> {
+        let mut file = File::open(path).await?;
+        let mut stream = UnixStream::connect(&self.socket).await?;
+        stream.write_all(b"zINSTREAM\0").await?;
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            stream.write_all(&(read as u32).to_be_bytes()).await?;
+            if read == 0 {
+                break;
+            }
+            stream.write_all(&buffer[..read]).await?;
+        }
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await?;
+        Ok(match parse_reply(&*reply)? {
+            None => Verdict::Clean,
+            Some(signature) if self.quarantine => Verdict::Quarantine(signature.to_string()),
+            Some(signature) => Verdict::Reject(signature.to_string()),
+        })
+    }
+}
+
+/// `ClamAv` if `CLAMAV_SOCKET` is set, files found are quarantined if `SCAN_QUARANTINE` is on.
+pub fn scanner() -> &'static dyn Scanner {
+    static SCANNER: OnceCell<Box<dyn Scanner>> = OnceCell::new();
+    let scanner = SCANNER.get_or_init(|| match std::env::var("CLAMAV_SOCKET") {
+        Ok(socket) if !socket.is_empty() => Box::new(ClamAv::new(Path::new(&*socket), scan_quarantine())),
+        _ => Box::new(NoScan),
+    });
+    scanner.as_ref()
+}
+
+pub fn quarantine_path(filename: &str) -> PathBuf {
+    media_path().join("quarantine").join(filename)
+}
+
+/// Scan a received temporary file, which is removed or quarantined if rejected.
+pub async fn check(path: &Path) -> Result<(), AppError> {
+    let rejected = || AppError::BadRequest("The file was rejected by the content scanner.".to_string());
+    match scanner().scan(path).await {
+        Ok(Verdict::Clean) => Ok(()),
+        Ok(Verdict::Reject(reason)) => {
+            log::warn!("An upload was rejected: {}", reason);
+            tokio::fs::remove_file(path).await.ok();
+            Err(rejected())
+        }
+        Ok(Verdict::Quarantine(reason)) => {
+            let filename = path.file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+            let quarantine = quarantine_path(&*filename);
+            log::warn!("An upload was quarantined as {}: {}", quarantine.display(), reason);
+            if let Some(parent) = quarantine.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(path, quarantine).await?;
+            Err(rejected())
+        }
+        Err(e) => {
+            tokio::fs::remove_file(path).await.ok();
+            Err(AppError::Unexpected(e))
+        }
+    }
+}
+
+#[test]
+fn reply_test() {
+    assert_eq!(parse_reply("stream: OK\0").unwrap(), None);
+    assert_eq!(
+        parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND\0").unwrap(),
+        Some("Win.Test.EICAR_HDB-1")
+    );
+    assert!(parse_reply("INSTREAM size limit exceeded. ERROR\0").is_err());
+}
@@ -0,0 +1,3 @@
+SELECT count(*)
+FROM media
+WHERE filename = $1;
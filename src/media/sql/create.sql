@@ -1,3 +1,3 @@
-INSERT INTO media (mime_type, uploader_id, filename, original_filename, hash, size, source)
-VALUES ($1, $2, $3, $4, $5, $6, $7)
+INSERT INTO media (mime_type, uploader_id, filename, original_filename, hash, size, source, width, height, space_id)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
 RETURNING media;
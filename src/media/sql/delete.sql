@@ -1,3 +1,6 @@
+WITH messages AS (
+    UPDATE messages SET media_id = NULL WHERE media_id = $1
+)
 DELETE
 FROM media
 WHERE id = $1
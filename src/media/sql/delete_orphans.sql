@@ -0,0 +1,11 @@
+DELETE
+FROM media
+WHERE id IN (SELECT m.id
+             FROM media m
+             WHERE m.created < $1
+               -- Media in the library of a space are kept.
+               AND m.space_id IS NULL
+               AND NOT EXISTS(SELECT 1 FROM messages msg WHERE msg.media_id = m.id)
+               AND NOT EXISTS(SELECT 1 FROM users u WHERE u.avatar_id = m.id)
+             LIMIT $2)
+RETURNING media;
@@ -0,0 +1,13 @@
+SELECT m
+FROM media m
+    LEFT JOIN channels ch ON m.channel_id = ch.id
+WHERE m.space_id = $1
+  AND ($3::uuid IS NULL OR m.channel_id = $3)
+  AND ($4::uuid IS NULL OR m.uploader_id = $4)
+  AND ($5::text IS NULL OR m.mime_type LIKE $5)
+  AND ($6::timestamp IS NULL OR m.created < $6) -- before
+  -- Media in private channels are only visible to members of the channel.
+  AND (ch.id IS NULL OR ch.is_public OR
+       EXISTS(SELECT 1 FROM channel_members cm WHERE cm.channel_id = ch.id AND cm.user_id = $2))
+ORDER BY m.created DESC
+LIMIT $7;
@@ -0,0 +1,4 @@
+SELECT media
+FROM media
+WHERE uploader_id = $1
+ORDER BY created;
@@ -0,0 +1 @@
+SELECT policy FROM space_upload_policies WHERE space_id = $1;
@@ -0,0 +1,9 @@
+SELECT EXISTS(SELECT 1
+              FROM space_members sm
+              WHERE sm.user_id = $2
+                AND sm.is_admin = true
+                AND (sm.space_id = (SELECT space_id FROM media WHERE id = $1)
+                  OR sm.space_id IN (SELECT ch.space_id
+                                     FROM messages msg
+                                         INNER JOIN channels ch ON msg.channel_id = ch.id
+                                     WHERE msg.media_id = $1)));
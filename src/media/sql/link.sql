@@ -0,0 +1,5 @@
+UPDATE media
+SET space_id   = $2,
+    channel_id = $3
+WHERE id = $1
+  AND (space_id IS NULL OR (space_id = $2 AND channel_id IS NULL));
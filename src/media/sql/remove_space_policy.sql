@@ -0,0 +1 @@
+DELETE FROM space_upload_policies WHERE space_id = $1;
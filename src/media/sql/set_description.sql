@@ -0,0 +1,4 @@
+UPDATE media
+SET description = $2
+WHERE id = $1
+RETURNING media;
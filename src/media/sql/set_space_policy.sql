@@ -0,0 +1,4 @@
+INSERT INTO space_upload_policies (space_id, policy)
+VALUES ($1, $2)
+ON CONFLICT (space_id) DO UPDATE SET policy   = excluded.policy,
+                                     modified = (now() at time zone 'utc');
@@ -0,0 +1,3 @@
+SELECT COALESCE(sum(size), 0)::bigint
+FROM media
+WHERE space_id = $1;
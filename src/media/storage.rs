@@ -0,0 +1,127 @@
+//! Where media files are kept.
+//!
+//! Uploads are written to a temporary file under `MEDIA_PATH` first, and moved into the storage once finished.
+use super::models::Media;
+use super::range::ByteRange;
+use super::s3::S3Storage;
+use crate::context::media_path;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use hyper::body::Sender;
+use once_cell::sync::OnceCell;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+pub const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Headers of the response when clients download a file from a presigned URL.
+pub struct ResponseHeaders<'a> {
+    pub content_type: &'a str,
+    pub content_disposition: &'a str,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync + 'static {
+    /// Move a local file into the storage.
+    async fn put_file(&self, filename: &str, local: &Path, mime_type: &str) -> Result<(), anyhow::Error>;
+    /// The size of a file, or `None` if it does not exist.
+    async fn size(&self, filename: &str) -> Result<Option<u64>, anyhow::Error>;
+    async fn remove(&self, filename: &str) -> Result<(), anyhow::Error>;
+    /// Send a range of a file to the response body.
+    async fn send_range(&self, filename: &str, range: ByteRange, sender: &mut Sender) -> Result<(), anyhow::Error>;
+    async fn read(&self, filename: &str) -> Result<Vec<u8>, anyhow::Error>;
+    /// A temporary URL which clients can download the file from directly, if supported.
+    fn presigned_url(&self, filename: &str, expires: Duration, headers: ResponseHeaders<'_>) -> Option<String>;
+
+    async fn exists(&self, filename: &str) -> Result<bool, anyhow::Error> {
+        self.size(filename).await.map(|size| size.is_some())
+    }
+}
+
+/// Files in a directory on the local disk.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &Path) -> LocalStorage {
+        LocalStorage { root: root.to_owned() }
+    }
+
+    fn path(&self, filename: &str) -> PathBuf {
+        self.root.join(filename)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put_file(&self, filename: &str, local: &Path, _mime_type: &str) -> Result<(), anyhow::Error> {
+        tokio::fs::rename(local, self.path(filename)).await?;
+        Ok(())
+    }
+
+    async fn size(&self, filename: &str) -> Result<Option<u64>, anyhow::Error> {
+        match tokio::fs::metadata(self.path(filename)).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn remove(&self, filename: &str) -> Result<(), anyhow::Error> {
+        match tokio::fs::remove_file(self.path(filename)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn send_range(&self, filename: &str, range: ByteRange, sender: &mut Sender) -> Result<(), anyhow::Error> {
+        let mut file = File::open(self.path(filename)).await?;
+        let mut buffer = BytesMut::with_capacity(BUFFER_SIZE);
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let mut remain = range.len();
+        while remain > 0 {
+            buffer.reserve(BUFFER_SIZE);
+            let read = (&mut file)
+                .take(remain.min(BUFFER_SIZE as u64))
+                .read_buf(&mut buffer)
+                .await?;
+            if read == 0 {
+                break;
+            }
+            remain -= read as u64;
+            sender.send_data(buffer.split().freeze()).await?;
+        }
+        Ok(())
+    }
+
+    async fn read(&self, filename: &str) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(tokio::fs::read(self.path(filename)).await?)
+    }
+
+    fn presigned_url(&self, _filename: &str, _expires: Duration, _headers: ResponseHeaders<'_>) -> Option<String> {
+        None
+    }
+}
+
+/// Selected by `MEDIA_STORAGE`, `local` (default) or `s3`.
+pub fn storage() -> &'static dyn Storage {
+    static STORAGE: OnceCell<Box<dyn Storage>> = OnceCell::new();
+    let storage = STORAGE.get_or_init(|| match std::env::var("MEDIA_STORAGE").as_deref() {
+        Ok("s3") if !cfg!(test) => Box::new(S3Storage::from_env()),
+        _ => Box::new(LocalStorage::new(media_path())),
+    });
+    storage.as_ref()
+}
+
+/// Write data to a temporary file, then move it into the storage.
+pub async fn put(filename: &str, data: Vec<u8>, mime_type: &str) -> Result<(), anyhow::Error> {
+    let temp_path = Media::path(&*format!("{}_{}", crate::utils::id(), filename));
+    tokio::fs::write(&temp_path, data).await?;
+    let result = storage().put_file(filename, &temp_path, mime_type).await;
+    tokio::fs::remove_file(&temp_path).await.ok();
+    result
+}
@@ -0,0 +1,66 @@
+use super::images;
+use super::models::Media;
+use super::resumable::Progress;
+use crate::context::media_path;
+use crate::database;
+use futures::StreamExt;
+use std::time::{Duration, SystemTime};
+use tokio::time::interval;
+use tokio_stream::wrappers::IntervalStream;
+
+/// Media may be uploaded a while before the message is sent.
+const GRACE_PERIOD_HOURS: i64 = 24;
+const BATCH_SIZE: i64 = 256;
+
+pub fn start() {
+    tokio::spawn(orphans_clean());
+}
+
+async fn orphans_clean() {
+    IntervalStream::new(interval(Duration::from_secs(60 * 60)))
+        .for_each(|_| async {
+            if let Err(e) = remove_orphans().await {
+                log::error!("Failed to remove orphaned media: {}", e);
+            }
+            if let Err(e) = remove_temp_files().await {
+                log::error!("Failed to remove temporary files: {}", e);
+            }
+            Progress::clean();
+        })
+        .await;
+}
+
+async fn remove_orphans() -> Result<(), anyhow::Error> {
+    let before = chrono::Utc::now().naive_utc() - chrono::Duration::hours(GRACE_PERIOD_HOURS);
+    let mut db = database::get().await?;
+    loop {
+        let orphans = Media::delete_orphans(&mut *db, &before, BATCH_SIZE).await?;
+        for media in orphans.iter() {
+            if Media::count_by_filename(&mut *db, &*media.filename).await? == 0 {
+                images::remove_files(media).await;
+            }
+        }
+        if !orphans.is_empty() {
+            log::info!("{} orphaned media were removed", orphans.len());
+        }
+        if orphans.len() < BATCH_SIZE as usize {
+            return Ok(());
+        }
+    }
+}
+
+/// Uploads which were interrupted before being moved into the storage.
+async fn remove_temp_files() -> Result<(), anyhow::Error> {
+    let temp_filename = regex!(r"^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}_");
+    let before = SystemTime::now() - Duration::from_secs(GRACE_PERIOD_HOURS as u64 * 60 * 60);
+    let mut entries = tokio::fs::read_dir(media_path()).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !temp_filename.is_match(&*entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        if entry.metadata().await?.modified()? < before {
+            tokio::fs::remove_file(entry.path()).await.ok();
+        }
+    }
+    Ok(())
+}
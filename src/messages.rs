@@ -1,5 +1,6 @@
 pub mod api;
 mod handlers;
+mod import;
 mod models;
 
 pub use handlers::router;
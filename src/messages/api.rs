@@ -1,5 +1,7 @@
-use serde::Deserialize;
+use super::import::ImportFormat;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[derive(Deserialize, Debug)]
@@ -51,3 +53,40 @@ pub struct ByChannel {
     pub before: Option<f64>,
     pub limit: Option<i32>,
 }
+
+/// Who a speaker in the log is.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Speaker {
+    /// A member of the channel, the importer if `None`.
+    pub user_id: Option<Uuid>,
+    /// The character name, the name in the log if `None`.
+    pub name: Option<String>,
+}
+
+fn default_in_game() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Import {
+    pub channel_id: Uuid,
+    pub format: ImportFormat,
+    /// The content of the log file.
+    pub content: String,
+    /// Keyed by names in the log. Unmapped speakers are matched with members by character name.
+    #[serde(default)]
+    pub speakers: HashMap<String, Speaker>,
+    /// For formats which do not record it.
+    #[serde(default = "default_in_game")]
+    pub in_game: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    pub imported: usize,
+    /// Imported before.
+    pub skipped: usize,
+}
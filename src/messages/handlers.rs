@@ -1,14 +1,19 @@
-use super::api::{Edit, NewMessage};
+use super::api::{Edit, Import, ImportResult, NewMessage};
+use super::import;
 use super::Message;
 use crate::channels::{Channel, ChannelMember};
 use crate::csrf::authenticate;
 use crate::error::{AppError, Find};
 use crate::events::Event;
 use crate::interface::{missing, ok_response, parse_query, Response};
+use crate::media::{self, Media};
 use crate::messages::api::{ByChannel, MoveBetween};
+use crate::spaces::permissions::{self, Permission};
 use crate::spaces::SpaceMember;
-use crate::{database, interface};
+use crate::users::UserBlock;
+use crate::{database, interface, notifications};
 use hyper::{Body, Request};
+use uuid::Uuid;
 
 async fn send(req: Request<Body>) -> Result<Message, AppError> {
     let session = authenticate(&req).await?;
@@ -29,6 +34,22 @@ async fn send(req: Request<Body>) -> Result<Message, AppError> {
     let (channel_member, space_member) = ChannelMember::get_with_space_member(db, &session.user_id, &channel_id)
         .await
         .or_no_permission()?;
+    Channel::get_by_id(db, &channel_id)
+        .await
+        .or_not_found()?
+        .check_writable()?;
+    if let Some(whisper_to_users) = whisper_to_users.as_ref() {
+        if UserBlock::is_blocked_by_any(db, &session.user_id, whisper_to_users).await? {
+            return Err(AppError::NoPermission(
+                "You have been blocked by the recipient".to_string(),
+            ));
+        }
+    }
+    // Whispered media stay out of the library of the space, where every member can see them.
+    let linked_media_id = media_id.filter(|_| whisper_to_users.is_none());
+    if let Some(media_id) = linked_media_id.as_ref() {
+        media::check_space_quota(db, media_id, &space_member.space_id).await?;
+    }
     let mut cache = crate::cache::conn().await;
     let message = Message::create(
         db,
@@ -48,6 +69,12 @@ async fn send(req: Request<Body>) -> Result<Message, AppError> {
         request_pos,
     )
     .await?;
+    if let Some(media_id) = linked_media_id.as_ref() {
+        Media::link(db, media_id, &space_member.space_id, &channel_id).await?;
+    }
+    if let Err(e) = notifications::notify(db, &mut cache, &message, space_member.space_id).await {
+        log::warn!("Failed to notify the mentioned users: {}", e);
+    }
     Event::new_message(space_member.space_id, message.clone());
     Ok(message)
 }
@@ -73,10 +100,15 @@ async fn edit(req: Request<Body>) -> Result<Message, AppError> {
     let (_, space_member) = ChannelMember::get_with_space_member(db, &session.user_id, &message.channel_id)
         .await
         .or_no_permission()?;
+    channel.check_writable()?;
     if !channel.is_document && message.sender_id != session.user_id {
-        return Err(AppError::NoPermission(format!("user id dismatch")));
+        return Err(AppError::NoPermission("user id dismatch".to_string()));
     }
+    let linked_media_id = media_id.filter(|_| message.whisper_to_users.is_none());
     if name.is_some() || text.is_some() || entities.is_some() || in_game.is_some() || is_action.is_some() {
+        if let Some(media_id) = linked_media_id.as_ref() {
+            media::check_space_quota(db, media_id, &space_member.space_id).await?;
+        }
         let text = text.as_deref();
         let name = name.as_deref();
         message = Message::edit(
@@ -92,6 +124,9 @@ async fn edit(req: Request<Body>) -> Result<Message, AppError> {
         )
         .await?
         .ok_or_else(|| unexpected!("The message had been delete."))?;
+        if let Some(media_id) = linked_media_id.as_ref() {
+            Media::link(db, media_id, &space_member.space_id, &channel.id).await?;
+        }
     }
     trans.commit().await?;
     Event::message_edited(space_member.space_id, message.clone());
@@ -113,15 +148,12 @@ async fn move_between(req: Request<Body>) -> Result<bool, AppError> {
         .await
         .or_not_found()?;
     let channel = Channel::get_by_id(db, &message.channel_id).await.or_not_found()?;
+    channel.check_writable()?;
     let channel_member = ChannelMember::get(db, &session.user_id, &message.channel_id)
         .await
         .or_no_permission()?;
-    if !channel.is_document {
-        if !channel_member.is_master && message.sender_id != session.user_id {
-            return Err(AppError::NoPermission(format!(
-                "Only the master can move other's messages."
-            )));
-        }
+    if !channel.is_document && !channel_member.is_master && message.sender_id != session.user_id {
+        permissions::require(db, &session.user_id, &channel.space_id, Permission::MoveMessages).await?;
     }
 
     let message = match range {
@@ -151,7 +183,14 @@ async fn query(req: Request<Body>) -> Result<Message, AppError> {
     let mut conn = database::get().await?;
     let db = &mut *conn;
     let user_id = authenticate(&req).await.ok().map(|session| session.user_id);
-    Message::get(db, &id, user_id.as_ref()).await.or_not_found()
+    let message = Message::get(db, &id, user_id.as_ref()).await.or_not_found()?;
+    if let (Some(user_id), Some(_)) = (user_id, message.whisper_to_users.as_ref()) {
+        let channel = Channel::get_by_id(db, &message.channel_id).await.or_not_found()?;
+        if permissions::has(db, &user_id, &channel.space_id, Permission::SeeWhispers).await? {
+            return Message::get_unhidden(db, &id).await.or_not_found();
+        }
+    }
+    Ok(message)
 }
 
 async fn delete(req: Request<Body>) -> Result<Message, AppError> {
@@ -163,9 +202,13 @@ async fn delete(req: Request<Body>) -> Result<Message, AppError> {
     let space_member = SpaceMember::get_by_channel(db, &session.user_id, &message.channel_id)
         .await
         .or_no_permission()?;
-    if !space_member.is_admin && message.sender_id != session.user_id {
-        return Err(AppError::NoPermission(format!("user id mismatch")));
+    if message.sender_id != session.user_id {
+        permissions::require(db, &session.user_id, &space_member.space_id, Permission::DeleteMessages).await?;
     }
+    Channel::get_by_id(db, &message.channel_id)
+        .await
+        .or_not_found()?
+        .check_writable()?;
     Message::delete(db, &id).await?;
     Event::message_deleted(space_member.space_id, message.channel_id, message.id);
     Ok(message)
@@ -178,13 +221,12 @@ async fn toggle_fold(req: Request<Body>) -> Result<Message, AppError> {
     let db = &mut *conn;
     let message = Message::get(db, &id, Some(&session.user_id)).await.or_not_found()?;
     let channel = Channel::get_by_id(db, &message.channel_id).await.or_not_found()?;
+    channel.check_writable()?;
     let channel_member = ChannelMember::get(db, &session.user_id, &message.channel_id)
         .await
         .or_no_permission()?;
-    if !channel.is_document {
-        if message.sender_id != session.user_id && !channel_member.is_master {
-            return Err(AppError::NoPermission(format!("user id dismatch")));
-        }
+    if !channel.is_document && message.sender_id != session.user_id && !channel_member.is_master {
+        return Err(AppError::NoPermission("user id dismatch".to_string()));
     }
     let folded = Some(!message.folded);
     let message = Message::edit(db, None, &message.id, None, None, None, None, folded, None)
@@ -194,6 +236,25 @@ async fn toggle_fold(req: Request<Body>) -> Result<Message, AppError> {
     Ok(message)
 }
 
+async fn toggle_pin(req: Request<Body>) -> Result<Message, AppError> {
+    let session = authenticate(&req).await?;
+    let interface::IdQuery { id } = interface::parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let message = Message::get(db, &id, Some(&session.user_id)).await.or_not_found()?;
+    let channel = Channel::get_by_id(db, &message.channel_id).await.or_not_found()?;
+    channel.check_writable()?;
+    let channel_member = ChannelMember::get(db, &session.user_id, &message.channel_id).await?;
+    if !channel_member.map_or(false, |member| member.is_master) {
+        permissions::require(db, &session.user_id, &channel.space_id, Permission::PinMessages).await?;
+    }
+    let message = Message::set_pinned(db, &message.id, !message.pinned)
+        .await?
+        .ok_or_else(|| unexpected!("message not found"))?;
+    Event::message_edited(channel.space_id, message.clone());
+    Ok(message)
+}
+
 async fn by_channel(req: Request<Body>) -> Result<Vec<Message>, AppError> {
     let ByChannel {
         channel_id,
@@ -217,6 +278,101 @@ async fn by_channel(req: Request<Body>) -> Result<Vec<Message>, AppError> {
         .map_err(Into::into)
 }
 
+/// Bring history in from the logs of other tools. Messages imported before are skipped.
+async fn import(req: Request<Body>) -> Result<ImportResult, AppError> {
+    let session = authenticate(&req).await?;
+    let Import {
+        channel_id,
+        format,
+        content,
+        speakers,
+        in_game,
+    } = interface::parse_body(req).await?;
+    let messages = import::parse(format, &*content, in_game).map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let space_member = SpaceMember::get_by_channel(db, &session.user_id, &channel_id)
+        .await
+        .or_no_permission()?;
+    permissions::require_admin(db, &session.user_id, &space_member.space_id).await?;
+    Channel::get_by_id(db, &channel_id)
+        .await
+        .or_not_found()?
+        .check_writable()?;
+    let members = ChannelMember::get_by_channel(db, &channel_id, false).await?;
+    let member_of = |user_id: &Uuid| members.iter().find(|member| member.member.user_id == *user_id);
+    let member_named = |name: &str| {
+        members.iter().find(|member| {
+            let character_name = &*member.member.character_name;
+            character_name == name || (character_name.is_empty() && member.user.nickname == name)
+        })
+    };
+    let importer_is_master = member_of(&session.user_id).map_or(false, |member| member.member.is_master);
+    let now = chrono::Utc::now().naive_utc();
+    let mut pos = Message::max_pos(db, &channel_id).await.ceil();
+    let mut result = ImportResult {
+        imported: 0,
+        skipped: 0,
+    };
+    for message in messages {
+        let speaker = speakers.get(&*message.speaker);
+        let member = match speaker {
+            Some(speaker) => match speaker.user_id.as_ref() {
+                Some(user_id) => Some(member_of(user_id).ok_or_else(|| {
+                    AppError::BadRequest(format!("The user {} is not a member of the channel.", user_id))
+                })?),
+                None => None,
+            },
+            None => message
+                .sender_id
+                .as_ref()
+                .and_then(member_of)
+                .or_else(|| member_named(&*message.speaker)),
+        };
+        let (sender_id, is_master) = member.map_or((session.user_id, importer_is_master), |member| {
+            (member.member.user_id, member.member.is_master)
+        });
+        let name = speaker
+            .and_then(|speaker| speaker.name.as_deref())
+            .unwrap_or(&*message.speaker);
+        // Whispers are kept for the master only.
+        let whisper_to = if message.whisper { Some(vec![]) } else { None };
+        pos += 1.0;
+        let inserted = Message::import(
+            db,
+            &import::message_id(&channel_id, &*message.key),
+            &sender_id,
+            &channel_id,
+            name,
+            &*message.text,
+            &message.entities,
+            message.in_game,
+            message.is_action,
+            is_master,
+            whisper_to,
+            message.created.as_ref().unwrap_or(&now),
+            pos,
+        )
+        .await?;
+        if inserted {
+            result.imported += 1;
+        } else {
+            result.skipped += 1;
+        }
+    }
+    trans.commit().await?;
+    let mut cache = crate::cache::conn().await;
+    crate::pos::reset_channel_pos(&mut cache, &channel_id).await?;
+    log::info!(
+        "{} messages were imported into the channel {} by {}",
+        result.imported,
+        channel_id,
+        session.user_id
+    );
+    Ok(result)
+}
+
 pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError> {
     use hyper::Method;
 
@@ -227,7 +383,9 @@ pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError
         ("/edit", Method::PATCH) => edit(req).await.map(ok_response),
         ("/move_between", Method::POST) => move_between(req).await.map(ok_response),
         ("/toggle_fold", Method::POST) => toggle_fold(req).await.map(ok_response),
+        ("/toggle_pin", Method::POST) => toggle_pin(req).await.map(ok_response),
         ("/delete", Method::POST) => delete(req).await.map(ok_response),
+        ("/import", Method::POST) => import(req).await.map(ok_response),
         _ => missing(),
     }
 }
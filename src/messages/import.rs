@@ -0,0 +1,228 @@
+//! Read chat logs of other tools, to import them into a channel.
+use super::Message;
+use chrono::{DateTime, NaiveDateTime};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// Exported by this server, the `json` format of channel exports or the messages in the response.
+    Boluo,
+    /// Exported by DiscordChatExporter.
+    Discord,
+    /// Lines like `Name: text`, optionally with a `[2020-01-01 12:00:00]` timestamp before.
+    Text,
+}
+
+/// A message read from a log.
+#[derive(Debug)]
+pub struct Imported {
+    /// Identifies the message in the log, stable across runs.
+    pub key: String,
+    pub speaker: String,
+    /// Set if the log comes from this server.
+    pub sender_id: Option<Uuid>,
+    pub text: String,
+    pub entities: JsonValue,
+    pub in_game: bool,
+    pub is_action: bool,
+    /// Only the master can read it.
+    pub whisper: bool,
+    pub created: Option<NaiveDateTime>,
+}
+
+/// The ID of an imported message, the same message is not imported twice.
+pub fn message_id(channel_id: &Uuid, key: &str) -> Uuid {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(b"import");
+    hasher.update(channel_id.as_bytes());
+    hasher.update(key.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hasher.finalize().as_bytes()[..16]);
+    uuid::Builder::from_bytes(bytes)
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Sha1)
+        .build()
+}
+
+fn parse_error() -> anyhow::Error {
+    anyhow::anyhow!("Failed to parse the log")
+}
+
+fn parse_boluo(content: &str) -> Result<Vec<Imported>, anyhow::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Log {
+        Messages(Vec<Message>),
+        Response { ok: Vec<Message> },
+    }
+    let messages = match serde_json::from_str(content).map_err(|_| parse_error())? {
+        Log::Messages(messages) => messages,
+        Log::Response { ok } => ok,
+    };
+    let imported = messages
+        .into_iter()
+        .map(|message| Imported {
+            key: message.id.to_string(),
+            speaker: message.name,
+            sender_id: Some(message.sender_id),
+            text: message.text,
+            entities: message.entities,
+            in_game: message.in_game,
+            is_action: message.is_action,
+            whisper: message.whisper_to_users.is_some(),
+            created: Some(message.created),
+        })
+        .collect();
+    Ok(imported)
+}
+
+fn parse_discord(content: &str, in_game: bool) -> Result<Vec<Imported>, anyhow::Error> {
+    #[derive(Deserialize)]
+    struct Author {
+        name: String,
+        nickname: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct DiscordMessage {
+        id: String,
+        #[serde(rename = "type")]
+        kind: String,
+        timestamp: String,
+        content: String,
+        author: Author,
+    }
+
+    #[derive(Deserialize)]
+    struct Log {
+        messages: Vec<DiscordMessage>,
+    }
+
+    let log: Log = serde_json::from_str(content).map_err(|_| parse_error())?;
+    let mut imported = Vec::with_capacity(log.messages.len());
+    for message in log.messages {
+        if message.kind != "Default" && message.kind != "Reply" {
+            continue;
+        }
+        let created = DateTime::parse_from_rfc3339(&*message.timestamp)
+            .map_err(|_| parse_error())?
+            .naive_utc();
+        imported.push(Imported {
+            key: format!("discord:{}", message.id),
+            speaker: message.author.nickname.unwrap_or(message.author.name),
+            sender_id: None,
+            text: message.content,
+            entities: JsonValue::Array(vec![]),
+            in_game,
+            is_action: false,
+            whisper: false,
+            created: Some(created),
+        });
+    }
+    Ok(imported)
+}
+
+fn parse_text(content: &str, in_game: bool) -> Vec<Imported> {
+    let line_pattern = regex!(r"^(?:\[(\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2})\] )?(\()?([^:]{1,32}): (.*)$");
+    let action_pattern = regex!(r"^(?:\[(\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2})\] )?\* (\S{1,32}) (.*)$");
+    let mut imported: Vec<Imported> = Vec::new();
+    // Identical lines are told apart by their order, so inserting lines keeps the keys of others.
+    let mut occurrences: HashMap<&str, usize> = HashMap::new();
+    for line in content.lines() {
+        let (captures, is_action) = match (line_pattern.captures(line), action_pattern.captures(line)) {
+            (_, Some(captures)) => (captures, true),
+            (Some(captures), None) => (captures, false),
+            (None, None) => {
+                // The message continues.
+                if let Some(last) = imported.last_mut() {
+                    last.text.push('\n');
+                    last.text.push_str(line);
+                }
+                continue;
+            }
+        };
+        let created = captures
+            .get(1)
+            .and_then(|time| NaiveDateTime::parse_from_str(time.as_str(), "%Y-%m-%d %H:%M:%S").ok());
+        let out_game = !is_action && captures.get(2).is_some();
+        let (speaker, mut text) = if is_action {
+            (&captures[2], captures[3].to_string())
+        } else {
+            (&captures[3], captures[4].to_string())
+        };
+        if out_game && text.ends_with(')') {
+            text.pop();
+        }
+        let occurrence = occurrences.entry(line).or_insert(0);
+        *occurrence += 1;
+        imported.push(Imported {
+            key: format!("text:{}:{}", blake3::hash(line.as_bytes()).to_hex(), occurrence),
+            speaker: speaker.trim().to_string(),
+            sender_id: None,
+            text,
+            entities: JsonValue::Array(vec![]),
+            in_game: in_game && !out_game,
+            is_action,
+            whisper: false,
+            created,
+        });
+    }
+    for message in imported.iter_mut() {
+        message.text = message.text.trim_end().to_string();
+    }
+    imported
+}
+
+/// `in_game` is used if the format does not record it.
+pub fn parse(format: ImportFormat, content: &str, in_game: bool) -> Result<Vec<Imported>, anyhow::Error> {
+    let mut imported = match format {
+        ImportFormat::Boluo => parse_boluo(content)?,
+        ImportFormat::Discord => parse_discord(content, in_game)?,
+        ImportFormat::Text => parse_text(content, in_game),
+    };
+    imported.retain(|message| !message.text.trim().is_empty() && !message.speaker.trim().is_empty());
+    Ok(imported)
+}
+
+#[test]
+fn parse_test() {
+    let text = "[2020-01-01 12:00:00] Madoka: Hello\nHomura: Hi\nsecond line\n(Sayaka: out of game)\n* Mami smiles";
+    let imported = parse(ImportFormat::Text, text, true).unwrap();
+    assert_eq!(imported.len(), 4);
+    assert_eq!(imported[0].speaker, "Madoka");
+    assert_eq!(
+        imported[0].created.unwrap(),
+        NaiveDateTime::parse_from_str("2020-01-01 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+    );
+    assert_eq!(imported[1].text, "Hi\nsecond line");
+    assert!(imported[1].created.is_none());
+    assert!(!imported[2].in_game);
+    assert_eq!(imported[2].text, "out of game");
+    assert!(imported[3].is_action);
+    assert_eq!(imported[3].speaker, "Mami");
+
+    let edited = parse(ImportFormat::Text, &*format!("Kyoko: Yo\n{}\nKyoko: Yo", text), true).unwrap();
+    assert_eq!(edited[1].key, imported[0].key);
+    assert_ne!(edited[0].key, edited[5].key);
+
+    let discord = r#"{"guild": {"id": "1"}, "messages": [
+        {"id": "42", "type": "Default", "timestamp": "2020-01-01T12:00:00.123+08:00", "content": "Hello",
+         "author": {"id": "7", "name": "kyoko", "nickname": "Kyoko"}},
+        {"id": "43", "type": "ChannelPinnedMessage", "timestamp": "2020-01-01T12:00:01+00:00", "content": "",
+         "author": {"id": "7", "name": "kyoko", "nickname": null}}
+    ]}"#;
+    let imported = parse(ImportFormat::Discord, discord, false).unwrap();
+    assert_eq!(imported.len(), 1);
+    assert_eq!(imported[0].speaker, "Kyoko");
+    assert_eq!(imported[0].key, "discord:42");
+    assert_eq!(imported[0].created.unwrap().to_string(), "2020-01-01 04:00:00.123");
+
+    let channel_id = crate::utils::id();
+    assert_eq!(message_id(&channel_id, "a"), message_id(&channel_id, "a"));
+    assert_ne!(message_id(&channel_id, "a"), message_id(&channel_id, "b"));
+}
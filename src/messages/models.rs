@@ -4,10 +4,11 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use uuid::Uuid;
 
-use crate::database::Querist;
+use crate::database::{Client, Querist};
 use crate::error::{AppError, DbError, ModelError, ValidationFailed};
-use crate::utils::merge_blank;
+use crate::utils::{inner_result_map, merge_blank};
 use crate::validators::CHARACTER_NAME;
+use futures::{Stream, StreamExt};
 use tokio_postgres::error::SqlState;
 
 pub fn check_pos(pos: f64) -> Result<(), ValidationFailed> {
@@ -68,6 +69,13 @@ impl Message {
         }
     }
 
+    /// Like `get`, but whispers are never hidden.
+    pub async fn get_unhidden<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<Message>, DbError> {
+        let user_id: Option<&Uuid> = None;
+        let result = db.query_one(include_str!("sql/get.sql"), &[id, &user_id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
     pub async fn query_by_pos<T: Querist>(db: &mut T, channel_id: &Uuid, pos: f64) -> Result<Option<Message>, DbError> {
         let row = db
             .query_one(include_str!("sql/by_pos.sql"), &[channel_id, &pos])
@@ -83,7 +91,7 @@ impl Message {
         limit: i32,
     ) -> Result<Vec<Message>, ModelError> {
         use postgres_types::Type;
-        if limit > 256 || limit < 1 {
+        if !(1..=256).contains(&limit) {
             return Err(ValidationFailed("illegal limit range").into());
         }
         let rows = db
@@ -106,9 +114,14 @@ impl Message {
         channel_id: &Uuid,
         hide: bool,
         after: Option<NaiveDateTime>,
+        in_game_only: bool,
+        hide_folded: bool,
     ) -> Result<Vec<Message>, DbError> {
         let rows = db
-            .query(include_str!("./sql/export.sql"), &[channel_id, &after])
+            .query(
+                include_str!("./sql/export.sql"),
+                &[channel_id, &after, &in_game_only, &hide_folded],
+            )
             .await?;
         let mut messages: Vec<Message> = vec![];
         for row in rows {
@@ -120,6 +133,24 @@ impl Message {
         Ok(messages)
     }
 
+    /// Like `export`, but messages are received one by one, and not hidden.
+    pub async fn export_stream(
+        db: &mut Client,
+        channel_id: &Uuid,
+        after: Option<NaiveDateTime>,
+        in_game_only: bool,
+        hide_folded: bool,
+    ) -> Result<impl Stream<Item = Result<Message, DbError>>, DbError> {
+        let rows = db
+            .query_stream(
+                include_str!("./sql/export.sql"),
+                &[channel_id, &after, &in_game_only, &hide_folded],
+            )
+            .await?;
+        Ok(rows.map(|row| row?.try_get(0)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn create<T: Querist>(
         db: &mut T,
         cache: &mut crate::cache::Connection,
@@ -336,12 +367,55 @@ impl Message {
             Ok(None)
         }
     }
+    /// Insert a message from a log, returns `false` if it was imported before.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn import<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        sender_id: &Uuid,
+        channel_id: &Uuid,
+        name: &str,
+        text: &str,
+        entities: &JsonValue,
+        in_game: bool,
+        is_action: bool,
+        is_master: bool,
+        whisper_to: Option<Vec<Uuid>>,
+        created: &NaiveDateTime,
+        pos: f64,
+    ) -> Result<bool, ModelError> {
+        let name = merge_blank(name);
+        CHARACTER_NAME.run(&name)?;
+        check_pos(pos)?;
+        let inserted = db
+            .execute(
+                include_str!("sql/import.sql"),
+                &[
+                    id,
+                    sender_id,
+                    channel_id,
+                    &name,
+                    &text,
+                    entities,
+                    &in_game,
+                    &is_action,
+                    &is_master,
+                    &whisper_to,
+                    created,
+                    &pos,
+                ],
+            )
+            .await?;
+        Ok(inserted > 0)
+    }
+
     pub async fn max_pos<T: Querist>(db: &mut T, channel_id: &Uuid) -> f64 {
         db.query_exactly_one(include_str!("./sql/max_pos.sql"), &[channel_id])
             .await
             .and_then(|row| row.try_get(0))
             .unwrap()
     }
+    #[allow(clippy::too_many_arguments)]
     pub async fn edit<T: Querist>(
         db: &mut T,
         name: Option<&str>,
@@ -373,9 +447,31 @@ impl Message {
         }
     }
 
+    pub async fn set_pinned<T: Querist>(db: &mut T, id: &Uuid, pinned: bool) -> Result<Option<Message>, DbError> {
+        let result = db.query_one(include_str!("sql/set_pinned.sql"), &[id, &pinned]).await;
+        let mut message: Option<Message> = inner_result_map(result, |row| row.try_get(0))?;
+        message.iter_mut().for_each(Message::hide);
+        Ok(message)
+    }
+
     pub async fn delete<T: Querist>(db: &mut T, id: &Uuid) -> Result<u64, DbError> {
         db.execute(include_str!("sql/delete.sql"), &[id]).await
     }
+
+    pub async fn get_by_sender<T: Querist>(db: &mut T, sender_id: &Uuid) -> Result<Vec<Message>, DbError> {
+        let rows = db.query(include_str!("sql/get_by_sender.sql"), &[sender_id]).await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    /// Replace the names on all messages of the sender.
+    pub async fn anonymize_by_sender<T: Querist>(db: &mut T, sender_id: &Uuid, name: &str) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/anonymize_by_sender.sql"), &[sender_id, &name])
+            .await
+    }
+
+    pub async fn delete_by_sender<T: Querist>(db: &mut T, sender_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/delete_by_sender.sql"), &[sender_id]).await
+    }
 }
 
 #[tokio::test]
@@ -453,6 +549,11 @@ async fn message_test() -> Result<(), crate::error::AppError> {
     ChannelMember::set_master(db, &user.id, &channel.id, false).await?;
     let a = Message::get(db, &message.id, Some(&user.id)).await?.unwrap();
     assert_eq!(a.text, "");
+    let unhidden = Message::get_unhidden(db, &message.id).await?.unwrap();
+    assert_eq!(unhidden.text, new_text);
+    let pinned = Message::set_pinned(db, &message.id, true).await?.unwrap();
+    assert!(pinned.pinned);
+    assert_eq!(pinned.text, "");
     let messages = Message::get_by_channel(db, &channel.id, None, 128).await?;
     assert_eq!(messages.len(), 1);
     assert_eq!(messages[0].id, a.id);
@@ -0,0 +1,3 @@
+UPDATE messages
+SET name = $2
+WHERE sender_id = $1;
@@ -0,0 +1,4 @@
+UPDATE messages
+SET deleted = true
+WHERE sender_id = $1
+  AND deleted = false;
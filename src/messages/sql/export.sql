@@ -3,5 +3,6 @@ FROM messages msg
 WHERE msg.channel_id = $1
   AND msg.deleted = false
   AND msg.order_date > coalesce($2, to_timestamp(0)::timestamp)
+  AND (msg.in_game OR NOT $3)
+  AND (NOT msg.folded OR NOT $4)
 ORDER BY msg.pos;
-
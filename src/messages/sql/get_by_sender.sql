@@ -0,0 +1,5 @@
+SELECT msg
+FROM messages msg
+WHERE msg.sender_id = $1
+  AND msg.deleted = false
+ORDER BY msg.created;
@@ -0,0 +1,18 @@
+INSERT INTO messages (
+    id,
+    sender_id,
+    channel_id,
+    name,
+    text,
+    entities,
+    in_game,
+    is_action,
+    is_master,
+    whisper_to_users,
+    created,
+    modified,
+    order_date,
+    pos
+)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11, $11, $12)
+ON CONFLICT (id) DO NOTHING;
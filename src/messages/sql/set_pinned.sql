@@ -0,0 +1,4 @@
+UPDATE messages
+SET pinned = $2
+WHERE id = $1
+RETURNING messages;
@@ -0,0 +1,7 @@
+pub mod api;
+mod handlers;
+mod mentions;
+mod models;
+
+pub use handlers::{notify, router};
+pub use models::Notification;
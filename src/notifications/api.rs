@@ -0,0 +1,30 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::Notification;
+use crate::messages::Message;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationWithMessage {
+    pub notification: Notification,
+    pub message: Message,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Inbox {
+    #[serde(default)]
+    #[serde(with = "crate::date_format::option")]
+    pub before: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub unread_only: bool,
+}
+
+/// Mark all notifications as read if `ids` is absent.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkRead {
+    pub ids: Option<Vec<Uuid>>,
+}
@@ -0,0 +1,97 @@
+use super::api::{Inbox, MarkRead, NotificationWithMessage};
+use super::mentions::{recipients, Mention, MentionGroup};
+use super::Notification;
+use crate::channels::ChannelMember;
+use crate::csrf::authenticate;
+use crate::database::{self, Querist};
+use crate::error::AppError;
+use crate::events::Event;
+use crate::interface::{self, missing, ok_response, parse_query, Response};
+use crate::messages::Message;
+use crate::spaces::models::{space_users_status, StatusKind};
+use hyper::{Body, Request};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Notify the members mentioned by a new message.
+pub async fn notify<T: Querist>(
+    db: &mut T,
+    cache: &mut crate::cache::Connection,
+    message: &Message,
+    space_id: Uuid,
+) -> Result<(), AppError> {
+    let entities = message.entities.as_array().map(Vec::as_slice).unwrap_or(&[]);
+    let mentions = Mention::parse(entities);
+    if mentions.is_empty() {
+        return Ok(());
+    }
+    let members: Vec<ChannelMember> = ChannelMember::get_by_channel(db, &message.channel_id, false)
+        .await?
+        .into_iter()
+        .map(|member| member.member)
+        .collect();
+    let here = Mention::Group {
+        group: MentionGroup::Here,
+    };
+    let online: HashSet<Uuid> = if mentions.contains(&here) {
+        space_users_status(cache, space_id)
+            .await?
+            .into_iter()
+            .filter(|(_, status)| status.kind == StatusKind::Online)
+            .map(|(user_id, _)| user_id)
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    let whisper_to_users = message.whisper_to_users.as_deref();
+    let recipients = recipients(&mentions, &message.sender_id, &members, &online, whisper_to_users);
+    if recipients.is_empty() {
+        return Ok(());
+    }
+    // Recipients who have blocked the sender are dropped by the insert, whichever way they were mentioned.
+    for notification in Notification::create(db, &recipients, message, &space_id).await? {
+        Event::notification(NotificationWithMessage {
+            notification,
+            message: message.clone(),
+        });
+    }
+    Ok(())
+}
+
+async fn inbox(req: Request<Body>) -> Result<Vec<NotificationWithMessage>, AppError> {
+    let session = authenticate(&req).await?;
+    let Inbox { before, unread_only } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    Notification::get_by_user(db, &session.user_id, before, unread_only, 64)
+        .await
+        .map_err(Into::into)
+}
+
+async fn unread_count(req: Request<Body>) -> Result<i64, AppError> {
+    let session = authenticate(&req).await?;
+    let mut conn = database::get().await?;
+    Notification::count_unread(&mut *conn, &session.user_id)
+        .await
+        .map_err(Into::into)
+}
+
+async fn read(req: Request<Body>) -> Result<u64, AppError> {
+    let session = authenticate(&req).await?;
+    let MarkRead { ids } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    Notification::mark_read(&mut *conn, &session.user_id, ids.as_deref())
+        .await
+        .map_err(Into::into)
+}
+
+pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError> {
+    use hyper::Method;
+
+    match (path, req.method().clone()) {
+        ("/inbox", Method::GET) => inbox(req).await.map(ok_response),
+        ("/unread_count", Method::GET) => unread_count(req).await.map(ok_response),
+        ("/read", Method::POST) => read(req).await.map(ok_response),
+        _ => missing(),
+    }
+}
@@ -0,0 +1,126 @@
+//! Mention entities of messages.
+//!
+//! A mention is an entity of the type `Mention`, which refers to a user, a character name or a group:
+//!
+//! ```json
+//! {"type": "Mention", "start": 0, "len": 5, "userId": "..."}
+//! {"type": "Mention", "start": 0, "len": 7, "characterName": "Cocona"}
+//! {"type": "Mention", "start": 0, "len": 8, "group": "MASTERS"}
+//! ```
+use crate::channels::ChannelMember;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MentionGroup {
+    /// `@masters`, the masters of the channel.
+    Masters,
+    /// `@here`, the members of the channel who are online.
+    Here,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Mention {
+    #[serde(rename_all = "camelCase")]
+    User {
+        user_id: Uuid,
+    },
+    #[serde(rename_all = "camelCase")]
+    Character {
+        character_name: String,
+    },
+    Group {
+        group: MentionGroup,
+    },
+}
+
+impl Mention {
+    pub fn parse(entities: &[JsonValue]) -> Vec<Mention> {
+        entities
+            .iter()
+            .filter(|entity| entity.get("type").and_then(JsonValue::as_str) == Some("Mention"))
+            .filter_map(|entity| serde_json::from_value(entity.clone()).ok())
+            .collect()
+    }
+
+    fn includes(&self, member: &ChannelMember, online: &HashSet<Uuid>) -> bool {
+        match self {
+            Mention::User { user_id } => member.user_id == *user_id,
+            Mention::Character { character_name } => {
+                !member.character_name.is_empty() && member.character_name.eq_ignore_ascii_case(character_name.trim())
+            }
+            Mention::Group {
+                group: MentionGroup::Masters,
+            } => member.is_master,
+            Mention::Group {
+                group: MentionGroup::Here,
+            } => online.contains(&member.user_id),
+        }
+    }
+}
+
+/// The members of the channel to notify, except the sender and those who can't see the whisper.
+pub fn recipients(
+    mentions: &[Mention],
+    sender_id: &Uuid,
+    members: &[ChannelMember],
+    online: &HashSet<Uuid>,
+    whisper_to_users: Option<&[Uuid]>,
+) -> Vec<Uuid> {
+    members
+        .iter()
+        .filter(|member| member.user_id != *sender_id)
+        .filter(|member| match whisper_to_users {
+            None => true,
+            Some(users) => member.is_master || users.contains(&member.user_id),
+        })
+        .filter(|member| mentions.iter().any(|mention| mention.includes(member, online)))
+        .map(|member| member.user_id)
+        .collect()
+}
+
+#[test]
+fn mentions_test() {
+    use serde_json::json;
+
+    let gm = Uuid::new_v4();
+    let alice = Uuid::new_v4();
+    let bob = Uuid::new_v4();
+    let member = |user_id: Uuid, character_name: &str, is_master: bool| ChannelMember {
+        user_id,
+        channel_id: Uuid::nil(),
+        join_date: chrono::Utc::now().naive_utc(),
+        character_name: character_name.to_string(),
+        is_master,
+        text_color: None,
+        is_joined: true,
+    };
+    let members = vec![
+        member(gm, "", true),
+        member(alice, "Alice", false),
+        member(bob, "Bob", false),
+    ];
+    let online: HashSet<Uuid> = vec![gm, bob].into_iter().collect();
+
+    let entities = vec![
+        json!({"type": "Text", "start": 0, "len": 5}),
+        json!({"type": "Mention", "start": 5, "len": 6, "characterName": "alice"}),
+        json!({"type": "Mention", "start": 11, "len": 3, "group": "NOBODY"}),
+    ];
+    let mentions = Mention::parse(&entities);
+    assert_eq!(mentions.len(), 1);
+    assert_eq!(recipients(&mentions, &gm, &members, &online, None), vec![alice]);
+    assert!(recipients(&mentions, &gm, &members, &online, Some(&[bob])).is_empty());
+
+    let mentions = Mention::parse(&[json!({"type": "Mention", "group": "HERE"})]);
+    assert_eq!(recipients(&mentions, &alice, &members, &online, None), vec![gm, bob]);
+    let mentions = Mention::parse(&[json!({"type": "Mention", "group": "MASTERS"})]);
+    assert_eq!(recipients(&mentions, &gm, &members, &online, None), Vec::<Uuid>::new());
+    assert_eq!(recipients(&mentions, &bob, &members, &online, Some(&[])), vec![gm]);
+    let mentions = Mention::parse(&[json!({"type": "Mention", "userId": bob})]);
+    assert_eq!(recipients(&mentions, &alice, &members, &online, None), vec![bob]);
+}
@@ -0,0 +1,180 @@
+use chrono::NaiveDateTime;
+use postgres_types::FromSql;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::api::NotificationWithMessage;
+use crate::database::Querist;
+use crate::error::DbError;
+use crate::messages::Message;
+
+#[derive(Debug, Serialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "notifications")]
+pub struct Notification {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub message_id: Uuid,
+    pub channel_id: Uuid,
+    pub space_id: Uuid,
+    pub sender_id: Uuid,
+    pub read: bool,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl Notification {
+    /// Users who have blocked the sender or have been notified of the message are skipped.
+    pub async fn create<T: Querist>(
+        db: &mut T,
+        recipients: &[Uuid],
+        message: &Message,
+        space_id: &Uuid,
+    ) -> Result<Vec<Notification>, DbError> {
+        let rows = db
+            .query(
+                include_str!("sql/create.sql"),
+                &[
+                    &recipients,
+                    &message.id,
+                    &message.channel_id,
+                    space_id,
+                    &message.sender_id,
+                ],
+            )
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    /// The newest notifications created before `before`.
+    pub async fn get_by_user<T: Querist>(
+        db: &mut T,
+        user_id: &Uuid,
+        before: Option<NaiveDateTime>,
+        unread_only: bool,
+        limit: i64,
+    ) -> Result<Vec<NotificationWithMessage>, DbError> {
+        let rows = db
+            .query(
+                include_str!("sql/get_by_user.sql"),
+                &[user_id, &before, &unread_only, &limit],
+            )
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(NotificationWithMessage {
+                    notification: row.try_get(0)?,
+                    message: row.try_get(1)?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn count_unread<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<i64, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/count_unread.sql"), &[user_id])
+            .await?;
+        row.try_get(0)
+    }
+
+    /// Mark the notifications of `ids` as read, or all of them if `ids` is `None`.
+    pub async fn mark_read<T: Querist>(db: &mut T, user_id: &Uuid, ids: Option<&[Uuid]>) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/mark_read.sql"), &[user_id, &ids]).await
+    }
+}
+
+#[tokio::test]
+async fn notifications_test() -> Result<(), crate::error::AppError> {
+    use crate::channels::{Channel, ChannelMember};
+    use crate::database::Client;
+    use crate::spaces::{Space, SpaceMember};
+    use crate::users::{User, UserBlock};
+
+    let mut client = Client::new().await?;
+    let mut trans = client.transaction().await?;
+    let db = &mut trans;
+    let password = "no password";
+    let gm = User::register(db, "notification_test@mythal.net", "notification_test", "GM", password).await?;
+    let player = User::register(
+        db,
+        "notification_test_2@mythal.net",
+        "notification_test_2",
+        "Player",
+        password,
+    )
+    .await?;
+    let space = Space::create(db, "Notification Space".to_string(), &gm.id, String::new(), None, None).await?;
+    SpaceMember::add_user(db, &player.id, &space.id).await?;
+    let channel = Channel::create(db, &space.id, "Notification Channel", true, None).await?;
+    ChannelMember::add_user(db, &gm.id, &channel.id, "", true).await?;
+    ChannelMember::add_user(db, &player.id, &channel.id, "Cocona", false).await?;
+
+    let message_id = Uuid::new_v4();
+    let created = chrono::Utc::now().naive_utc();
+    let entities = serde_json::json!([]);
+    Message::import(
+        db,
+        &message_id,
+        &gm.id,
+        &channel.id,
+        "GM",
+        "@Cocona",
+        &entities,
+        true,
+        false,
+        true,
+        None,
+        &created,
+        1.0,
+    )
+    .await?;
+    let message = Message::get(db, &message_id, Some(&gm.id)).await?.unwrap();
+
+    let notifications = Notification::create(db, &[player.id], &message, &space.id).await?;
+    assert_eq!(notifications.len(), 1);
+    assert!(Notification::create(db, &[player.id], &message, &space.id)
+        .await?
+        .is_empty());
+    assert_eq!(Notification::count_unread(db, &player.id).await?, 1);
+    let joined = Channel::get_by_user(db, player.id).await?;
+    assert_eq!(joined[0].unread.as_ref().unwrap().mentions, 1);
+
+    let inbox = Notification::get_by_user(db, &player.id, None, true, 16).await?;
+    assert_eq!(inbox[0].message.id, message.id);
+    assert_eq!(
+        Notification::mark_read(db, &player.id, Some(&[inbox[0].notification.id])).await?,
+        1
+    );
+    assert!(Notification::get_by_user(db, &player.id, None, true, 16)
+        .await?
+        .is_empty());
+    assert_eq!(
+        Notification::get_by_user(db, &player.id, None, false, 16).await?.len(),
+        1
+    );
+    assert_eq!(Notification::mark_read(db, &player.id, None).await?, 0);
+
+    UserBlock::block(db, &player.id, &gm.id).await?;
+    let message_id = Uuid::new_v4();
+    Message::import(
+        db,
+        &message_id,
+        &gm.id,
+        &channel.id,
+        "GM",
+        "@Cocona",
+        &entities,
+        true,
+        false,
+        true,
+        None,
+        &created,
+        2.0,
+    )
+    .await?;
+    let message = Message::get(db, &message_id, Some(&gm.id)).await?.unwrap();
+    assert!(Notification::create(db, &[player.id], &message, &space.id)
+        .await?
+        .is_empty());
+    Ok(())
+}
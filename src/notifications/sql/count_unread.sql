@@ -0,0 +1,4 @@
+SELECT count(*)
+FROM notifications n
+    INNER JOIN messages m ON n.message_id = m.id AND m.deleted = false
+WHERE n.user_id = $1 AND n.read = false;
@@ -0,0 +1,6 @@
+INSERT INTO notifications (user_id, message_id, channel_id, space_id, sender_id)
+SELECT recipient, $2, $3, $4, $5
+FROM unnest($1::uuid[]) AS recipient
+WHERE NOT EXISTS(SELECT 1 FROM user_blocks b WHERE b.user_id = recipient AND b.blocked_id = $5)
+ON CONFLICT (message_id, user_id) DO NOTHING
+RETURNING notifications;
@@ -0,0 +1,8 @@
+SELECT n, m
+FROM notifications n
+    INNER JOIN messages m ON n.message_id = m.id AND m.deleted = false
+WHERE n.user_id = $1
+  AND ($2::timestamp IS NULL OR n.created < $2)
+  AND (NOT $3 OR n.read = false)
+ORDER BY n.created DESC
+LIMIT $4;
@@ -0,0 +1,5 @@
+UPDATE notifications
+SET read = true
+WHERE user_id = $1
+  AND read = false
+  AND ($2::uuid[] IS NULL OR id = ANY ($2));
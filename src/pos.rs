@@ -45,7 +45,7 @@ pub async fn pos<T: Querist>(
 }
 
 pub async fn reset_channel_pos(cache: &mut crate::cache::Connection, channel_id: &Uuid) -> Result<(), CacheError> {
-    cache.inner.del(create_max_pos_key(&channel_id)).await
+    cache.inner.del(create_max_pos_key(channel_id)).await
 }
 
 pub async fn finished(cache: &mut crate::cache::Connection, channel_id: Uuid, message_id: Uuid) -> Result<i32, CacheError> {
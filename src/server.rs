@@ -21,16 +21,19 @@ mod csrf;
 mod database;
 mod date_format;
 mod events;
+mod http_client;
 mod interface;
 mod logger;
 mod media;
 mod messages;
+mod notifications;
 mod pool;
 mod pos;
 mod session;
 mod spaces;
 mod users;
 mod validators;
+mod webhooks;
 mod websocket;
 
 use crate::cors::allow_origin;
@@ -43,8 +46,8 @@ async fn router(req: Request<Body>) -> Result<Response, AppError> {
     macro_rules! table {
         ($prefix: expr, $handler: expr) => {
             let prefix = $prefix;
-            if path.starts_with(prefix) {
-                return $handler(req, &path[prefix.len()..]).await;
+            if let Some(rest) = path.strip_prefix(prefix) {
+                return $handler(req, rest).await;
             }
         };
     }
@@ -57,6 +60,8 @@ async fn router(req: Request<Body>) -> Result<Response, AppError> {
     table!("/api/channels", channels::router);
     table!("/api/spaces", spaces::router);
     table!("/api/events", events::router);
+    table!("/api/notifications", notifications::router);
+    table!("/api/webhooks", webhooks::router);
     missing()
 }
 
@@ -121,6 +126,7 @@ async fn main() {
 
     let server = Server::bind(&addr).serve(make_svc);
     events::tasks::start();
+    media::tasks::start();
     // Run this server for... forever!
     if let Err(e) = server.await {
         log::error!("server error: {}", e);
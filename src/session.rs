@@ -27,9 +27,14 @@ pub fn token_verify(token: &str) -> Result<Uuid, anyhow::Error> {
     Uuid::from_slice(session.as_slice()).context("Failed to convert session bytes data to UUID.")
 }
 
-pub async fn revoke_session(id: &Uuid) -> Result<(), CacheError> {
-    let key = make_key(id);
-    cache::conn().await.remove(&*key).await
+pub async fn revoke_session(session: &Session) -> Result<(), CacheError> {
+    use cache::AsyncCommands;
+    let mut cache = cache::conn().await;
+    cache.remove(&*make_key(&session.id)).await?;
+    cache
+        .inner
+        .srem(user_sessions_key(&session.user_id), session.id.as_bytes().to_vec())
+        .await
 }
 
 #[test]
@@ -38,25 +43,66 @@ fn test_session_sign() {
     assert!(token_verify("").is_err());
     let session_2 = token_verify(&*token(&session)).unwrap();
     assert_eq!(session, session_2);
+    let session = Session {
+        id: session,
+        user_id: utils::id(),
+    };
+    assert!(session.age().unwrap() < std::time::Duration::from_secs(1));
 }
 
 fn make_key(session: &Uuid) -> Vec<u8> {
     cache::make_key(b"sessions", session, b"user_id")
 }
 
+fn user_sessions_key(user_id: &Uuid) -> Vec<u8> {
+    cache::make_key(b"users", user_id, b"sessions")
+}
+
 pub async fn start(user_id: &Uuid) -> Result<Uuid, CacheError> {
+    use cache::AsyncCommands;
     let session = utils::id();
     let key = make_key(&session);
-    cache::conn().await.set(&key, user_id.as_bytes()).await?;
+    let mut cache = cache::conn().await;
+    cache.set(&key, user_id.as_bytes()).await?;
+    // Keep track of sessions of the user, so that all of them can be revoked.
+    cache
+        .inner
+        .sadd(user_sessions_key(user_id), session.as_bytes().to_vec())
+        .await?;
     Ok(session)
 }
 
+/// Revoke all sessions of the user.
+///
+/// Sessions started before the sessions of users were tracked are not in the set and can't
+/// be revoked here, they have to be removed from the cache by hand.
+pub async fn revoke_user_sessions(user_id: &Uuid) -> Result<(), CacheError> {
+    use cache::AsyncCommands;
+    let key = user_sessions_key(user_id);
+    let mut cache = cache::conn().await;
+    let sessions: Vec<Vec<u8>> = cache.inner.smembers(&*key).await?;
+    for session in sessions {
+        if let Ok(session) = Uuid::from_slice(&*session) {
+            cache.remove(&*make_key(&session)).await?;
+        }
+    }
+    cache.remove(&*key).await
+}
+
 #[derive(Debug)]
 pub struct Session {
     pub id: Uuid,
     pub user_id: Uuid,
 }
 
+impl Session {
+    /// How long ago the session was started, taken from the time-based ID.
+    pub fn age(&self) -> Option<std::time::Duration> {
+        let (seconds, nanos) = self.id.to_timestamp()?.to_unix();
+        utils::now_unix_duration().checked_sub(std::time::Duration::new(seconds, nanos))
+    }
+}
+
 pub async fn remove_session(id: Uuid) -> Result<(), CacheError> {
     let key = make_key(&id);
     cache::conn().await.remove(&*key).await?;
@@ -85,19 +131,19 @@ pub async fn authenticate(req: &hyper::Request<hyper::Body>) -> Result<Session,
     } else {
         let cookie = headers
             .get(COOKIE)
-            .ok_or(Unauthenticated(format!("There is no cookie in header")))?;
+            .ok_or_else(|| Unauthenticated("There is no cookie in header".to_string()))?;
         let token = parse_cookie(cookie);
 
         token.map_err(|err| {
             log::warn!("Failed to parse cookie: {}", err);
-            Unauthenticated(format!("Invalid cookie"))
+            Unauthenticated("Invalid cookie".to_string())
         })?
     };
 
     let id = match token_verify(token) {
         Err(err) => {
             log::warn!("{}", err);
-            return Err(AppError::Unauthenticated(format!("Invalid session")));
+            return Err(AppError::Unauthenticated("Invalid session".to_string()));
         }
         Ok(id) => id,
     };
@@ -110,7 +156,7 @@ pub async fn authenticate(req: &hyper::Request<hyper::Body>) -> Result<Session,
         .map_err(error_unexpected!())?
         .ok_or_else(|| {
             log::warn!("Session {} not found, token: {}", id, token);
-            Unauthenticated(format!("Session not found"))
+            Unauthenticated("Session not found".to_string())
         })?;
 
     let user_id = Uuid::from_slice(&*bytes).map_err(error_unexpected!())?;
@@ -1,6 +1,7 @@
 pub mod api;
 pub mod handlers;
 pub mod models;
+pub mod permissions;
 
 pub use handlers::router;
-pub use models::{RestrainedMember, Space, SpaceMember};
+pub use models::{Space, SpaceInvite, SpaceMember, SpaceRole, SpaceTransfer};
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
 
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use super::models::UserStatus;
+use super::permissions::Permission;
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -19,9 +21,75 @@ pub struct Create {
 #[serde(rename_all = "camelCase")]
 pub struct Join {
     pub space_id: Uuid,
+    /// The invite token of the space, or the ID of an invite.
     pub token: Option<Uuid>,
 }
 
+/// The body of joining, it's optional.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinPassword {
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateInvite {
+    pub space_id: Uuid,
+    #[serde(default)]
+    pub name: String,
+    /// Players who join with the invite join the channel too.
+    pub channel_id: Option<Uuid>,
+    #[serde(default)]
+    pub spectator: bool,
+    pub max_uses: Option<i32>,
+    #[serde(default, with = "crate::date_format::option")]
+    pub expires: Option<NaiveDateTime>,
+}
+
+/// What users see before joining with an invite.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitePreview {
+    pub invite: super::SpaceInvite,
+    pub space: super::Space,
+    pub member_count: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    pub space_id: Uuid,
+    /// Must be an admin of the space.
+    pub user_id: Uuid,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRole {
+    pub space_id: Uuid,
+    pub name: String,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EditRole {
+    pub role_id: Uuid,
+    pub name: Option<String>,
+    pub permissions: Option<Vec<Permission>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AssignRole {
+    pub role_id: Uuid,
+    pub user_id: Uuid,
+    /// Remove the role if false.
+    pub assigned: bool,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Kick {
@@ -45,6 +113,10 @@ pub struct Edit {
     pub explorable: Option<bool>,
     pub is_public: Option<bool>,
     pub allow_spectator: Option<bool>,
+    /// An empty string removes the password.
+    pub password: Option<String>,
+    /// Permissions every member has.
+    pub member_permissions: Option<Vec<Permission>>,
     #[serde(default)]
     pub grant_admins: Vec<Uuid>,
     #[serde(default)]
@@ -56,9 +128,14 @@ pub struct Edit {
 pub struct SpaceWithRelated {
     pub space: super::Space,
     pub members: HashMap<Uuid, super::models::SpaceMemberWithUser>,
+    /// In the order of the sidebar.
     pub channels: Vec<crate::channels::Channel>,
+    pub categories: Vec<crate::channels::ChannelCategory>,
     pub channel_members: HashMap<Uuid, Vec<crate::channels::ChannelMember>>,
     pub users_status: HashMap<Uuid, UserStatus>,
+    pub roles: Vec<super::SpaceRole>,
+    /// IDs of roles of each member.
+    pub member_roles: HashMap<Uuid, Vec<Uuid>>,
 }
 
 #[derive(Serialize, Debug)]
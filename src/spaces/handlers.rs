@@ -2,14 +2,19 @@ use std::collections::HashMap;
 
 use super::api::{Create, Edit, SpaceWithRelated};
 use super::models::space_users_status;
-use super::{Space, SpaceMember};
-use crate::channels::{Channel, ChannelMember};
+use super::permissions::{self, Permission, Permissions};
+use super::{Space, SpaceInvite, SpaceMember, SpaceRole, SpaceTransfer};
+use crate::cache::make_key;
+use crate::channels::{Channel, ChannelCategory, ChannelMember};
 use crate::csrf::authenticate;
-use crate::database;
+use crate::database::{self, Querist};
 use crate::error::{AppError, Find};
 use crate::events::Event;
 use crate::interface::{self, missing, ok_response, parse_query, IdQuery, Response};
-use crate::spaces::api::{Join, Kick, SearchParams, SpaceWithMember};
+use crate::spaces::api::{
+    AssignRole, CreateInvite, CreateRole, EditRole, InvitePreview, Join, JoinPassword, Kick, SearchParams,
+    SpaceWithMember, Transfer,
+};
 use crate::spaces::models::SpaceMemberWithUser;
 use hyper::{Body, Request};
 use uuid::Uuid;
@@ -31,16 +36,23 @@ pub async fn space_related(id: &Uuid) -> Result<SpaceWithRelated, AppError> {
     let db = &mut *conn;
     let space = Space::get_by_id(db, id).await?.or_not_found()?;
     let members = SpaceMemberWithUser::get_by_space(db, id).await?;
-    let channels = Channel::get_by_space(db, id).await?;
+    // Archived channels are included, clients decide how to show them.
+    let channels = Channel::get_by_space(db, id, true).await?;
+    let categories = ChannelCategory::get_by_space(db, id).await?;
     let mut cache = crate::cache::conn().await;
     let users_status = space_users_status(&mut cache, space.id).await?;
     let channel_members = ChannelMember::get_by_space(db, &space.id).await?;
+    let roles = SpaceRole::get_by_space(db, id).await?;
+    let member_roles = SpaceRole::get_member_roles(db, id).await?;
     Ok(SpaceWithRelated {
         space,
         members,
         channels,
+        categories,
         users_status,
         channel_members,
+        roles,
+        member_roles,
     })
 }
 
@@ -54,13 +66,7 @@ async fn token(req: Request<Body>) -> Result<Uuid, AppError> {
     let IdQuery { id } = parse_query(req.uri())?;
     let mut conn = database::get().await?;
     let db = &mut *conn;
-    let is_admin = SpaceMember::get(db, &session.user_id, &id)
-        .await?
-        .map(|space_member| space_member.is_admin)
-        .unwrap_or(false);
-    if !is_admin {
-        return Err(AppError::NoPermission(format!("A non-admin tries to get join token")));
-    }
+    permissions::require(db, &session.user_id, &id, Permission::ManageMembers).await?;
     Space::get_token(db, &id).await.map_err(Into::into)
 }
 
@@ -69,18 +75,87 @@ async fn refresh_token(req: Request<Body>) -> Result<Uuid, AppError> {
     let IdQuery { id } = parse_query(req.uri())?;
     let mut conn = database::get().await?;
     let db = &mut *conn;
-    let is_admin = SpaceMember::get(db, &session.user_id, &id)
-        .await?
-        .map(|space_member| space_member.is_admin)
-        .unwrap_or(false);
-    if !is_admin {
-        return Err(AppError::NoPermission(format!(
-            "A non-admin tries to refresh join token"
-        )));
-    }
+    permissions::require(db, &session.user_id, &id, Permission::ManageMembers).await?;
     Space::refresh_token(db, &id).await.map_err(Into::into)
 }
 
+async fn invites(req: Request<Body>) -> Result<Vec<SpaceInvite>, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    permissions::require(db, &session.user_id, &id, Permission::ManageMembers).await?;
+    SpaceInvite::get_by_space(db, &id).await.map_err(Into::into)
+}
+
+async fn create_invite(req: Request<Body>) -> Result<SpaceInvite, AppError> {
+    let session = authenticate(&req).await?;
+    let CreateInvite {
+        space_id,
+        name,
+        channel_id,
+        spectator,
+        max_uses,
+        expires,
+    } = interface::parse_body(req).await?;
+    if max_uses.map_or(false, |max_uses| max_uses < 1) {
+        return Err(AppError::BadRequest(
+            "An invite should be usable at least once".to_string(),
+        ));
+    }
+    if expires.map_or(false, |expires| expires <= chrono::Utc::now().naive_utc()) {
+        return Err(AppError::BadRequest("The expiration time has passed".to_string()));
+    }
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    permissions::require(db, &session.user_id, &space_id, Permission::ManageMembers).await?;
+    if let Some(channel_id) = channel_id.as_ref() {
+        let channel = Channel::get_by_id(db, channel_id).await?.or_not_found()?;
+        if channel.space_id != space_id {
+            return Err(AppError::BadRequest("The channel is not in the space".to_string()));
+        }
+    }
+    let invite = SpaceInvite::create(
+        db,
+        &space_id,
+        &session.user_id,
+        &*name,
+        channel_id.as_ref(),
+        spectator,
+        max_uses,
+        expires,
+    )
+    .await?;
+    Ok(invite)
+}
+
+async fn revoke_invite(req: Request<Body>) -> Result<SpaceInvite, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let invite = SpaceInvite::get_by_id(db, &id).await?.or_not_found()?;
+    permissions::require(db, &session.user_id, &invite.space_id, Permission::ManageMembers).await?;
+    SpaceInvite::revoke(db, &id).await?.or_not_found()
+}
+
+async fn preview_invite(req: Request<Body>) -> Result<InvitePreview, AppError> {
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let invite = SpaceInvite::get_by_id(db, &id).await?.or_not_found()?;
+    if !invite.is_usable(chrono::Utc::now().naive_utc()) {
+        return Err(AppError::NotFound("invite"));
+    }
+    let space = Space::get_by_id(db, &invite.space_id).await?.or_not_found()?;
+    let member_count = Space::member_count(db, &space.id).await?;
+    Ok(InvitePreview {
+        invite,
+        space,
+        member_count,
+    })
+}
+
 async fn my_spaces(req: Request<Body>) -> Result<Vec<SpaceWithMember>, AppError> {
     let session = authenticate(&req).await?;
     let mut conn = database::get().await?;
@@ -128,6 +203,8 @@ async fn edit(req: Request<Body>) -> Result<Space, AppError> {
         explorable,
         is_public,
         allow_spectator,
+        password,
+        member_permissions,
         grant_admins,
         remove_admins,
     }: Edit = interface::parse_body(req).await?;
@@ -136,13 +213,8 @@ async fn edit(req: Request<Body>) -> Result<Space, AppError> {
     let mut trans = conn.transaction().await?;
     let db = &mut trans;
 
-    let space_member = SpaceMember::get(db, &session.user_id, &space_id)
-        .await
-        .or_no_permission()?;
-    if !space_member.is_admin {
-        return Err(AppError::NoPermission(format!("A non-admin tries to edit space")));
-    }
-    let space = Space::edit(
+    permissions::require_admin(db, &session.user_id, &space_id).await?;
+    let mut space = Space::edit(
         db,
         space_id,
         name,
@@ -151,9 +223,15 @@ async fn edit(req: Request<Body>) -> Result<Space, AppError> {
         explorable,
         is_public,
         allow_spectator,
+        password,
     )
     .await?
     .ok_or_else(|| unexpected!("No such space found."))?;
+    if let Some(member_permissions) = member_permissions {
+        space = Space::set_member_permissions(db, &space_id, Permissions::from_list(&*member_permissions))
+            .await?
+            .ok_or_else(|| unexpected!("No such space found."))?;
+    }
 
     if space.owner_id == session.user_id {
         for user_id in grant_admins.iter() {
@@ -171,26 +249,86 @@ async fn edit(req: Request<Body>) -> Result<Space, AppError> {
     Ok(space)
 }
 
+/// Wrong passwords allowed in the window, for a user and a space.
+const MAX_PASSWORD_FAILURES: u32 = 5;
+const PASSWORD_FAILURE_WINDOW: usize = 60 * 15;
+
+async fn check_join_password<T: Querist>(
+    db: &mut T,
+    user_id: &Uuid,
+    space_id: &Uuid,
+    password: &str,
+) -> Result<(), AppError> {
+    let mut cache = crate::cache::conn().await;
+    let key = make_key(b"space", space_id, format!("password_failures:{}", user_id).as_bytes());
+    // Counted before checking, so concurrent guesses can't pass the limit.
+    let attempts = cache.incr_with_expiration(&*key, PASSWORD_FAILURE_WINDOW).await?;
+    if attempts > MAX_PASSWORD_FAILURES {
+        return Err(AppError::TooManyRequests(
+            "Too many wrong passwords, please try again later".to_string(),
+        ));
+    }
+    if !Space::check_password(db, space_id, password).await? {
+        return Err(AppError::NoPermission("The password is wrong".to_string()));
+    }
+    cache.remove(&*key).await?;
+    Ok(())
+}
+
 async fn join(req: Request<Body>) -> Result<SpaceWithMember, AppError> {
     let session = authenticate(&req).await?;
     let Join { space_id, token } = parse_query(req.uri())?;
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let JoinPassword { password } = if body.is_empty() {
+        JoinPassword::default()
+    } else {
+        serde_json::from_slice(&*body)
+            .map_err(|_| AppError::BadRequest("Failed to parse the request body".to_string()))?
+    };
 
-    let mut db = database::get().await?;
-    let db = &mut *db;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
 
     let space = Space::get_by_id(db, &space_id).await?.or_not_found()?;
-    if !space.is_public && token != Some(space.invite_token) && space.owner_id != session.user_id {
-        return Err(AppError::NoPermission(format!(
-            "A user tries to join group without token"
-        )));
-    }
     let user_id = &session.user_id;
+    if let Some(member) = SpaceMember::get(db, user_id, &space_id).await? {
+        return Ok(SpaceWithMember { space, member });
+    }
+    let mut invite = None;
+    if let Some(token) = token.filter(|token| *token != space.invite_token) {
+        invite = SpaceInvite::consume(db, &token, &space_id).await?;
+        if invite.is_none() {
+            return Err(AppError::NoPermission(
+                "The invite has expired or been used up".to_string(),
+            ));
+        }
+    }
+    let with_token = invite.is_some() || token == Some(space.invite_token);
+    if !space.is_public && !with_token && space.owner_id != *user_id {
+        return Err(AppError::NoPermission(
+            "A user tries to join group without token".to_string(),
+        ));
+    }
+    if !with_token && space.owner_id != *user_id && !space.password.is_empty() {
+        check_join_password(db, user_id, &space_id, &*password).await?;
+    }
     let member = if &space.owner_id == user_id {
         SpaceMember::add_admin(db, user_id, &space_id).await?
     } else {
         SpaceMember::add_user(db, user_id, &space_id).await?
     };
+    let channel_id = invite
+        .filter(|invite| !invite.spectator)
+        .and_then(|invite| invite.channel_id);
+    if let Some(channel_id) = channel_id.as_ref() {
+        ChannelMember::add_user(db, user_id, channel_id, "", false).await?;
+    }
+    trans.commit().await?;
     Event::space_updated(space_id);
+    if let Some(channel_id) = channel_id {
+        Event::push_members(channel_id);
+    }
     Ok(SpaceWithMember { space, member })
 }
 
@@ -218,22 +356,187 @@ async fn kick(req: Request<Body>) -> Result<bool, AppError> {
     let mut conn = database::get().await?;
     let mut trans = conn.transaction().await?;
     let db = &mut trans;
-    let my_member = SpaceMember::get(db, &session.user_id, &space_id).await.or_not_found()?;
+    permissions::require(db, &session.user_id, &space_id, Permission::ManageMembers).await?;
     let kick_member = SpaceMember::get(db, &user_id, &space_id).await.or_not_found()?;
     if kick_member.is_admin {
         return Err(AppError::BadRequest("Can't kick admin".to_string()));
     }
-    if my_member.is_admin {
-        let channels = SpaceMember::remove_user(db, &user_id, &space_id).await?;
-        trans.commit().await?;
-        Event::space_updated(space_id);
-        for channel_id in channels {
-            Event::push_members(channel_id);
-        }
-        Ok(true)
+    let channels = SpaceMember::remove_user(db, &user_id, &space_id).await?;
+    trans.commit().await?;
+    Event::space_updated(space_id);
+    for channel_id in channels {
+        Event::push_members(channel_id);
+    }
+    Ok(true)
+}
+
+async fn my_permissions(req: Request<Body>) -> Result<Vec<Permission>, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let permissions = permissions::permissions(db, &session.user_id, &id)
+        .await?
+        .or_no_permission()?;
+    Ok(permissions.to_list())
+}
+
+async fn create_role(req: Request<Body>) -> Result<SpaceRole, AppError> {
+    let session = authenticate(&req).await?;
+    let CreateRole {
+        space_id,
+        name,
+        permissions,
+    } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    permissions::require_admin(db, &session.user_id, &space_id).await?;
+    let role = SpaceRole::create(db, &space_id, &*name, Permissions::from_list(&*permissions)).await?;
+    Event::space_updated(space_id);
+    Ok(role)
+}
+
+async fn edit_role(req: Request<Body>) -> Result<SpaceRole, AppError> {
+    let session = authenticate(&req).await?;
+    let EditRole {
+        role_id,
+        name,
+        permissions,
+    } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let role = SpaceRole::get_by_id(db, &role_id).await?.or_not_found()?;
+    permissions::require_admin(db, &session.user_id, &role.space_id).await?;
+    let permissions = permissions.map(|permissions| Permissions::from_list(&*permissions));
+    let role = SpaceRole::edit(db, &role_id, name.as_deref(), permissions)
+        .await?
+        .or_not_found()?;
+    Event::space_updated(role.space_id);
+    Ok(role)
+}
+
+async fn delete_role(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let role = SpaceRole::get_by_id(db, &id).await?.or_not_found()?;
+    permissions::require_admin(db, &session.user_id, &role.space_id).await?;
+    SpaceRole::delete(db, &id).await?;
+    Event::space_updated(role.space_id);
+    Ok(true)
+}
+
+async fn assign_role(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let AssignRole {
+        role_id,
+        user_id,
+        assigned,
+    } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let role = SpaceRole::get_by_id(db, &role_id).await?.or_not_found()?;
+    permissions::require_admin(db, &session.user_id, &role.space_id).await?;
+    SpaceMember::get(db, &user_id, &role.space_id)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("The user is not a member of the space".to_string()))?;
+    if assigned {
+        SpaceRole::assign(db, &role_id, &user_id).await?;
     } else {
-        Err(AppError::NoPermission(format!("A non-admin tries to kick")))
+        SpaceRole::unassign(db, &role_id, &user_id).await?;
+    }
+    Event::space_updated(role.space_id);
+    Ok(true)
+}
+
+async fn transfer(req: Request<Body>) -> Result<SpaceTransfer, AppError> {
+    let session = authenticate(&req).await?;
+    let Transfer { space_id, user_id } = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let space = Space::get_by_id(db, &space_id).await?.or_not_found()?;
+    if space.owner_id != session.user_id {
+        return Err(AppError::NoPermission(
+            "Only the owner can transfer the space".to_string(),
+        ));
+    }
+    if user_id == space.owner_id {
+        return Err(AppError::BadRequest("The user already owns the space".to_string()));
     }
+    let nominee = SpaceMember::get(db, &user_id, &space_id).await?;
+    if !nominee.map_or(false, |member| member.is_admin) {
+        return Err(AppError::BadRequest(
+            "The new owner must be an admin of the space".to_string(),
+        ));
+    }
+    let transfer = SpaceTransfer::nominate(db, &space_id, &user_id, &session.user_id).await?;
+    log::info!(
+        "The owner of the space {} nominated {} as the new owner",
+        space_id,
+        user_id
+    );
+    Ok(transfer)
+}
+
+async fn query_transfer(req: Request<Body>) -> Result<Option<SpaceTransfer>, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    SpaceMember::get(db, &session.user_id, &id).await.or_no_permission()?;
+    SpaceTransfer::get(db, &id).await.map_err(Into::into)
+}
+
+async fn accept_transfer(req: Request<Body>) -> Result<Space, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let space = Space::get_by_id(db, &id).await?.or_not_found()?;
+    let transfer = SpaceTransfer::get(db, &id).await?.or_not_found()?;
+    if transfer.nominee_id != session.user_id {
+        return Err(AppError::NoPermission(
+            "The space was not transferred to you".to_string(),
+        ));
+    }
+    // The nomination is no longer valid if the owner or the nominee has changed since.
+    let nominee = SpaceMember::get(db, &session.user_id, &id).await?;
+    if transfer.nominator_id != space.owner_id || !nominee.map_or(false, |member| member.is_admin) {
+        SpaceTransfer::remove(db, &id).await?;
+        trans.commit().await?;
+        return Err(AppError::BadRequest("The transfer is no longer valid".to_string()));
+    }
+    let space = Space::set_owner(db, &id, &session.user_id).await?;
+    SpaceMember::set_admin(db, &transfer.nominator_id, &id, true).await?;
+    SpaceTransfer::remove(db, &id).await?;
+    trans.commit().await?;
+    log::info!(
+        "The space {} was transferred from {} to {}",
+        id,
+        transfer.nominator_id,
+        session.user_id
+    );
+    Event::space_updated(id);
+    Ok(space)
+}
+
+/// Both the owner and the nominee can cancel a transfer.
+async fn cancel_transfer(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let space = Space::get_by_id(db, &id).await?.or_not_found()?;
+    let transfer = SpaceTransfer::get(db, &id).await?.or_not_found()?;
+    if session.user_id != space.owner_id && session.user_id != transfer.nominee_id {
+        return Err(AppError::NoPermission(
+            "A user tries to cancel others' transfer".to_string(),
+        ));
+    }
+    SpaceTransfer::remove(db, &id).await?;
+    Ok(true)
 }
 
 async fn members(req: Request<Body>) -> Result<HashMap<Uuid, SpaceMemberWithUser>, AppError> {
@@ -257,7 +560,7 @@ async fn delete(req: Request<Body>) -> Result<Space, AppError> {
         return Ok(space);
     }
     log::warn!("The user {} failed to try delete a space {}", session.user_id, space.id);
-    Err(AppError::NoPermission(format!("failed to delete")))
+    Err(AppError::NoPermission("failed to delete".to_string()))
 }
 
 pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError> {
@@ -269,6 +572,10 @@ pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError
         ("/query_with_related", Method::GET) => query_with_related(req).await.map(ok_response),
         ("/token", Method::GET) => token(req).await.map(ok_response),
         ("/refresh_token", Method::POST) => refresh_token(req).await.map(ok_response),
+        ("/invites", Method::GET) => invites(req).await.map(ok_response),
+        ("/invite", Method::GET) => preview_invite(req).await.map(ok_response),
+        ("/create_invite", Method::POST) => create_invite(req).await.map(ok_response),
+        ("/revoke_invite", Method::POST) => revoke_invite(req).await.map(ok_response),
         ("/my", Method::GET) => my_spaces(req).await.map(ok_response),
         ("/search", Method::GET) => search(req).await.map(ok_response),
         ("/create", Method::POST) => create(req).await.map(ok_response),
@@ -276,6 +583,15 @@ pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError
         ("/join", Method::POST) => join(req).await.map(ok_response),
         ("/leave", Method::POST) => leave(req).await.map(ok_response),
         ("/kick", Method::POST) => kick(req).await.map(ok_response),
+        ("/query_transfer", Method::GET) => query_transfer(req).await.map(ok_response),
+        ("/transfer", Method::POST) => transfer(req).await.map(ok_response),
+        ("/accept_transfer", Method::POST) => accept_transfer(req).await.map(ok_response),
+        ("/cancel_transfer", Method::POST) => cancel_transfer(req).await.map(ok_response),
+        ("/my_permissions", Method::GET) => my_permissions(req).await.map(ok_response),
+        ("/create_role", Method::POST) => create_role(req).await.map(ok_response),
+        ("/edit_role", Method::POST) => edit_role(req).await.map(ok_response),
+        ("/delete_role", Method::POST) => delete_role(req).await.map(ok_response),
+        ("/assign_role", Method::POST) => assign_role(req).await.map(ok_response),
         ("/members", Method::GET) => members(req).await.map(ok_response),
         ("/delete", Method::POST) => delete(req).await.map(ok_response),
         _ => missing(),
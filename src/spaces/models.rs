@@ -12,6 +12,7 @@ use crate::channels::ChannelMember;
 use crate::database::Querist;
 use crate::error::{AppError, DbError, ModelError};
 use crate::spaces::api::SpaceWithMember;
+use crate::spaces::permissions::Permissions;
 use crate::users::User;
 use crate::utils::{inner_result_map, merge_blank};
 
@@ -53,7 +54,11 @@ pub async fn space_users_status(
             Err(err) => log::error!("failed to deserialize user status in cache: {}", err),
         }
     }
-    return Ok(table);
+    Ok(table)
+}
+
+fn is_set<S: serde::Serializer>(password: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(!password.is_empty())
 }
 
 #[derive(Debug, Serialize, Deserialize, FromSql, Clone)]
@@ -68,7 +73,8 @@ pub struct Space {
     pub modified: NaiveDateTime,
     pub name: String,
     pub description: String,
-    #[serde(skip)]
+    /// Hashed, only whether it is set is sent to clients.
+    #[serde(rename = "hasPassword", serialize_with = "is_set", skip_deserializing)]
     pub password: String,
     pub language: String,
     pub default_dice_type: String,
@@ -77,6 +83,9 @@ pub struct Space {
     pub deleted: bool,
     pub explorable: bool,
     pub allow_spectator: bool,
+    /// Permissions every member has.
+    #[serde(with = "crate::spaces::permissions::as_list")]
+    pub member_permissions: i32,
 }
 
 impl Space {
@@ -88,9 +97,12 @@ impl Space {
         password: Option<String>,
         default_dice_type: Option<&str>,
     ) -> Result<Space, ModelError> {
-        use crate::validators::{DESCRIPTION, DICE, DISPLAY_NAME};
+        use crate::validators::{DESCRIPTION, DICE, DISPLAY_NAME, SPACE_PASSWORD};
         let name = merge_blank(&*name);
         DISPLAY_NAME.run(&name)?;
+        if let Some(password) = password.as_ref() {
+            SPACE_PASSWORD.run(password)?;
+        }
         if let Some(default_dice_type) = default_dice_type {
             DICE.run(default_dice_type)?;
         }
@@ -143,16 +155,50 @@ impl Space {
         row.try_get(0)
     }
 
+    pub async fn set_owner<T: Querist>(db: &mut T, id: &Uuid, owner_id: &Uuid) -> Result<Space, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/set_owner.sql"), &[id, owner_id])
+            .await?;
+        row.try_get(0)
+    }
+
+    pub async fn set_member_permissions<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        permissions: Permissions,
+    ) -> Result<Option<Space>, DbError> {
+        let result = db
+            .query_one(include_str!("sql/set_member_permissions.sql"), &[id, &permissions.0])
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
     pub async fn get_token<T: Querist>(db: &mut T, id: &Uuid) -> Result<Uuid, DbError> {
         let row = db.query_exactly_one(include_str!("sql/get_token.sql"), &[id]).await?;
         row.try_get(0)
     }
 
+    /// Always passes if the space has no password.
+    pub async fn check_password<T: Querist>(db: &mut T, id: &Uuid, password: &str) -> Result<bool, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/check_password.sql"), &[id, &password])
+            .await?;
+        row.try_get(0)
+    }
+
+    pub async fn member_count<T: Querist>(db: &mut T, id: &Uuid) -> Result<i64, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/count_members.sql"), &[id])
+            .await?;
+        row.try_get(0)
+    }
+
     pub async fn is_public<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<bool>, DbError> {
         let row = db.query_one(include_str!("sql/is_public.sql"), &[id]).await?;
         Ok(row.map(|row| row.get(0)))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn edit<T: Querist>(
         db: &mut T,
         space_id: Uuid,
@@ -162,6 +208,7 @@ impl Space {
         explorable: Option<bool>,
         is_public: Option<bool>,
         allow_spectator: Option<bool>,
+        password: Option<String>,
     ) -> Result<Option<Space>, ModelError> {
         use crate::validators;
         let name = name.as_ref().map(|s| s.trim());
@@ -175,6 +222,9 @@ impl Space {
         if let Some(dice) = default_dice_type.as_ref() {
             validators::DICE.run(dice)?;
         }
+        if let Some(password) = password.as_ref() {
+            validators::SPACE_PASSWORD.run(password)?;
+        }
         let result = db
             .query_one(
                 include_str!("sql/edit.sql"),
@@ -186,6 +236,7 @@ impl Space {
                     &explorable,
                     &is_public,
                     &allow_spectator,
+                    &password,
                 ],
             )
             .await?;
@@ -302,6 +353,216 @@ impl SpaceMember {
     }
 }
 
+/// An invite link of a space, the ID is the token in the link.
+///
+/// Players join the target channel along with the space, spectators only join the space.
+#[derive(Debug, Serialize, Deserialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "space_invites")]
+pub struct SpaceInvite {
+    pub id: Uuid,
+    pub space_id: Uuid,
+    pub creator_id: Uuid,
+    pub name: String,
+    pub channel_id: Option<Uuid>,
+    pub spectator: bool,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    #[serde(with = "crate::date_format::option")]
+    pub expires: Option<NaiveDateTime>,
+    pub revoked: bool,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl SpaceInvite {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        creator_id: &Uuid,
+        name: &str,
+        channel_id: Option<&Uuid>,
+        spectator: bool,
+        max_uses: Option<i32>,
+        expires: Option<NaiveDateTime>,
+    ) -> Result<SpaceInvite, ModelError> {
+        let name = merge_blank(name);
+        if !name.is_empty() {
+            crate::validators::DISPLAY_NAME.run(&name)?;
+        }
+        let row = db
+            .query_exactly_one(
+                include_str!("sql/create_invite.sql"),
+                &[
+                    space_id,
+                    creator_id,
+                    &name,
+                    &channel_id,
+                    &spectator,
+                    &max_uses,
+                    &expires,
+                ],
+            )
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    pub async fn get_by_id<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<SpaceInvite>, DbError> {
+        let result = db.query_one(include_str!("sql/get_invite.sql"), &[id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    /// Invites which are not revoked, expired ones included.
+    pub async fn get_by_space<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<Vec<SpaceInvite>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_invites_by_space.sql"), &[space_id])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn revoke<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<SpaceInvite>, DbError> {
+        let result = db.query_one(include_str!("sql/revoke_invite.sql"), &[id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    /// Count a use of the invite, returns `None` if it can't be used anymore.
+    pub async fn consume<T: Querist>(db: &mut T, id: &Uuid, space_id: &Uuid) -> Result<Option<SpaceInvite>, DbError> {
+        let result = db.query_one(include_str!("sql/use_invite.sql"), &[id, space_id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub fn is_usable(&self, now: NaiveDateTime) -> bool {
+        !self.revoked
+            && self.expires.map_or(true, |expires| expires > now)
+            && self.max_uses.map_or(true, |max_uses| self.uses < max_uses)
+    }
+}
+
+/// A named set of permissions, assigned to members of the space.
+#[derive(Debug, Serialize, Deserialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "space_roles")]
+pub struct SpaceRole {
+    pub id: Uuid,
+    pub space_id: Uuid,
+    pub name: String,
+    #[serde(with = "crate::spaces::permissions::as_list")]
+    pub permissions: i32,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl SpaceRole {
+    pub async fn create<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        name: &str,
+        permissions: Permissions,
+    ) -> Result<SpaceRole, ModelError> {
+        let name = merge_blank(name);
+        crate::validators::DISPLAY_NAME.run(&name)?;
+        let row = db
+            .query_exactly_one(include_str!("sql/create_role.sql"), &[space_id, &name, &permissions.0])
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    pub async fn get_by_id<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<SpaceRole>, DbError> {
+        let result = db.query_one(include_str!("sql/get_role.sql"), &[id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn get_by_space<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<Vec<SpaceRole>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_roles_by_space.sql"), &[space_id])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn edit<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        name: Option<&str>,
+        permissions: Option<Permissions>,
+    ) -> Result<Option<SpaceRole>, ModelError> {
+        let name = name.map(merge_blank);
+        if let Some(name) = name.as_ref() {
+            crate::validators::DISPLAY_NAME.run(name)?;
+        }
+        let permissions = permissions.map(|permissions| permissions.0);
+        let result = db
+            .query_one(include_str!("sql/edit_role.sql"), &[id, &name, &permissions])
+            .await;
+        Ok(inner_result_map(result, |row| row.try_get(0))?)
+    }
+
+    pub async fn delete<T: Querist>(db: &mut T, id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/delete_role.sql"), &[id]).await
+    }
+
+    /// The user must be a member of the space of the role.
+    pub async fn assign<T: Querist>(db: &mut T, id: &Uuid, user_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/assign_role.sql"), &[user_id, id]).await
+    }
+
+    pub async fn unassign<T: Querist>(db: &mut T, id: &Uuid, user_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/unassign_role.sql"), &[user_id, id]).await
+    }
+
+    /// Roles of each member of the space.
+    pub async fn get_member_roles<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+    ) -> Result<HashMap<Uuid, Vec<Uuid>>, DbError> {
+        let rows = db.query(include_str!("sql/get_member_roles.sql"), &[space_id]).await?;
+        let mut member_roles: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for row in rows {
+            member_roles.entry(row.try_get(0)?).or_default().push(row.try_get(1)?);
+        }
+        Ok(member_roles)
+    }
+}
+
+/// The owner of a space nominates an admin, who becomes the owner after accepting.
+#[derive(Debug, Serialize, Deserialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "space_transfers")]
+pub struct SpaceTransfer {
+    pub space_id: Uuid,
+    pub nominee_id: Uuid,
+    pub nominator_id: Uuid,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl SpaceTransfer {
+    /// Replaces the previous nomination of the space.
+    pub async fn nominate<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        nominee_id: &Uuid,
+        nominator_id: &Uuid,
+    ) -> Result<SpaceTransfer, DbError> {
+        let row = db
+            .query_exactly_one(
+                include_str!("sql/nominate_owner.sql"),
+                &[space_id, nominee_id, nominator_id],
+            )
+            .await?;
+        row.try_get(0)
+    }
+
+    pub async fn get<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<Option<SpaceTransfer>, DbError> {
+        let result = db.query_one(include_str!("sql/get_transfer.sql"), &[space_id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn remove<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/remove_transfer.sql"), &[space_id]).await
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct SpaceMemberWithUser {
@@ -358,7 +619,7 @@ async fn space_test() -> Result<(), crate::error::AppError> {
     let space_name = "Pure Illusion";
     let user = User::register(db, email, username, nickname, password).await.unwrap();
     let space = Space::create(db, space_name.to_string(), &user.id, String::new(), None, None).await?;
-    Space::edit(db, space.id, None, None, None, Some(true), None, None)
+    Space::edit(db, space.id, None, None, None, Some(true), None, None, None)
         .await?
         .unwrap();
     let space = Space::get_by_id(db, &space.id).await?.unwrap();
@@ -382,11 +643,33 @@ async fn space_test() -> Result<(), crate::error::AppError> {
         None,
         None,
         None,
+        None,
     )
     .await?
     .unwrap();
     assert_eq!(space_edited.name, new_name);
 
+    // password
+    assert!(Space::check_password(db, &space.id, "").await?);
+    let space_edited = Space::edit(
+        db,
+        space.id,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        Some("soul gem".to_string()),
+    )
+    .await?
+    .unwrap();
+    assert_ne!(space_edited.password, "soul gem");
+    assert!(Space::check_password(db, &space.id, "soul gem").await?);
+    assert!(!Space::check_password(db, &space.id, "grief seed").await?);
+    Space::edit(db, space.id, None, None, None, None, None, None, Some(String::new())).await?;
+    assert!(Space::check_password(db, &space.id, "").await?);
+
     let _space_2 = Space::create(db, "学园都市".to_string(), &user.id, String::new(), None, None).await?;
     // let result = Space::edit(db, _space_2.id, Some(new_name.to_string())).await;
     // assert!(if let Err(ModelError::Conflict(_)) = result { true } else { false });
@@ -405,6 +688,88 @@ async fn space_test() -> Result<(), crate::error::AppError> {
     assert_eq!(joined.len(), 1);
     assert_eq!(joined[0].member.space_id, space.id);
 
+    assert_eq!(Space::member_count(db, &space.id).await?, 1);
+
+    // invites
+    let invite = SpaceInvite::create(db, &space.id, &user.id, "Guests", None, false, Some(1), None).await?;
+    assert!(invite.is_usable(chrono::Utc::now().naive_utc()));
+    let invites = SpaceInvite::get_by_space(db, &space.id).await?;
+    assert_eq!(invites.len(), 1);
+    assert!(SpaceInvite::consume(db, &invite.id, &_space_2.id).await?.is_none());
+    let invite = SpaceInvite::consume(db, &invite.id, &space.id).await?.unwrap();
+    assert_eq!(invite.uses, 1);
+    assert!(!invite.is_usable(chrono::Utc::now().naive_utc()));
+    assert!(SpaceInvite::consume(db, &invite.id, &space.id).await?.is_none());
+    let expired = chrono::Utc::now().naive_utc() - chrono::Duration::hours(1);
+    let invite = SpaceInvite::create(db, &space.id, &user.id, "", None, true, None, Some(expired)).await?;
+    assert!(SpaceInvite::consume(db, &invite.id, &space.id).await?.is_none());
+    let invite = SpaceInvite::create(db, &space.id, &user.id, "", None, true, None, None).await?;
+    SpaceInvite::revoke(db, &invite.id).await?;
+    assert!(SpaceInvite::consume(db, &invite.id, &space.id).await?.is_none());
+    assert!(SpaceInvite::get_by_id(db, &invite.id).await?.unwrap().revoked);
+    assert_eq!(SpaceInvite::get_by_space(db, &space.id).await?.len(), 2);
+
+    // roles
+    use crate::spaces::permissions::{self, Permission};
+    let member = User::register(db, "test-space-3@mythal.net", "space_test_user_3", "Member", password).await?;
+    SpaceMember::add_user(db, &member.id, &space.id).await?;
+    let member_permissions = permissions::permissions(db, &member.id, &space.id).await?.unwrap();
+    assert_eq!(member_permissions.to_list(), vec![Permission::UploadMedia]);
+    assert_eq!(
+        permissions::permissions(db, &user.id, &space.id).await?,
+        Some(Permissions::ALL)
+    );
+    let role = SpaceRole::create(
+        db,
+        &space.id,
+        "Co-GM",
+        Permissions::from_list(&[Permission::MoveMessages]),
+    )
+    .await?;
+    SpaceRole::assign(db, &role.id, &member.id).await?;
+    assert!(permissions::has(db, &member.id, &space.id, Permission::MoveMessages).await?);
+    assert_eq!(
+        SpaceRole::get_member_roles(db, &space.id).await?[&member.id],
+        vec![role.id]
+    );
+    let role = SpaceRole::edit(
+        db,
+        &role.id,
+        None,
+        Some(Permissions::from_list(&[Permission::SeeWhispers])),
+    )
+    .await?
+    .unwrap();
+    assert_eq!(role.name, "Co-GM");
+    assert!(!permissions::has(db, &member.id, &space.id, Permission::MoveMessages).await?);
+    assert!(permissions::has(db, &member.id, &space.id, Permission::SeeWhispers).await?);
+    Space::set_member_permissions(db, &space.id, Permissions::default()).await?;
+    assert!(!permissions::has(db, &member.id, &space.id, Permission::UploadMedia).await?);
+    SpaceRole::unassign(db, &role.id, &member.id).await?;
+    assert!(!permissions::has(db, &member.id, &space.id, Permission::SeeWhispers).await?);
+    SpaceRole::assign(db, &role.id, &member.id).await?;
+    SpaceMember::remove_user(db, &member.id, &space.id).await?;
+    assert!(SpaceRole::get_member_roles(db, &space.id).await?.is_empty());
+    assert!(permissions::permissions(db, &member.id, &space.id).await?.is_none());
+    SpaceRole::delete(db, &role.id).await?;
+    assert!(SpaceRole::get_by_space(db, &space.id).await?.is_empty());
+
+    // transfer
+    let nominee = User::register(db, "test-space-2@mythal.net", "space_test_user_2", "Nominee", password).await?;
+    SpaceMember::add_admin(db, &nominee.id, &space.id).await?;
+    SpaceTransfer::nominate(db, &space.id, &user.id, &user.id).await?;
+    let transfer = SpaceTransfer::nominate(db, &space.id, &nominee.id, &user.id).await?;
+    assert_eq!(
+        SpaceTransfer::get(db, &space.id).await?.unwrap().nominee_id,
+        transfer.nominee_id
+    );
+    let space_transferred = Space::set_owner(db, &space.id, &nominee.id).await?;
+    assert_eq!(space_transferred.owner_id, nominee.id);
+    assert_eq!(SpaceTransfer::remove(db, &space.id).await?, 1);
+    assert!(SpaceTransfer::get(db, &space.id).await?.is_none());
+    Space::set_owner(db, &space.id, &user.id).await?;
+    SpaceMember::remove_user(db, &nominee.id, &space.id).await?;
+
     SpaceMember::remove_user(db, &user.id, &space.id).await?;
     assert!(SpaceMember::get(db, &user.id, &space.id).await?.is_none());
 
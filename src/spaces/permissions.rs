@@ -0,0 +1,146 @@
+//! What members can do in a space.
+//!
+//! Admins can do everything, other members have the permissions of the space and of their roles.
+use super::SpaceMember;
+use crate::database::Querist;
+use crate::error::{AppError, DbError, Find};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The discriminant is the position in the bitset, don't reorder.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Permission {
+    /// Edit, delete, archive and arrange channels.
+    ManageChannels = 0,
+    /// Manage invites and kick members.
+    ManageMembers = 1,
+    DeleteMessages = 2,
+    MoveMessages = 3,
+    SeeWhispers = 4,
+    PinMessages = 5,
+    UploadMedia = 6,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 7] = [
+        Permission::ManageChannels,
+        Permission::ManageMembers,
+        Permission::DeleteMessages,
+        Permission::MoveMessages,
+        Permission::SeeWhispers,
+        Permission::PinMessages,
+        Permission::UploadMedia,
+    ];
+
+    pub fn bit(self) -> i32 {
+        1 << self as i32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Permissions(pub i32);
+
+impl Permissions {
+    pub const ALL: Permissions = Permissions((1 << Permission::ALL.len()) - 1);
+
+    pub fn has(self, permission: Permission) -> bool {
+        self.0 & permission.bit() != 0
+    }
+
+    pub fn from_list(list: &[Permission]) -> Permissions {
+        Permissions(list.iter().fold(0, |bits, permission| bits | permission.bit()))
+    }
+
+    pub fn to_list(self) -> Vec<Permission> {
+        Permission::ALL
+            .iter()
+            .copied()
+            .filter(|permission| self.has(*permission))
+            .collect()
+    }
+}
+
+/// Bitsets in the database are lists of names in the API.
+pub mod as_list {
+    use super::{Permission, Permissions};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bits: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        Permissions(*bits).to_list().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        let list = Vec::<Permission>::deserialize(deserializer)?;
+        Ok(Permissions::from_list(&*list).0)
+    }
+}
+
+/// Permissions of the user in the space, `None` if the user is not a member.
+pub async fn permissions<T: Querist>(
+    db: &mut T,
+    user_id: &Uuid,
+    space_id: &Uuid,
+) -> Result<Option<Permissions>, DbError> {
+    let row = db
+        .query_one(include_str!("sql/get_permissions.sql"), &[user_id, space_id])
+        .await?;
+    match row {
+        Some(row) => {
+            let is_admin: bool = row.try_get(0)?;
+            let bits: i32 = row.try_get(1)?;
+            Ok(Some(if is_admin { Permissions::ALL } else { Permissions(bits) }))
+        }
+        None => Ok(None),
+    }
+}
+
+pub async fn has<T: Querist>(
+    db: &mut T,
+    user_id: &Uuid,
+    space_id: &Uuid,
+    permission: Permission,
+) -> Result<bool, DbError> {
+    let permissions = permissions(db, user_id, space_id).await?;
+    Ok(permissions.map_or(false, |permissions| permissions.has(permission)))
+}
+
+pub async fn require<T: Querist>(
+    db: &mut T,
+    user_id: &Uuid,
+    space_id: &Uuid,
+    permission: Permission,
+) -> Result<(), AppError> {
+    if !has(db, user_id, space_id, permission).await? {
+        return Err(AppError::NoPermission(format!(
+            "The permission {:?} is required",
+            permission
+        )));
+    }
+    Ok(())
+}
+
+/// For space settings and roles, which can't be granted.
+pub async fn require_admin<T: Querist>(db: &mut T, user_id: &Uuid, space_id: &Uuid) -> Result<SpaceMember, AppError> {
+    let member = SpaceMember::get(db, user_id, space_id).await.or_no_permission()?;
+    if !member.is_admin {
+        return Err(AppError::NoPermission("Only space admins can do this".to_string()));
+    }
+    Ok(member)
+}
+
+#[test]
+fn permissions_test() {
+    let permissions = Permissions::from_list(&[Permission::MoveMessages, Permission::UploadMedia]);
+    assert_eq!(permissions.0, 8 | 64);
+    assert!(permissions.has(Permission::MoveMessages));
+    assert!(!permissions.has(Permission::DeleteMessages));
+    assert_eq!(
+        permissions.to_list(),
+        vec![Permission::MoveMessages, Permission::UploadMedia]
+    );
+    assert!(Permission::ALL
+        .iter()
+        .all(|permission| Permissions::ALL.has(*permission)));
+    assert_eq!(Permissions::ALL.0, 127);
+}
@@ -0,0 +1,5 @@
+INSERT INTO space_member_roles (user_id, space_id, role_id)
+SELECT $1, r.space_id, r.id
+FROM space_roles r
+WHERE r.id = $2
+ON CONFLICT DO NOTHING;
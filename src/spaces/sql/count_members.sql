@@ -0,0 +1,3 @@
+SELECT count(*)
+FROM space_members
+WHERE space_id = $1;
@@ -0,0 +1,3 @@
+INSERT INTO space_invites (space_id, creator_id, name, channel_id, spectator, max_uses, expires)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+RETURNING space_invites;
@@ -4,6 +4,10 @@ SET name              = COALESCE($2, name),
     default_dice_type = COALESCE($4, default_dice_type),
     explorable        = COALESCE($5, explorable),
     is_public         = COALESCE($6, is_public),
-    allow_spectator   = COALESCE($7, allow_spectator)
+    allow_spectator   = COALESCE($7, allow_spectator),
+    password          = CASE
+                            WHEN $8::text IS NULL THEN password
+                            WHEN $8 = '' THEN ''
+                            ELSE crypt($8, gen_salt('bf')) END
 WHERE id = $1
 RETURNING spaces;
@@ -0,0 +1,5 @@
+UPDATE space_roles
+SET name        = COALESCE($2, name),
+    permissions = COALESCE($3, permissions)
+WHERE id = $1
+RETURNING space_roles;
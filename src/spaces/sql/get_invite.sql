@@ -0,0 +1,3 @@
+SELECT space_invites
+FROM space_invites
+WHERE id = $1;
@@ -0,0 +1,4 @@
+SELECT space_invites
+FROM space_invites
+WHERE space_id = $1 AND revoked = false
+ORDER BY created DESC;
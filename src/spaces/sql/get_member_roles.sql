@@ -0,0 +1,3 @@
+SELECT user_id, role_id
+FROM space_member_roles
+WHERE space_id = $1;
@@ -0,0 +1,8 @@
+SELECT m.is_admin,
+       s.member_permissions | COALESCE((SELECT bit_or(r.permissions)
+                                        FROM space_member_roles mr
+                                                 INNER JOIN space_roles r ON r.id = mr.role_id
+                                        WHERE mr.user_id = $1 AND mr.space_id = $2), 0)
+FROM space_members m
+         INNER JOIN spaces s ON s.id = m.space_id
+WHERE m.user_id = $1 AND m.space_id = $2;
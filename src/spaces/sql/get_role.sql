@@ -0,0 +1,3 @@
+SELECT space_roles
+FROM space_roles
+WHERE id = $1;
@@ -0,0 +1,4 @@
+SELECT space_roles
+FROM space_roles
+WHERE space_id = $1
+ORDER BY created;
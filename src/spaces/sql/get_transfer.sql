@@ -0,0 +1,3 @@
+SELECT space_transfers
+FROM space_transfers
+WHERE space_id = $1;
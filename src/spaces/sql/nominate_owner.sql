@@ -0,0 +1,6 @@
+INSERT INTO space_transfers (space_id, nominee_id, nominator_id)
+VALUES ($1, $2, $3)
+ON CONFLICT (space_id) DO UPDATE SET nominee_id   = excluded.nominee_id,
+                                     nominator_id = excluded.nominator_id,
+                                     created      = excluded.created
+RETURNING space_transfers;
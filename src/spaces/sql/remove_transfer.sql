@@ -0,0 +1,3 @@
+DELETE
+FROM space_transfers
+WHERE space_id = $1;
@@ -0,0 +1,4 @@
+UPDATE spaces
+SET member_permissions = $2
+WHERE id = $1
+RETURNING spaces;
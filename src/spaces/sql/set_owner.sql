@@ -0,0 +1,4 @@
+UPDATE spaces
+SET owner_id = $2
+WHERE id = $1
+RETURNING spaces;
@@ -0,0 +1,3 @@
+DELETE
+FROM space_member_roles
+WHERE user_id = $1 AND role_id = $2;
@@ -0,0 +1,8 @@
+UPDATE space_invites
+SET uses = uses + 1
+WHERE id = $1
+  AND space_id = $2
+  AND revoked = false
+  AND (expires IS NULL OR expires > (now() at time zone 'utc'))
+  AND (max_uses IS NULL OR uses < max_uses)
+RETURNING space_invites;
@@ -1,6 +1,8 @@
 mod api;
 mod handlers;
 mod models;
+mod oidc;
+mod totp;
 
 pub use handlers::router;
-pub use models::User;
+pub use models::{User, UserBlock};
@@ -2,12 +2,14 @@ use super::User;
 use crate::channels::api::ChannelWithMember;
 use crate::spaces::api::SpaceWithMember;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct QueryUser {
     pub id: Option<Uuid>,
+    pub username: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,3 +65,87 @@ pub struct Edit {
     pub bio: Option<String>,
     pub avatar: Option<Uuid>,
 }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcLogin {
+    pub provider: String,
+    pub redirect: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcCallback {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Returned by `login` instead of a session if the second factor is required.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginChallenge {
+    pub totp_required: bool,
+    pub challenge: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginTotp {
+    /// `None` takes the challenge from the cookie set by the OpenID Connect callback.
+    pub challenge: Option<String>,
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnroll {
+    pub secret: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpStatus {
+    pub enabled: bool,
+    pub recovery_codes: i64,
+}
+
+/// A TOTP code, or a recovery code where accepted.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpCode {
+    pub code: String,
+}
+
+/// What to do with the messages of a deleted account.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DeletedMessages {
+    Keep,
+    /// Replace the names on the messages.
+    Anonymize,
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAccount {
+    /// Required unless the session has just been started.
+    pub password: Option<String>,
+    /// Required if the two-factor authentication is enabled.
+    pub code: Option<String>,
+    pub messages: DeletedMessages,
+    /// Owned spaces to hand over, space id to the new owner.
+    #[serde(default)]
+    pub transfer_spaces: HashMap<Uuid, Uuid>,
+    /// Delete owned spaces which are not transferred.
+    #[serde(default)]
+    pub delete_spaces: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeUsername {
+    pub username: String,
+}
@@ -1,4 +1,7 @@
-use super::api::{Login, LoginReturn, Register};
+use super::api::{
+    DeleteAccount, DeletedMessages, Login, LoginChallenge, LoginReturn, LoginTotp, Register, TotpCode, TotpEnroll,
+    TotpStatus,
+};
 use super::models::User;
 use crate::database;
 use crate::interface::{missing, ok_response, parse_body, parse_query, Response};
@@ -6,14 +9,24 @@ use crate::session::{remove_session, revoke_session};
 
 use crate::channels::Channel;
 use crate::context::debug;
+use crate::database::Querist;
 use crate::error::{AppError, Find, ValidationFailed};
+use crate::events::context::blocks_changed;
 use crate::interface;
-use crate::media::{upload, upload_params};
+use crate::interface::IdQuery;
+use crate::media::{storage, upload, upload_params, Media};
 use crate::spaces::Space;
-use crate::users::api::{CheckEmailExists, CheckUsernameExists, Edit, GetMe, QueryUser};
-use crate::users::models::UserExt;
+use crate::users::api::{
+    ChangeUsername, CheckEmailExists, CheckUsernameExists, Edit, GetMe, OidcCallback, OidcLogin, QueryUser,
+};
+use crate::users::models::{UserBlock, UserExt, UserIdentity, UserTotp, UsernameHistory};
+use crate::users::oidc::{self, AuthorizeState, Claims, Provider};
+use crate::users::totp::{self, PendingLogin};
+use crate::utils;
+use hyper::header::HeaderValue;
 use hyper::{Body, Method, Request};
 use once_cell::sync::OnceCell;
+use uuid::Uuid;
 
 async fn register(req: Request<Body>) -> Result<User, AppError> {
     let Register {
@@ -31,16 +44,23 @@ async fn register(req: Request<Body>) -> Result<User, AppError> {
 pub async fn query_user(req: Request<Body>) -> Result<User, AppError> {
     use crate::session::authenticate;
 
-    let QueryUser { id } = parse_query(req.uri())?;
+    let QueryUser { id, username } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
 
+    if let Some(username) = username {
+        // Old usernames redirect to the renamed user for a while.
+        return match User::get_by_username(db, &*username).await? {
+            Some(user) => Ok(user),
+            None => User::get_by_old_username(db, &*username).await.or_not_found(),
+        };
+    }
     let id = if let Some(id) = id {
         id
     } else {
         authenticate(&req).await?.user_id
     };
-
-    let mut db = database::get().await?;
-    User::get_by_id(&mut *db, &id).await.or_not_found()
+    User::get_by_id(db, &id).await.or_not_found()
 }
 
 pub async fn get_me(req: Request<Body>) -> Result<Option<GetMe>, AppError> {
@@ -69,20 +89,9 @@ pub async fn get_me(req: Request<Body>) -> Result<Option<GetMe>, AppError> {
     }
 }
 
-pub async fn login(req: Request<Body>) -> Result<Response, AppError> {
-    use crate::session;
+fn session_cookie(token: &str, is_developer: bool) -> HeaderValue {
     use cookie::{CookieBuilder, SameSite};
-    use hyper::header::{HeaderValue, SET_COOKIE};
-    let is_developer = req.headers().contains_key("development");
-    let form: Login = interface::parse_body(req).await?;
-    let mut conn = database::get().await?;
-    let db = &mut *conn;
-    let user = User::login(db, &*form.username, &*form.password)
-        .await
-        .or_no_permission()?;
-    let session = session::start(&user.id).await.map_err(error_unexpected!())?;
-    let token = session::token(&session);
-    let session_cookie = CookieBuilder::new("session", token.clone())
+    let cookie = CookieBuilder::new("session", token)
         .same_site(SameSite::Lax)
         .secure(!is_developer && !debug())
         .http_only(true)
@@ -90,8 +99,22 @@ pub async fn login(req: Request<Body>) -> Result<Response, AppError> {
         .permanent()
         .finish()
         .to_string();
+    HeaderValue::from_str(&*cookie).unwrap()
+}
 
-    let token = if form.with_token { Some(token) } else { None };
+async fn login_response<T: Querist>(
+    db: &mut T,
+    user: User,
+    with_token: bool,
+    is_developer: bool,
+) -> Result<Response, AppError> {
+    use crate::session;
+    use hyper::header::SET_COOKIE;
+    let session = session::start(&user.id).await.map_err(error_unexpected!())?;
+    let token = session::token(&session);
+    let session_cookie = session_cookie(&*token, is_developer);
+
+    let token = if with_token { Some(token) } else { None };
     let my_spaces = Space::get_by_user(db, &user.id).await?;
     let my_channels = Channel::get_by_user(db, user.id).await?;
     let settings = UserExt::get_settings(db, user.id).await?;
@@ -103,17 +126,606 @@ pub async fn login(req: Request<Body>) -> Result<Response, AppError> {
     };
     let mut response = ok_response(LoginReturn { me, token });
     let headers = response.headers_mut();
-    headers.insert(SET_COOKIE, HeaderValue::from_str(&*session_cookie).unwrap());
+    headers.insert(SET_COOKIE, session_cookie);
+    Ok(response)
+}
+
+pub async fn login(req: Request<Body>) -> Result<Response, AppError> {
+    let is_developer = req.headers().contains_key("development");
+    let form: Login = interface::parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let user = User::login(db, &*form.username, &*form.password)
+        .await
+        .or_no_permission()?;
+    if UserTotp::is_enabled(db, &user.id).await? {
+        let challenge = PendingLogin::start(user.id, form.with_token).await?;
+        return Ok(ok_response(LoginChallenge {
+            totp_required: true,
+            challenge,
+        }));
+    }
+    login_response(db, user, form.with_token, is_developer).await
+}
+
+/// Count the attempt against the limit of the user before any code is checked.
+async fn count_totp_attempt(user_id: &Uuid) -> Result<(), AppError> {
+    if !totp::count_attempt(user_id).await? {
+        return Err(AppError::TooManyRequests(
+            "Too many wrong verification codes, please try again later".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Accepts a TOTP code, or an unused recovery code.
+async fn verify_second_factor<T: Querist>(db: &mut T, totp: &UserTotp, code: &str) -> Result<bool, AppError> {
+    count_totp_attempt(&totp.user_id).await?;
+    let now = utils::now_unix_duration().as_secs();
+    let verified = match totp::verify(&*totp.secret, code, now) {
+        Some(step) => UserTotp::use_step(db, &totp.user_id, step as i64).await?,
+        None => {
+            let code = totp::normalize_recovery_code(code);
+            UserTotp::use_recovery_code(db, &totp.user_id, &*code).await?
+        }
+    };
+    if verified {
+        totp::reset_attempts(&totp.user_id).await?;
+    }
+    Ok(verified)
+}
+
+async fn login_totp(req: Request<Body>) -> Result<Response, AppError> {
+    use hyper::header::SET_COOKIE;
+    let is_developer = req.headers().contains_key("development");
+    let challenge_cookie = request_cookie(&req, TOTP_CHALLENGE_COOKIE);
+    let LoginTotp { challenge, code } = parse_body(req).await?;
+    let expired = || AppError::Unauthenticated("The login request has expired".to_string());
+    let challenge = challenge.or(challenge_cookie).ok_or_else(expired)?;
+    let mut pending = PendingLogin::take(&*challenge).await?.ok_or_else(expired)?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let totp = UserTotp::get(db, &pending.user_id)
+        .await?
+        .filter(|totp| totp.enabled)
+        .ok_or_else(expired)?;
+    if !verify_second_factor(db, &totp, &*code).await? {
+        pending.attempts += 1;
+        if pending.attempts < totp::MAX_ATTEMPTS {
+            pending.save(&*challenge).await?;
+        }
+        return Err(AppError::Unauthenticated("Invalid verification code".to_string()));
+    }
+    let user = User::get_by_id(db, &pending.user_id).await.or_not_found()?;
+    let mut response = login_response(db, user, pending.with_token, is_developer).await?;
+    response
+        .headers_mut()
+        .append(SET_COOKIE, short_cookie(TOTP_CHALLENGE_COOKIE, None, is_developer));
     Ok(response)
 }
 
+async fn totp_status(req: Request<Body>) -> Result<TotpStatus, AppError> {
+    use crate::session::authenticate;
+    let session = authenticate(&req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let enabled = UserTotp::is_enabled(db, &session.user_id).await?;
+    let recovery_codes = UserTotp::count_recovery_codes(db, &session.user_id).await?;
+    Ok(TotpStatus {
+        enabled,
+        recovery_codes,
+    })
+}
+
+async fn totp_enroll(req: Request<Body>) -> Result<TotpEnroll, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let user = User::get_by_id(db, &session.user_id).await.or_not_found()?;
+    let secret = totp::generate_secret();
+    UserTotp::set_secret(db, &user.id, &*secret)
+        .await?
+        .ok_or_else(|| AppError::Conflict("user_totp".to_string()))?;
+    Ok(TotpEnroll {
+        secret: totp::base32_encode(&*secret),
+        uri: totp::otpauth_uri("Boluo", &*user.username, &*secret),
+    })
+}
+
+async fn reset_recovery_codes<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<Vec<String>, AppError> {
+    let codes = totp::generate_recovery_codes();
+    let normalized: Vec<String> = codes.iter().map(|code| totp::normalize_recovery_code(code)).collect();
+    UserTotp::set_recovery_codes(db, user_id, &*normalized).await?;
+    Ok(codes)
+}
+
+/// Enable the two-factor authentication once the authenticator app produces a valid code.
+///
+/// Returns the recovery codes, which are only shown this time.
+async fn totp_confirm(req: Request<Body>) -> Result<Vec<String>, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let TotpCode { code } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let totp = UserTotp::get(db, &session.user_id).await.or_not_found()?;
+    if totp.enabled {
+        return Err(AppError::Conflict("user_totp".to_string()));
+    }
+    let now = utils::now_unix_duration().as_secs();
+    let step = totp::verify(&*totp.secret, &*code, now).ok_or(ValidationFailed("Invalid verification code"))?;
+    UserTotp::enable(db, &session.user_id, step as i64).await?;
+    let codes = reset_recovery_codes(db, &session.user_id).await?;
+    trans.commit().await?;
+    log::info!("The user {} enabled two-factor authentication.", session.user_id);
+    Ok(codes)
+}
+
+async fn totp_recovery_codes(req: Request<Body>) -> Result<Vec<String>, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let TotpCode { code } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let totp = UserTotp::get(db, &session.user_id)
+        .await?
+        .filter(|totp| totp.enabled)
+        .ok_or(AppError::NotFound("user_totp"))?;
+    count_totp_attempt(&totp.user_id).await?;
+    let now = utils::now_unix_duration().as_secs();
+    let verified = match totp::verify(&*totp.secret, &*code, now) {
+        Some(step) => UserTotp::use_step(db, &totp.user_id, step as i64).await?,
+        None => false,
+    };
+    if !verified {
+        return Err(AppError::NoPermission("Invalid verification code".to_string()));
+    }
+    totp::reset_attempts(&totp.user_id).await?;
+    let codes = reset_recovery_codes(db, &session.user_id).await?;
+    trans.commit().await?;
+    Ok(codes)
+}
+
+async fn totp_disable(req: Request<Body>) -> Result<bool, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let TotpCode { code } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let totp = UserTotp::get(db, &session.user_id).await.or_not_found()?;
+    // An unconfirmed enrollment can be dropped freely.
+    if totp.enabled && !verify_second_factor(db, &totp, &*code).await? {
+        return Err(AppError::NoPermission("Invalid verification code".to_string()));
+    }
+    UserTotp::remove(db, &session.user_id).await?;
+    trans.commit().await?;
+    log::info!("The user {} disabled two-factor authentication.", session.user_id);
+    Ok(true)
+}
+
+/// Ties the OpenID Connect login to the browser which started it.
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+/// Carries the challenge from the OpenID Connect callback to `/login_totp`.
+const TOTP_CHALLENGE_COOKIE: &str = "totp_challenge";
+
+/// A cookie which lives through a login, `None` clears it.
+fn short_cookie(name: &str, value: Option<&str>, is_developer: bool) -> HeaderValue {
+    use cookie::{CookieBuilder, SameSite};
+    let max_age = if value.is_some() { 60 * 10 } else { 0 };
+    let cookie = CookieBuilder::new(name, value.unwrap_or_default())
+        .same_site(SameSite::Lax)
+        .secure(!is_developer && !debug())
+        .http_only(true)
+        .path("/api/users/")
+        .max_age(time::Duration::seconds(max_age))
+        .finish()
+        .to_string();
+    HeaderValue::from_str(&*cookie).unwrap()
+}
+
+fn request_cookie(req: &Request<Body>, name: &str) -> Option<String> {
+    use cookie::Cookie;
+    use hyper::header::COOKIE;
+    req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| Cookie::parse(pair.trim()).ok())
+        .find(|cookie| cookie.name() == name)
+        .map(|cookie| cookie.value().to_string())
+}
+
+fn oidc_provider(name: &str) -> Result<&'static Provider, AppError> {
+    oidc::providers()
+        .get(name)
+        .ok_or(AppError::NotFound("OpenID Connect provider"))
+}
+
+async fn oidc_providers(_req: Request<Body>) -> Result<Vec<&'static str>, AppError> {
+    let mut names: Vec<&'static str> = oidc::providers().keys().map(String::as_str).collect();
+    names.sort_unstable();
+    Ok(names)
+}
+
+pub async fn oidc_login(req: Request<Body>) -> Result<Response, AppError> {
+    use crate::session::authenticate;
+    use hyper::header::{LOCATION, SET_COOKIE};
+    use hyper::StatusCode;
+
+    let is_developer = req.headers().contains_key("development");
+    let OidcLogin { provider, redirect } = parse_query(req.uri())?;
+    let provider = oidc_provider(&*provider)?;
+    let redirect = redirect.unwrap_or_else(|| "/".to_string());
+    if !redirect.starts_with('/') || redirect.starts_with("//") {
+        return Err(AppError::BadRequest("The redirect must be a local path".to_string()));
+    }
+    // A signed-in user links the identity to the current account.
+    let link_user_id = authenticate(&req).await.ok().map(|session| session.user_id);
+    let discovery = oidc::discover(provider).await.map_err(AppError::Unexpected)?;
+    let state = utils::random_token(32);
+    let nonce = utils::random_token(16);
+    let url = oidc::authorize_url(&discovery, provider, &*state, &*nonce);
+    AuthorizeState {
+        provider: provider.name.clone(),
+        nonce,
+        redirect,
+        link_user_id,
+    }
+    .save(&*state)
+    .await?;
+    hyper::Response::builder()
+        .status(StatusCode::FOUND)
+        .header(LOCATION, url)
+        .header(SET_COOKIE, short_cookie(OIDC_STATE_COOKIE, Some(&*state), is_developer))
+        .body(Body::empty())
+        .map_err(error_unexpected!())
+}
+
+async fn oidc_user<T: Querist>(
+    db: &mut T,
+    provider: &Provider,
+    claims: &Claims,
+    link_user_id: Option<Uuid>,
+) -> Result<User, AppError> {
+    if let Some(identity) = UserIdentity::get(db, &*claims.iss, &*claims.sub).await? {
+        if link_user_id.map_or(false, |user_id| user_id != identity.user_id) {
+            return Err(AppError::Conflict("user_identities".to_string()));
+        }
+        return User::get_by_id(db, &identity.user_id)
+            .await?
+            .ok_or_else(|| AppError::NoPermission("The account has been deactivated".to_string()));
+    }
+    let email = claims.verified_email();
+    let user = if let Some(user_id) = link_user_id {
+        User::get_by_id(db, &user_id).await.or_not_found()?
+    } else if let Some(email) = email {
+        if let Some(user) = User::get_by_email(db, email).await? {
+            user
+        } else {
+            let mut username = claims.suggest_username();
+            while User::is_username_taken(db, &*username).await? {
+                username = format!(
+                    "{}_{}",
+                    claims.suggest_username(),
+                    &utils::id().to_simple().to_string()[..6]
+                );
+            }
+            let nickname: String = claims
+                .name
+                .as_deref()
+                .filter(|name| name.trim().chars().count() >= 2)
+                .unwrap_or(&*username)
+                .chars()
+                .take(32)
+                .collect();
+            let password = utils::random_token(32);
+            let user = User::register(db, email, &*username, &*nickname, &*password).await?;
+            User::mark_password_unset(db, &user.id).await?;
+            log::info!(
+                "{} ({}) was registered by {}.",
+                user.username,
+                user.email,
+                provider.name
+            );
+            user
+        }
+    } else {
+        return Err(AppError::Unauthenticated(
+            "The identity provider did not verify the e-mail address".to_string(),
+        ));
+    };
+    UserIdentity::link(
+        db,
+        &user.id,
+        &*provider.name,
+        &*claims.iss,
+        &*claims.sub,
+        email.unwrap_or(""),
+    )
+    .await?;
+    Ok(user)
+}
+
+pub async fn oidc_callback(req: Request<Body>) -> Result<Response, AppError> {
+    use crate::session;
+    use hyper::header::{LOCATION, SET_COOKIE};
+    use hyper::StatusCode;
+
+    let is_developer = req.headers().contains_key("development");
+    let OidcCallback { state, code, error } = parse_query(req.uri())?;
+    // Reject callbacks started in another browser, which would sign the victim in to the attacker's account.
+    if request_cookie(&req, OIDC_STATE_COOKIE).as_ref() != Some(&state) {
+        return Err(AppError::Unauthenticated(
+            "The login request was not started here".to_string(),
+        ));
+    }
+    let AuthorizeState {
+        provider,
+        nonce,
+        redirect,
+        link_user_id,
+    } = AuthorizeState::take(&*state)
+        .await?
+        .ok_or_else(|| AppError::Unauthenticated("The login request has expired".to_string()))?;
+    if let Some(error) = error {
+        return Err(AppError::Unauthenticated(format!(
+            "The identity provider refused: {}",
+            error
+        )));
+    }
+    let code = code.ok_or_else(|| AppError::BadRequest("Missing the authorization code".to_string()))?;
+    let provider = oidc_provider(&*provider)?;
+    let claims = oidc::authenticate(provider, &*code, &*nonce).await.map_err(|e| {
+        log::warn!("Failed to authenticate with {}: {}", provider.name, e);
+        AppError::Unauthenticated("Failed to verify the identity".to_string())
+    })?;
+
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let user = oidc_user(&mut trans, provider, &claims, link_user_id).await?;
+    trans.commit().await?;
+
+    let response = hyper::Response::builder()
+        .status(StatusCode::FOUND)
+        .header(SET_COOKIE, short_cookie(OIDC_STATE_COOKIE, None, is_developer));
+    if UserTotp::is_enabled(&mut *conn, &user.id).await? {
+        // The page redirected to finishes the login with `/login_totp`, which reads the challenge from the cookie.
+        let challenge = PendingLogin::start(user.id, false).await?;
+        let separator = if redirect.contains('?') { '&' } else { '?' };
+        let location = format!("{}{}totpRequired=true", redirect, separator);
+        return response
+            .header(
+                SET_COOKIE,
+                short_cookie(TOTP_CHALLENGE_COOKIE, Some(&*challenge), is_developer),
+            )
+            .header(LOCATION, location)
+            .body(Body::empty())
+            .map_err(error_unexpected!());
+    }
+    let session = session::start(&user.id).await.map_err(error_unexpected!())?;
+    let token = session::token(&session);
+    response
+        .header(LOCATION, redirect)
+        .header(SET_COOKIE, session_cookie(&*token, is_developer))
+        .body(Body::empty())
+        .map_err(error_unexpected!())
+}
+
+async fn identities(req: Request<Body>) -> Result<Vec<UserIdentity>, AppError> {
+    use crate::session::authenticate;
+    let session = authenticate(&req).await?;
+    let mut db = database::get().await?;
+    UserIdentity::get_by_user(&mut *db, &session.user_id)
+        .await
+        .map_err(Into::into)
+}
+
+async fn unlink_identity(req: Request<Body>) -> Result<bool, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let user = User::get_by_id(db, &session.user_id).await.or_not_found()?;
+    let identities = UserIdentity::get_by_user(db, &user.id).await?;
+    // Without a password, the last identity is the only way to sign in.
+    if !user.password_set && identities.iter().all(|identity| identity.id == id) {
+        return Err(AppError::BadRequest(
+            "The last identity of an account without password can't be unlinked".to_string(),
+        ));
+    }
+    let removed = UserIdentity::unlink(db, &user.id, &id).await?;
+    Ok(removed > 0)
+}
+
+fn write_archive(files: Vec<(&'static str, Vec<u8>)>) -> zip::result::ZipResult<Vec<u8>> {
+    use std::io::{Cursor, Write};
+    use zip::write::{FileOptions, ZipWriter};
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, content) in files {
+        archive.start_file(name, options)?;
+        archive.write_all(&*content)?;
+    }
+    Ok(archive.finish()?.into_inner())
+}
+
+/// Bundle the personal data of the user into a zip archive.
+async fn export_data(req: Request<Body>) -> Result<Response, AppError> {
+    use crate::messages::Message;
+    use crate::session::authenticate;
+    use hyper::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+
+    let session = authenticate(&req).await?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let user = User::get_by_id(db, &session.user_id).await.or_not_found()?;
+    let profile = serde_json::json!({
+        "user": &user,
+        "email": &user.email,
+        "settings": UserExt::get_settings(db, user.id).await?,
+        "identities": UserIdentity::get_by_user(db, &user.id).await?,
+        "usernameHistory": UsernameHistory::get_by_user(db, &user.id).await?,
+    });
+    let spaces = Space::get_by_user(db, &user.id).await?;
+    let channels = Channel::get_by_user(db, user.id).await?;
+    let messages = Message::get_by_sender(db, &user.id).await?;
+    let media = Media::get_by_uploader(db, &user.id).await?;
+    fn to_json<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, AppError> {
+        serde_json::to_vec_pretty(value).map_err(AppError::Serialize)
+    }
+    let files = vec![
+        ("profile.json", to_json(&profile)?),
+        ("spaces.json", to_json(&spaces)?),
+        ("channels.json", to_json(&channels)?),
+        ("messages.json", to_json(&messages)?),
+        ("media.json", to_json(&media)?),
+    ];
+    let archive = tokio::task::spawn_blocking(move || write_archive(files))
+        .await
+        .map_err(error_unexpected!())?
+        .map_err(error_unexpected!())?;
+    log::info!("The user {} exported the personal data.", user.id);
+    hyper::Response::builder()
+        .header(CONTENT_TYPE, "application/zip")
+        .header(CONTENT_DISPOSITION, "attachment; filename=\"boluo-export.zip\"")
+        .body(Body::from(archive))
+        .map_err(error_unexpected!())
+}
+
+const DELETED_NAME: &str = "Deleted User";
+
+/// A session started this recently can delete the account without the password.
+const FRESH_SESSION: std::time::Duration = std::time::Duration::from_secs(60 * 10);
+
+async fn delete_account(req: Request<Body>) -> Result<bool, AppError> {
+    use crate::csrf::authenticate;
+    use crate::events::Event;
+    use crate::messages::Message;
+    use crate::session::revoke_user_sessions;
+    use crate::spaces::SpaceMember;
+
+    let session = authenticate(&req).await?;
+    let DeleteAccount {
+        password,
+        code,
+        messages,
+        transfer_spaces,
+        delete_spaces,
+    } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let user = User::get_by_id(db, &session.user_id).await.or_not_found()?;
+    if let Some(password) = password {
+        User::login(db, &*user.username, &*password).await.or_no_permission()?;
+    } else if !session.age().map_or(false, |age| age < FRESH_SESSION) {
+        // Users registered through OpenID Connect have no password, they sign in again instead.
+        return Err(AppError::NoPermission(
+            "Please sign in again before deleting the account".to_string(),
+        ));
+    }
+    if let Some(totp) = UserTotp::get(db, &user.id).await?.filter(|totp| totp.enabled) {
+        let code = code.unwrap_or_default();
+        if !verify_second_factor(db, &totp, &*code).await? {
+            return Err(AppError::NoPermission("Invalid verification code".to_string()));
+        }
+    }
+
+    for space in Space::user_owned(db, &user.id).await? {
+        if let Some(new_owner) = transfer_spaces.get(&space.id).filter(|id| **id != user.id) {
+            SpaceMember::get(db, new_owner, &space.id)
+                .await?
+                .ok_or_else(|| AppError::BadRequest(format!("The new owner of \"{}\" is not a member", space.name)))?;
+            Space::set_owner(db, &space.id, new_owner).await?;
+            SpaceMember::set_admin(db, new_owner, &space.id, true).await?;
+            log::info!("The space {} was transferred to {}.", space.id, new_owner);
+        } else if delete_spaces {
+            Space::delete(db, &space.id).await?;
+            log::info!("A space ({}) was deleted", space.id);
+        } else {
+            return Err(AppError::BadRequest(format!(
+                "The space \"{}\" has to be transferred or deleted",
+                space.name
+            )));
+        }
+    }
+    let mut spaces = Vec::new();
+    let mut channels = Vec::new();
+    for joined in Space::get_by_user(db, &user.id).await? {
+        channels.extend(SpaceMember::remove_user(db, &user.id, &joined.space.id).await?);
+        spaces.push(joined.space.id);
+    }
+    match messages {
+        DeletedMessages::Keep => {}
+        DeletedMessages::Anonymize => {
+            Message::anonymize_by_sender(db, &user.id, DELETED_NAME).await?;
+        }
+        DeletedMessages::Delete => {
+            Message::delete_by_sender(db, &user.id).await?;
+        }
+    }
+    User::delete_account(db, &user.id, DELETED_NAME).await?;
+    trans.commit().await?;
+
+    revoke_user_sessions(&user.id).await?;
+    for space_id in spaces {
+        Event::space_updated(space_id);
+    }
+    for channel_id in channels {
+        Event::push_members(channel_id);
+    }
+    log::info!("The user {} ({}) deleted the account.", user.username, user.id);
+    Ok(true)
+}
+
+async fn block(req: Request<Body>) -> Result<UserBlock, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    if id == session.user_id {
+        return Err(AppError::BadRequest("You can't block yourself".to_string()));
+    }
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    User::get_by_id(db, &id).await.or_not_found()?;
+    let block = UserBlock::block(db, &session.user_id, &id).await?;
+    blocks_changed(session.user_id);
+    Ok(block)
+}
+
+async fn unblock(req: Request<Body>) -> Result<bool, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut db = database::get().await?;
+    let removed = UserBlock::unblock(&mut *db, &session.user_id, &id).await?;
+    blocks_changed(session.user_id);
+    Ok(removed > 0)
+}
+
+async fn blocked(req: Request<Body>) -> Result<Vec<User>, AppError> {
+    use crate::session::authenticate;
+    let session = authenticate(&req).await?;
+    let mut db = database::get().await?;
+    UserBlock::get_blocked_users(&mut *db, &session.user_id)
+        .await
+        .map_err(Into::into)
+}
+
 pub async fn logout(req: Request<Body>) -> Result<Response, AppError> {
     use crate::session::authenticate;
     use cookie::CookieBuilder;
     use hyper::header::{HeaderValue, SET_COOKIE};
 
     if let Ok(session) = authenticate(&req).await {
-        revoke_session(&session.id).await?;
+        revoke_session(&session).await?;
     }
     let mut response = ok_response(true);
     let header = response.headers_mut();
@@ -132,6 +744,21 @@ pub async fn logout(req: Request<Body>) -> Result<Response, AppError> {
     Ok(response)
 }
 
+async fn change_username(req: Request<Body>) -> Result<User, AppError> {
+    use crate::csrf::authenticate;
+    let session = authenticate(&req).await?;
+    let ChangeUsername { username } = parse_body(req).await?;
+    let mut conn = database::get().await?;
+    let mut trans = conn.transaction().await?;
+    let db = &mut trans;
+    let user = User::get_by_id(db, &session.user_id).await.or_not_found()?;
+    let old_username = user.username.clone();
+    let user = User::change_username(db, user, &*username).await?;
+    trans.commit().await?;
+    log::info!("{} changed the username to {}.", old_username, user.username);
+    Ok(user)
+}
+
 pub async fn edit(req: Request<Body>) -> Result<User, AppError> {
     use crate::csrf::authenticate;
     let session = authenticate(&req).await?;
@@ -142,13 +769,8 @@ pub async fn edit(req: Request<Body>) -> Result<User, AppError> {
         .map_err(Into::into)
 }
 
-pub fn is_image(mime: &Option<String>) -> bool {
-    if let Some(mime) = mime {
-        if mime == r"image/png" || mime == r"image/gif" || mime == r"image/jpeg" {
-            return true;
-        }
-    }
-    false
+pub fn is_image(mime: &str) -> bool {
+    mime == r"image/png" || mime == r"image/gif" || mime == r"image/jpeg"
 }
 
 pub async fn update_settings(req: Request<Body>) -> Result<serde_json::Value, AppError> {
@@ -165,10 +787,14 @@ pub async fn edit_avatar(req: Request<Body>) -> Result<User, AppError> {
     use crate::csrf::authenticate;
     let session = authenticate(&req).await?;
     let params = upload_params(req.uri())?;
-    if !is_image(&params.mime_type) {
+    let media = upload(req, params, Some(1024 * 1024), &session.user_id).await?;
+    // The type is sniffed from the content.
+    if !is_image(&*media.mime_type) {
+        if !media.duplicate {
+            storage().remove(&*media.filename).await.ok();
+        }
         return Err(ValidationFailed("Incorrect File Format").into());
     }
-    let media = upload(req, params, 1024 * 1024).await?;
     let mut db = database::get().await?;
     let media = media.create(&mut *db, session.user_id, "avatar").await?;
     User::edit(&mut *db, &session.user_id, None, None, Some(media.id))
@@ -186,22 +812,40 @@ pub async fn check_email_exists(req: Request<Body>) -> Result<bool, AppError> {
 pub async fn check_username_exists(req: Request<Body>) -> Result<bool, AppError> {
     let CheckUsernameExists { username } = parse_query(req.uri())?;
     let mut db = database::get().await?;
-    let user = User::get_by_username(&mut *db, &*username).await?;
-    Ok(user.is_some())
+    User::is_username_taken(&mut *db, username.trim())
+        .await
+        .map_err(Into::into)
 }
 
 pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError> {
     match (path, req.method().clone()) {
         ("/login", Method::POST) => login(req).await,
+        ("/login_totp", Method::POST) => login_totp(req).await,
         ("/register", Method::POST) => register(req).await.map(ok_response),
         ("/logout", _) => logout(req).await,
         ("/query", Method::GET) => query_user(req).await.map(ok_response),
         ("/get_me", Method::GET) => get_me(req).await.map(ok_response),
         ("/edit", Method::POST) => edit(req).await.map(ok_response),
+        ("/change_username", Method::POST) => change_username(req).await.map(ok_response),
         ("/edit_avatar", Method::POST) => edit_avatar(req).await.map(ok_response),
         ("/update_settings", Method::POST) => update_settings(req).await.map(ok_response),
         ("/check_username", Method::GET) => check_username_exists(req).await.map(ok_response),
         ("/check_email", Method::GET) => check_email_exists(req).await.map(ok_response),
+        ("/oidc_providers", Method::GET) => oidc_providers(req).await.map(ok_response),
+        ("/oidc_login", Method::GET) => oidc_login(req).await,
+        ("/oidc_callback", Method::GET) => oidc_callback(req).await,
+        ("/identities", Method::GET) => identities(req).await.map(ok_response),
+        ("/unlink_identity", Method::POST) => unlink_identity(req).await.map(ok_response),
+        ("/totp", Method::GET) => totp_status(req).await.map(ok_response),
+        ("/totp_enroll", Method::POST) => totp_enroll(req).await.map(ok_response),
+        ("/totp_confirm", Method::POST) => totp_confirm(req).await.map(ok_response),
+        ("/totp_recovery_codes", Method::POST) => totp_recovery_codes(req).await.map(ok_response),
+        ("/totp_disable", Method::POST) => totp_disable(req).await.map(ok_response),
+        ("/export", Method::GET) => export_data(req).await,
+        ("/delete_account", Method::POST) => delete_account(req).await.map(ok_response),
+        ("/block", Method::POST) => block(req).await.map(ok_response),
+        ("/unblock", Method::POST) => unblock(req).await.map(ok_response),
+        ("/blocked", Method::GET) => blocked(req).await.map(ok_response),
         _ => missing(),
     }
 }
@@ -1,11 +1,21 @@
+use once_cell::sync::OnceCell;
 use postgres_types::FromSql;
 use serde::Serialize;
 use uuid::Uuid;
 
 use crate::database::Querist;
-use crate::error::{DbError, ModelError};
+use crate::error::{DbError, ModelError, ValidationFailed};
 use crate::utils::{inner_result_map, merge_blank};
 
+/// How long a user has to wait between two username changes.
+pub const USERNAME_COOLDOWN_DAYS: i64 = 30;
+/// How long an old username stays reserved for the user who changed it.
+pub const USERNAME_RESERVED_DAYS: i64 = 90;
+
+fn days_ago(days: i64) -> chrono::naive::NaiveDateTime {
+    chrono::Utc::now().naive_utc() - chrono::Duration::days(days)
+}
+
 #[derive(Debug, Serialize, FromSql, Clone)]
 #[serde(rename_all = "camelCase")]
 #[postgres(name = "users")]
@@ -23,6 +33,8 @@ pub struct User {
     #[serde(skip)]
     pub deactivated: bool,
     pub avatar_id: Option<Uuid>,
+    #[serde(skip)]
+    pub password_set: bool,
 }
 
 impl User {
@@ -51,6 +63,9 @@ impl User {
         DISPLAY_NAME.run(&nickname)?;
         NAME.run(&username)?;
         PASSWORD.run(&password)?;
+        if User::is_username_taken(db, username).await? {
+            return Err(ModelError::Conflict("users".to_string()));
+        }
 
         let row = db
             .query_exactly_one(
@@ -107,10 +122,84 @@ impl User {
         User::get(db, None, None, Some(username)).await
     }
 
+    /// Find the user who used to have this username, while it is still reserved.
+    pub async fn get_by_old_username<T: Querist>(db: &mut T, username: &str) -> Result<Option<User>, DbError> {
+        let reserved_since = days_ago(USERNAME_RESERVED_DAYS);
+        let result = db
+            .query_one(
+                include_str!("sql/get_by_old_username.sql"),
+                &[&username, &reserved_since],
+            )
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    async fn username_taken<T: Querist>(db: &mut T, username: &str, except: Option<&Uuid>) -> Result<bool, DbError> {
+        let reserved_since = days_ago(USERNAME_RESERVED_DAYS);
+        let row = db
+            .query_exactly_one(
+                include_str!("sql/username_taken.sql"),
+                &[&username, &except, &reserved_since],
+            )
+            .await?;
+        row.try_get(0)
+    }
+
+    /// Unlike `get_by_username`, deactivated users and reserved old usernames are taken into account.
+    pub async fn is_username_taken<T: Querist>(db: &mut T, username: &str) -> Result<bool, DbError> {
+        User::username_taken(db, username, None).await
+    }
+
+    pub async fn change_username<T: Querist>(db: &mut T, user: User, username: &str) -> Result<User, ModelError> {
+        use crate::validators::NAME;
+        let username = username.trim();
+        NAME.run(username)?;
+        if user.username == username {
+            return Ok(user);
+        }
+        let id = &user.id;
+        let cooldown_since = days_ago(USERNAME_COOLDOWN_DAYS);
+        let recently_changed = UsernameHistory::get_by_user(db, id)
+            .await?
+            .into_iter()
+            .any(|history| history.changed > cooldown_since);
+        if recently_changed {
+            static MESSAGE: OnceCell<String> = OnceCell::new();
+            let message = MESSAGE.get_or_init(|| {
+                format!(
+                    "The username can only be changed once every {} days.",
+                    USERNAME_COOLDOWN_DAYS
+                )
+            });
+            return Err(ValidationFailed(message).into());
+        }
+        // Users are allowed to take their own old usernames back.
+        if User::username_taken(db, username, Some(id)).await? {
+            return Err(ModelError::Conflict("users".to_string()));
+        }
+        let row = db
+            .query_exactly_one(include_str!("sql/change_username.sql"), &[id, &username])
+            .await?;
+        row.try_get(0).map_err(Into::into)
+    }
+
+    /// For users registered through OpenID Connect, who can't sign in with the password.
+    pub async fn mark_password_unset<T: Querist>(db: &mut T, id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/mark_password_unset.sql"), &[id]).await
+    }
+
     pub async fn deactivated<T: Querist>(db: &mut T, id: &Uuid) -> Result<u64, DbError> {
         db.execute(include_str!("sql/deactivated.sql"), &[id]).await
     }
 
+    /// Deactivate the user and erase the personal data, the row is kept for the references.
+    pub async fn delete_account<T: Querist>(db: &mut T, id: &Uuid, nickname: &str) -> Result<User, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/delete_account.sql"), &[id, &nickname])
+            .await?;
+        row.try_get(0)
+    }
+
     pub async fn edit<T: Querist>(
         db: &mut T,
         id: &Uuid,
@@ -134,6 +223,26 @@ impl User {
     }
 }
 
+#[derive(Debug, Serialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "username_history")]
+pub struct UsernameHistory {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    #[serde(with = "crate::date_format")]
+    pub changed: chrono::naive::NaiveDateTime,
+}
+
+impl UsernameHistory {
+    pub async fn get_by_user<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<Vec<UsernameHistory>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_username_history.sql"), &[user_id])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+}
+
 #[derive(Debug, Serialize, FromSql, Clone)]
 #[serde(rename_all = "camelCase")]
 #[postgres(name = "users_extension")]
@@ -159,8 +268,185 @@ impl UserExt {
         let row = db
             .query_exactly_one(include_str!("sql/set_settings.sql"), &[&user_id, &settings])
             .await?;
+        row.try_get(0)
+    }
+}
+
+#[derive(Debug, Serialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "user_identities")]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    pub issuer: String,
+    #[serde(skip)]
+    pub subject: String,
+    pub email: String,
+    #[serde(with = "crate::date_format")]
+    pub created: chrono::naive::NaiveDateTime,
+}
+
+impl UserIdentity {
+    pub async fn get<T: Querist>(db: &mut T, issuer: &str, subject: &str) -> Result<Option<UserIdentity>, DbError> {
+        let result = db
+            .query_one(include_str!("sql/get_identity.sql"), &[&issuer, &subject])
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn link<T: Querist>(
+        db: &mut T,
+        user_id: &Uuid,
+        provider: &str,
+        issuer: &str,
+        subject: &str,
+        email: &str,
+    ) -> Result<UserIdentity, ModelError> {
+        let row = db
+            .query_exactly_one(
+                include_str!("sql/link_identity.sql"),
+                &[user_id, &provider, &issuer, &subject, &email],
+            )
+            .await?;
         row.try_get(0).map_err(Into::into)
     }
+
+    pub async fn get_by_user<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<Vec<UserIdentity>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_identities_by_user.sql"), &[user_id])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn unlink<T: Querist>(db: &mut T, user_id: &Uuid, id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/unlink_identity.sql"), &[user_id, id])
+            .await
+    }
+}
+
+#[derive(Debug, FromSql, Clone)]
+#[postgres(name = "user_totp")]
+pub struct UserTotp {
+    pub user_id: Uuid,
+    pub secret: Vec<u8>,
+    pub enabled: bool,
+    pub last_step: i64,
+    pub created: chrono::naive::NaiveDateTime,
+}
+
+impl UserTotp {
+    pub async fn get<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<Option<UserTotp>, DbError> {
+        let result = db.query_one(include_str!("sql/get_totp.sql"), &[user_id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn is_enabled<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<bool, DbError> {
+        let totp = UserTotp::get(db, user_id).await?;
+        Ok(totp.map_or(false, |totp| totp.enabled))
+    }
+
+    /// Returns `None` if the two-factor authentication has been enabled.
+    pub async fn set_secret<T: Querist>(
+        db: &mut T,
+        user_id: &Uuid,
+        secret: &[u8],
+    ) -> Result<Option<UserTotp>, DbError> {
+        let result = db
+            .query_one(include_str!("sql/set_totp_secret.sql"), &[user_id, &secret])
+            .await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn enable<T: Querist>(db: &mut T, user_id: &Uuid, step: i64) -> Result<bool, DbError> {
+        let updated = db
+            .execute(include_str!("sql/enable_totp.sql"), &[user_id, &step])
+            .await?;
+        Ok(updated > 0)
+    }
+
+    /// Mark the time step as used, returns `false` if the step (or a later one) has been used.
+    pub async fn use_step<T: Querist>(db: &mut T, user_id: &Uuid, step: i64) -> Result<bool, DbError> {
+        let updated = db
+            .execute(include_str!("sql/use_totp_step.sql"), &[user_id, &step])
+            .await?;
+        Ok(updated > 0)
+    }
+
+    pub async fn remove<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/remove_totp.sql"), &[user_id]).await
+    }
+
+    /// Replace all recovery codes of the user.
+    pub async fn set_recovery_codes<T: Querist>(db: &mut T, user_id: &Uuid, codes: &[String]) -> Result<(), DbError> {
+        db.execute(include_str!("sql/set_recovery_codes.sql"), &[user_id, &codes])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn use_recovery_code<T: Querist>(db: &mut T, user_id: &Uuid, code: &str) -> Result<bool, DbError> {
+        let updated = db
+            .execute(include_str!("sql/use_recovery_code.sql"), &[user_id, &code])
+            .await?;
+        Ok(updated > 0)
+    }
+
+    pub async fn count_recovery_codes<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<i64, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/count_recovery_codes.sql"), &[user_id])
+            .await?;
+        row.try_get(0)
+    }
+}
+
+#[derive(Debug, Serialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "user_blocks")]
+pub struct UserBlock {
+    pub user_id: Uuid,
+    pub blocked_id: Uuid,
+    #[serde(with = "crate::date_format")]
+    pub created: chrono::naive::NaiveDateTime,
+}
+
+impl UserBlock {
+    pub async fn block<T: Querist>(db: &mut T, user_id: &Uuid, blocked_id: &Uuid) -> Result<UserBlock, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/block_user.sql"), &[user_id, blocked_id])
+            .await?;
+        row.try_get(0)
+    }
+
+    pub async fn unblock<T: Querist>(db: &mut T, user_id: &Uuid, blocked_id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/unblock_user.sql"), &[user_id, blocked_id])
+            .await
+    }
+
+    pub async fn get_blocked_users<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<Vec<User>, DbError> {
+        let rows = db.query(include_str!("sql/get_blocked_users.sql"), &[user_id]).await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn get_blocked_ids<T: Querist>(db: &mut T, user_id: &Uuid) -> Result<Vec<Uuid>, DbError> {
+        let rows = db.query(include_str!("sql/get_blocked_ids.sql"), &[user_id]).await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn is_blocked<T: Querist>(db: &mut T, user_id: &Uuid, blocked_id: &Uuid) -> Result<bool, DbError> {
+        UserBlock::is_blocked_by_any(db, blocked_id, &[*user_id]).await
+    }
+
+    /// Whether any of the users has blocked `blocked_id`.
+    pub async fn is_blocked_by_any<T: Querist>(
+        db: &mut T,
+        blocked_id: &Uuid,
+        user_ids: &[Uuid],
+    ) -> Result<bool, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/is_blocked_by_any.sql"), &[blocked_id, &user_ids])
+            .await?;
+        row.try_get(0)
+    }
 }
 
 #[tokio::test]
@@ -190,6 +476,9 @@ async fn user_test() -> Result<(), crate::error::AppError> {
         "".to_string(),
         0,
         "",
+        None,
+        None,
+        None,
     )
     .await?;
     let new_nickname = "动感超人";
@@ -205,6 +494,42 @@ async fn user_test() -> Result<(), crate::error::AppError> {
     assert_eq!(user_altered.nickname, new_nickname);
     assert_eq!(user_altered.bio, bio);
     assert_eq!(user_altered.avatar_id, Some(avatar.id));
+    let shared = Media::create(
+        db,
+        "text/plain",
+        user.id,
+        "avatar.jpg",
+        "avatar.jpg",
+        "".to_string(),
+        42,
+        "",
+        None,
+        None,
+        None,
+    )
+    .await?;
+    assert_eq!(Media::user_usage(db, &user.id).await?, 42);
+    Media::delete(db, &shared.id).await?.unwrap();
+    let orphan = Media::create(
+        db,
+        "",
+        user.id,
+        "orphan",
+        "orphan",
+        "".to_string(),
+        0,
+        "",
+        None,
+        None,
+        None,
+    )
+    .await?;
+    let tomorrow = chrono::Utc::now().naive_utc() + chrono::Duration::days(1);
+    let orphans = Media::delete_orphans(db, &tomorrow, 1024).await?;
+    assert!(orphans.iter().any(|media| media.id == orphan.id));
+    assert!(orphans.iter().all(|media| media.id != avatar.id));
+    assert_eq!(Media::count_by_filename(db, "avatar.jpg").await?, 1);
+    assert!(!Media::is_space_admin(db, &avatar.id, &user.id).await?);
     let settings = UserExt::update_settings(db, user.id, serde_json::json!({"madoka": "homura"})).await?;
     assert_eq!(
         *settings.get("madoka").unwrap(),
@@ -217,6 +542,64 @@ async fn user_test() -> Result<(), crate::error::AppError> {
         serde_json::Value::String("madoka".to_string())
     );
 
+    let issuer = "https://sso.mythal.net";
+    let identity = UserIdentity::link(db, &user.id, "mythal", issuer, "homura", email).await?;
+    let linked = UserIdentity::get(db, issuer, "homura").await?.unwrap();
+    assert_eq!(linked.user_id, user.id);
+    assert_eq!(UserIdentity::get_by_user(db, &user.id).await?.len(), 1);
+    assert!(User::is_username_taken(db, username).await?);
+    UserIdentity::unlink(db, &user.id, &identity.id).await?;
+    assert!(UserIdentity::get(db, issuer, "homura").await?.is_none());
+
+    UserTotp::set_secret(db, &user.id, b"12345678901234567890")
+        .await?
+        .unwrap();
+    assert!(!UserTotp::is_enabled(db, &user.id).await?);
+    assert!(UserTotp::enable(db, &user.id, 42).await?);
+    assert!(UserTotp::set_secret(db, &user.id, b"madoka").await?.is_none());
+    assert!(!UserTotp::use_step(db, &user.id, 42).await?);
+    assert!(UserTotp::use_step(db, &user.id, 43).await?);
+    let codes = vec!["aaaaabbbbb".to_string(), "cccccddddd".to_string()];
+    UserTotp::set_recovery_codes(db, &user.id, &*codes).await?;
+    assert!(UserTotp::use_recovery_code(db, &user.id, "aaaaabbbbb").await?);
+    assert!(!UserTotp::use_recovery_code(db, &user.id, "aaaaabbbbb").await?);
+    assert_eq!(UserTotp::count_recovery_codes(db, &user.id).await?, 1);
+    UserTotp::remove(db, &user.id).await?;
+    assert!(UserTotp::get(db, &user.id).await?.is_none());
+
+    let kyouko = User::register(db, "kyouko@mythal.net", "kyouko", "Sakura Kyouko", password).await?;
+    let renamed = User::change_username(db, kyouko.clone(), "sakura").await?;
+    assert_eq!(renamed.username, "sakura");
+    assert!(User::change_username(db, renamed, "kyouko_sakura").await.is_err());
+    assert!(User::is_username_taken(db, "kyouko").await?);
+    assert!(User::register(db, "fake@mythal.net", "kyouko", "Fake Kyouko", password)
+        .await
+        .is_err());
+    let redirected = User::get_by_old_username(db, "kyouko").await?.unwrap();
+    assert_eq!(redirected.id, kyouko.id);
+    assert_eq!(UsernameHistory::get_by_user(db, &kyouko.id).await?.len(), 1);
+    UserBlock::block(db, &user.id, &kyouko.id).await?;
+    UserBlock::block(db, &user.id, &kyouko.id).await?;
+    assert!(UserBlock::is_blocked(db, &user.id, &kyouko.id).await?);
+    assert!(!UserBlock::is_blocked(db, &kyouko.id, &user.id).await?);
+    assert!(UserBlock::is_blocked_by_any(db, &kyouko.id, &[kyouko.id, user.id]).await?);
+    assert_eq!(UserBlock::get_blocked_users(db, &user.id).await?.len(), 1);
+    assert_eq!(UserBlock::get_blocked_ids(db, &user.id).await?, vec![kyouko.id]);
+    assert_eq!(UserBlock::unblock(db, &user.id, &kyouko.id).await?, 1);
+    assert!(UserBlock::get_blocked_ids(db, &user.id).await?.is_empty());
+
+    let deleted = User::delete_account(db, &user.id, "Deleted User").await?;
+    assert!(deleted.deactivated);
+    assert!(!deleted.password_set);
+    assert_ne!(deleted.email, email);
+    assert!(User::login(db, username, password).await?.is_none());
+    assert!(User::is_username_taken(db, username).await?);
+    assert!(UserExt::get_settings(db, user.id)
+        .await?
+        .as_object()
+        .unwrap()
+        .is_empty());
+
     User::deactivated(db, &new_user.id).await.unwrap();
 
     let all_users = User::all(db).await.unwrap();
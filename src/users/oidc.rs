@@ -0,0 +1,349 @@
+//! Log in with external OpenID Connect providers.
+//!
+//! Providers are configured by environment variables:
+//!
+//! ```text
+//! OIDC_PROVIDERS=keycloak,discord
+//! OIDC_REDIRECT_URI=https://example.com/api/users/oidc_callback
+//! OIDC_KEYCLOAK_ISSUER=https://sso.example.com/realms/boluo
+//! OIDC_KEYCLOAK_CLIENT_ID=boluo
+//! OIDC_KEYCLOAK_CLIENT_SECRET=...
+//! ```
+use crate::cache;
+use crate::error::CacheError;
+use crate::http_client;
+use anyhow::{anyhow, Context};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+fn load_providers() -> HashMap<String, Provider> {
+    let names = env::var("OIDC_PROVIDERS").unwrap_or_default();
+    let mut providers = HashMap::new();
+    for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let var = |key: &str| env::var(format!("OIDC_{}_{}", name.to_ascii_uppercase(), key));
+        match (var("ISSUER"), var("CLIENT_ID"), var("CLIENT_SECRET")) {
+            (Ok(issuer), Ok(client_id), Ok(client_secret)) => {
+                let issuer = issuer.trim_end_matches('/').to_string();
+                let name = name.to_string();
+                providers.insert(
+                    name.clone(),
+                    Provider {
+                        name,
+                        issuer,
+                        client_id,
+                        client_secret,
+                    },
+                );
+            }
+            _ => log::error!("The OpenID Connect provider \"{}\" is not fully configured", name),
+        }
+    }
+    providers
+}
+
+pub fn providers() -> &'static HashMap<String, Provider> {
+    static PROVIDERS: OnceCell<HashMap<String, Provider>> = OnceCell::new();
+    PROVIDERS.get_or_init(load_providers)
+}
+
+pub fn redirect_uri() -> &'static str {
+    static REDIRECT_URI: OnceCell<String> = OnceCell::new();
+    REDIRECT_URI
+        .get_or_init(|| env::var("OIDC_REDIRECT_URI").unwrap_or_else(|_| "/api/users/oidc_callback".to_string()))
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+pub async fn discover(provider: &Provider) -> Result<Discovery, anyhow::Error> {
+    let uri = format!("{}/.well-known/openid-configuration", provider.issuer);
+    http_client::get_json(&*uri).await
+}
+
+/// The state of an authorization request, kept in the cache until the provider calls back.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeState {
+    pub provider: String,
+    pub nonce: String,
+    pub redirect: String,
+    pub link_user_id: Option<Uuid>,
+}
+
+fn state_key(state: &str) -> Vec<u8> {
+    [b"oidc:" as &[u8], state.as_bytes(), b":state"].concat()
+}
+
+impl AuthorizeState {
+    pub async fn save(&self, state: &str) -> Result<(), CacheError> {
+        let value = serde_json::to_vec(self).expect("failed to serialize OpenID Connect state");
+        cache::conn()
+            .await
+            .set_with_expiration(&*state_key(state), &*value, 60 * 10)
+            .await
+    }
+
+    pub async fn take(state: &str) -> Result<Option<AuthorizeState>, CacheError> {
+        let key = state_key(state);
+        let mut cache = cache::conn().await;
+        let value = cache.get(&*key).await?;
+        cache.remove(&*key).await?;
+        Ok(value.and_then(|bytes| serde_json::from_slice(&*bytes).ok()))
+    }
+}
+
+pub fn authorize_url(discovery: &Discovery, provider: &Provider, state: &str, nonce: &str) -> String {
+    let query = serde_urlencoded::to_string(&[
+        ("response_type", "code"),
+        ("client_id", &*provider.client_id),
+        ("redirect_uri", redirect_uri()),
+        ("scope", "openid email profile"),
+        ("state", state),
+        ("nonce", nonce),
+    ])
+    .expect("failed to encode authorize query");
+    let separator = if discovery.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    format!("{}{}{}", discovery.authorization_endpoint, separator, query)
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+    pub crv: Option<String>,
+    pub x: Option<String>,
+    pub y: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub exp: i64,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Claims {
+    pub fn verified_email(&self) -> Option<&str> {
+        if self.email_verified {
+            self.email.as_deref()
+        } else {
+            None
+        }
+    }
+
+    /// A username that passes `validators::NAME`, derived from the claims.
+    pub fn suggest_username(&self) -> String {
+        let base = self
+            .preferred_username
+            .as_deref()
+            .or_else(|| self.email.as_deref().and_then(|email| email.split('@').next()))
+            .unwrap_or("");
+        let mut username: String = regex!(r"[^\w]+").replace_all(base, "_").chars().take(24).collect();
+        while username.chars().count() < 3 {
+            username.push('_');
+        }
+        username
+    }
+}
+
+fn decode_segment(segment: &str) -> Result<Vec<u8>, anyhow::Error> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD).context("Invalid base64 in JWT")
+}
+
+fn verify_signature(header: &JwtHeader, key: &Jwk, message: &[u8], signature: &[u8]) -> Result<(), anyhow::Error> {
+    use ring::signature;
+    let field = |value: &Option<String>| -> Result<Vec<u8>, anyhow::Error> {
+        decode_segment(value.as_deref().ok_or_else(|| anyhow!("Incomplete JWK"))?)
+    };
+    let result = match (&*header.alg, &*key.kty) {
+        ("RS256", "RSA") => {
+            let n = field(&key.n)?;
+            let e = field(&key.e)?;
+            signature::RsaPublicKeyComponents { n: &*n, e: &*e }.verify(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                message,
+                signature,
+            )
+        }
+        ("ES256", "EC") if key.crv.as_deref() == Some("P-256") => {
+            let mut point = vec![0x04];
+            point.extend(field(&key.x)?);
+            point.extend(field(&key.y)?);
+            signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(message, signature)
+        }
+        (alg, kty) => return Err(anyhow!("Unsupported JWT algorithm {} with {} key", alg, kty)),
+    };
+    result.map_err(|_| anyhow!("Invalid JWT signature"))
+}
+
+/// Verify an ID token against the provider's keys, and return its claims.
+pub fn verify_id_token(
+    token: &str,
+    keys: &[Jwk],
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: i64,
+) -> Result<Claims, anyhow::Error> {
+    let mut segments = token.split('.');
+    let (header, payload, signature) = match (segments.next(), segments.next(), segments.next(), segments.next()) {
+        (Some(header), Some(payload), Some(signature), None) => (header, payload, signature),
+        _ => return Err(anyhow!("Malformed JWT")),
+    };
+    let message = &token[..header.len() + 1 + payload.len()];
+    let header: JwtHeader = serde_json::from_slice(&*decode_segment(header)?)?;
+    let key = keys
+        .iter()
+        .find(|key| header.kid.is_none() || key.kid == header.kid)
+        .ok_or_else(|| anyhow!("No matching key for JWT"))?;
+    verify_signature(&header, key, message.as_bytes(), &*decode_segment(signature)?)?;
+
+    let claims: Claims = serde_json::from_slice(&*decode_segment(payload)?)?;
+    if claims.iss.trim_end_matches('/') != issuer.trim_end_matches('/') {
+        return Err(anyhow!("Unexpected issuer {}", claims.iss));
+    }
+    if !claims.aud.contains(client_id) {
+        return Err(anyhow!("The ID token was not issued for this client"));
+    }
+    if claims.exp + 60 < now {
+        return Err(anyhow!("The ID token has expired"));
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(anyhow!("Nonce mismatch"));
+    }
+    Ok(claims)
+}
+
+/// Exchange the authorization code, and verify the returned ID token.
+pub async fn authenticate(provider: &Provider, code: &str, nonce: &str) -> Result<Claims, anyhow::Error> {
+    let discovery = discover(provider).await?;
+    let token: TokenResponse = http_client::post_form(
+        &*discovery.token_endpoint,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri()),
+            ("client_id", &*provider.client_id),
+            ("client_secret", &*provider.client_secret),
+        ],
+    )
+    .await?;
+    let JwkSet { keys } = http_client::get_json(&*discovery.jwks_uri).await?;
+    let now = crate::utils::now_unix_duration().as_secs() as i64;
+    verify_id_token(
+        &*token.id_token,
+        &*keys,
+        &*discovery.issuer,
+        &*provider.client_id,
+        nonce,
+        now,
+    )
+}
+
+#[test]
+fn test_verify_id_token() {
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+    let point = key_pair.public_key().as_ref();
+    let encode = |bytes: &[u8]| base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+    let jwk = Jwk {
+        kty: "EC".to_string(),
+        kid: Some("test".to_string()),
+        n: None,
+        e: None,
+        crv: Some("P-256".to_string()),
+        x: Some(encode(&point[1..33])),
+        y: Some(encode(&point[33..65])),
+    };
+    let issuer = "https://sso.example.com/realms/boluo";
+    let header = encode(br#"{"alg":"ES256","kid":"test"}"#);
+    let payload = serde_json::json!({
+        "iss": issuer,
+        "sub": "42",
+        "aud": ["boluo"],
+        "exp": 2000,
+        "nonce": "nonce",
+        "email": "madoka@example.com",
+        "email_verified": true,
+    });
+    let payload = encode(payload.to_string().as_bytes());
+    let message = format!("{}.{}", header, payload);
+    let signature = key_pair.sign(&rng, message.as_bytes()).unwrap();
+    let token = format!("{}.{}", message, encode(signature.as_ref()));
+    let keys = [jwk];
+
+    let claims = verify_id_token(&*token, &keys, issuer, "boluo", "nonce", 1000).unwrap();
+    assert_eq!(claims.sub, "42");
+    assert_eq!(claims.verified_email(), Some("madoka@example.com"));
+    assert_eq!(claims.suggest_username(), "madoka");
+    assert!(verify_id_token(&*token, &keys, issuer, "boluo", "other nonce", 1000).is_err());
+    assert!(verify_id_token(&*token, &keys, issuer, "another client", "nonce", 1000).is_err());
+    assert!(verify_id_token(&*token, &keys, issuer, "boluo", "nonce", 3000).is_err());
+    let tampered = format!("{}.{}.{}", header, encode(b"{}"), encode(signature.as_ref()));
+    assert!(verify_id_token(&*tampered, &keys, issuer, "boluo", "nonce", 1000).is_err());
+}
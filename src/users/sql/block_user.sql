@@ -0,0 +1,4 @@
+INSERT INTO user_blocks (user_id, blocked_id)
+VALUES ($1, $2)
+ON CONFLICT (user_id, blocked_id) DO UPDATE SET created = user_blocks.created
+RETURNING user_blocks;
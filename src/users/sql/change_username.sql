@@ -0,0 +1,8 @@
+WITH history AS (
+    INSERT INTO username_history (user_id, username)
+        SELECT id, username FROM users WHERE id = $1
+)
+UPDATE users
+SET username = $2
+WHERE id = $1
+RETURNING users;
@@ -0,0 +1,4 @@
+SELECT count(*)
+FROM totp_recovery_codes
+WHERE user_id = $1
+  AND used = false;
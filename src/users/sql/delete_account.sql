@@ -0,0 +1,16 @@
+WITH identities AS (DELETE FROM user_identities WHERE user_id = $1),
+     extension AS (DELETE FROM users_extension WHERE user_id = $1),
+     totp AS (DELETE FROM user_totp WHERE user_id = $1),
+     -- Keep the old usernames reserved, with the current one.
+     history AS (INSERT INTO username_history (user_id, username) SELECT id, username FROM users WHERE id = $1)
+UPDATE users
+SET deactivated  = true,
+    email        = id::text || '@deleted.invalid',
+    username     = 'deleted_' || replace(id::text, '-', ''),
+    nickname     = $2,
+    password     = crypt(gen_random_uuid()::text, gen_salt('bf')),
+    password_set = false,
+    bio          = '',
+    avatar_id    = NULL
+WHERE id = $1
+RETURNING users;
@@ -0,0 +1,5 @@
+UPDATE user_totp
+SET enabled   = true,
+    last_step = $2
+WHERE user_id = $1
+  AND enabled = false;
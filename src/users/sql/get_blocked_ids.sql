@@ -0,0 +1,3 @@
+SELECT blocked_id
+FROM user_blocks
+WHERE user_id = $1;
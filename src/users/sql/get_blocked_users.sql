@@ -0,0 +1,5 @@
+SELECT u
+FROM user_blocks b
+    INNER JOIN users u ON b.blocked_id = u.id
+WHERE b.user_id = $1
+ORDER BY b.created;
@@ -0,0 +1,8 @@
+SELECT u
+FROM username_history h
+    INNER JOIN users u ON h.user_id = u.id
+WHERE h.username = $1
+  AND h.changed > $2
+  AND u.deactivated = false
+ORDER BY h.changed DESC
+LIMIT 1;
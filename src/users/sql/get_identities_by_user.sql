@@ -0,0 +1,4 @@
+SELECT user_identities
+FROM user_identities
+WHERE user_id = $1
+ORDER BY created;
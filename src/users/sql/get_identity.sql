@@ -0,0 +1,5 @@
+SELECT identity
+FROM user_identities identity
+WHERE identity.issuer = $1
+  AND identity.subject = $2
+LIMIT 1;
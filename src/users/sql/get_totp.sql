@@ -0,0 +1,3 @@
+SELECT user_totp
+FROM user_totp
+WHERE user_id = $1;
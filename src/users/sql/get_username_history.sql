@@ -0,0 +1,4 @@
+SELECT username_history
+FROM username_history
+WHERE user_id = $1
+ORDER BY changed DESC;
@@ -0,0 +1,4 @@
+SELECT EXISTS(SELECT 1
+              FROM user_blocks
+              WHERE blocked_id = $1
+                AND user_id = ANY ($2));
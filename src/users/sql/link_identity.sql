@@ -0,0 +1,3 @@
+INSERT INTO user_identities (user_id, provider, issuer, subject, email)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING user_identities;
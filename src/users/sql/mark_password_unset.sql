@@ -0,0 +1,3 @@
+UPDATE users
+SET password_set = false
+WHERE id = $1;
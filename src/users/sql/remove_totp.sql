@@ -0,0 +1,3 @@
+DELETE
+FROM user_totp
+WHERE user_id = $1;
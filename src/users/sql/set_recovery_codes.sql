@@ -0,0 +1,7 @@
+WITH removed AS (
+    DELETE FROM totp_recovery_codes WHERE user_id = $1
+)
+INSERT
+INTO totp_recovery_codes (user_id, code)
+SELECT $1, crypt(code, gen_salt('bf'))
+FROM unnest($2::text[]) AS code;
@@ -0,0 +1,8 @@
+INSERT INTO user_totp (user_id, secret)
+VALUES ($1, $2)
+ON CONFLICT (user_id) DO UPDATE
+    SET secret    = $2,
+        last_step = 0,
+        created   = (now() at time zone 'utc')
+WHERE user_totp.enabled = false
+RETURNING user_totp;
@@ -0,0 +1,4 @@
+DELETE
+FROM user_blocks
+WHERE user_id = $1
+  AND blocked_id = $2;
@@ -0,0 +1,4 @@
+DELETE
+FROM user_identities
+WHERE user_id = $1
+  AND id = $2;
@@ -0,0 +1,8 @@
+UPDATE totp_recovery_codes
+SET used = true
+WHERE id = (SELECT id
+            FROM totp_recovery_codes
+            WHERE user_id = $1
+              AND used = false
+              AND code = crypt($2, code)
+            LIMIT 1);
@@ -0,0 +1,6 @@
+SELECT EXISTS(SELECT 1 FROM users WHERE username = $1 AND id IS DISTINCT FROM $2)
+           OR EXISTS(SELECT 1
+                     FROM username_history
+                     WHERE username = $1
+                       AND user_id IS DISTINCT FROM $2
+                       AND changed > $3);
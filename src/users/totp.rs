@@ -0,0 +1,225 @@
+//! Time-based one-time passwords ([RFC 6238](https://tools.ietf.org/html/rfc6238)).
+use crate::cache;
+use crate::error::CacheError;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const DIGITS: u32 = 6;
+pub const PERIOD: u64 = 30;
+pub const RECOVERY_CODES: usize = 10;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    output
+}
+
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let c = c.to_ascii_uppercase() as u8;
+        let value = BASE32_ALPHABET.iter().position(|x| *x == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+/// HOTP ([RFC 4226](https://tools.ietf.org/html/rfc4226)) with HMAC-SHA1.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = (digest[digest.len() - 1] & 0xf) as usize;
+    let binary = ((digest[offset] as u32 & 0x7f) << 24)
+        | ((digest[offset + 1] as u32) << 16)
+        | ((digest[offset + 2] as u32) << 8)
+        | (digest[offset + 3] as u32);
+    binary % 10u32.pow(digits)
+}
+
+pub fn step(unix_seconds: u64) -> u64 {
+    unix_seconds / PERIOD
+}
+
+/// Check the code against the adjacent time steps too, to tolerate clock drift.
+///
+/// Returns the matched time step.
+pub fn verify(secret: &[u8], code: &str, unix_seconds: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = step(unix_seconds);
+    (current.saturating_sub(1)..=current + 1).find(|step| hotp(secret, *step, DIGITS) == code)
+}
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .expect("failed to generate TOTP secret");
+    secret
+}
+
+/// One-time recovery codes, formatted as `xxxxx-xxxxx`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let rng = SystemRandom::new();
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            rng.fill(&mut bytes).expect("failed to generate recovery code");
+            let code = base32_encode(&bytes).to_ascii_lowercase();
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect()
+}
+
+/// Recovery codes are stored without separators.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+    let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC).to_string();
+    let account = utf8_percent_encode(account, NON_ALPHANUMERIC).to_string();
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        account,
+        base32_encode(secret),
+        issuer,
+        DIGITS,
+        PERIOD
+    )
+}
+
+/// A login that passed the password check, waiting for the second factor.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingLogin {
+    pub user_id: Uuid,
+    pub with_token: bool,
+    pub attempts: u32,
+}
+
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Attempts allowed for a user in the window, however many challenges they are spread over.
+pub const MAX_USER_ATTEMPTS: u32 = 10;
+const USER_ATTEMPT_WINDOW: usize = 60 * 15;
+
+fn pending_key(challenge: &str) -> Vec<u8> {
+    [b"login:" as &[u8], challenge.as_bytes(), b":pending"].concat()
+}
+
+fn attempts_key(user_id: &Uuid) -> Vec<u8> {
+    cache::make_key(b"login", user_id, b"totp_attempts")
+}
+
+/// Count an attempt before checking the code, so concurrent guesses can't pass the limit.
+///
+/// Returns `false` if the user has run out of attempts.
+pub async fn count_attempt(user_id: &Uuid) -> Result<bool, CacheError> {
+    let attempts = cache::conn()
+        .await
+        .incr_with_expiration(&*attempts_key(user_id), USER_ATTEMPT_WINDOW)
+        .await?;
+    Ok(attempts <= MAX_USER_ATTEMPTS)
+}
+
+pub async fn reset_attempts(user_id: &Uuid) -> Result<(), CacheError> {
+    cache::conn().await.remove(&*attempts_key(user_id)).await
+}
+
+impl PendingLogin {
+    /// Returns the challenge to be answered with the second factor.
+    pub async fn start(user_id: Uuid, with_token: bool) -> Result<String, CacheError> {
+        let challenge = crate::utils::random_token(32);
+        PendingLogin {
+            user_id,
+            with_token,
+            attempts: 0,
+        }
+        .save(&challenge)
+        .await?;
+        Ok(challenge)
+    }
+
+    pub async fn save(&self, challenge: &str) -> Result<(), CacheError> {
+        let value = serde_json::to_vec(self).expect("failed to serialize pending login");
+        cache::conn()
+            .await
+            .set_with_expiration(&*pending_key(challenge), &*value, 60 * 5)
+            .await
+    }
+
+    pub async fn take(challenge: &str) -> Result<Option<PendingLogin>, CacheError> {
+        let key = pending_key(challenge);
+        let mut cache = cache::conn().await;
+        let value = cache.get(&*key).await?;
+        cache.remove(&*key).await?;
+        Ok(value.and_then(|bytes| serde_json::from_slice(&*bytes).ok()))
+    }
+}
+
+#[test]
+fn totp_test() {
+    // https://tools.ietf.org/html/rfc6238#appendix-B
+    let secret = b"12345678901234567890";
+    let vectors: &[(u64, u32)] = &[
+        (59, 94287082),
+        (1111111109, 7081804),
+        (1111111111, 14050471),
+        (1234567890, 89005924),
+        (2000000000, 69279037),
+        (20000000000, 65353130),
+    ];
+    for (time, expected) in vectors {
+        assert_eq!(hotp(secret, step(*time), 8), *expected);
+    }
+    // https://tools.ietf.org/html/rfc4226#page-32
+    assert_eq!(hotp(secret, 0, 6), 755224);
+    assert_eq!(hotp(secret, 9, 6), 520489);
+
+    assert_eq!(verify(secret, "287082", 59), Some(1));
+    assert_eq!(verify(secret, "287082", 89), Some(1));
+    assert_eq!(verify(secret, "287082", 200), None);
+    assert_eq!(verify(secret, "28708", 59), None);
+
+    assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+    assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+    assert_eq!(base32_decode(&*base32_encode(secret)).unwrap(), secret);
+    assert!(base32_decode("1").is_none());
+
+    let codes = generate_recovery_codes();
+    assert_eq!(codes.len(), RECOVERY_CODES);
+    assert_eq!(normalize_recovery_code(&*codes[0]).len(), 10);
+    assert_eq!(normalize_recovery_code(" ABCDE-fghij "), "abcdefghij");
+}
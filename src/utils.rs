@@ -38,18 +38,33 @@ pub fn id() -> Uuid {
     Uuid::new_v1(timestamp, node_id).expect("failed to generate UUID")
 }
 
+/// Generate `n` random bytes, encoded as URL-safe base64.
+pub fn random_token(n: usize) -> String {
+    let rng = ring::rand::SystemRandom::new();
+    let mut bytes = vec![0u8; n];
+    rng.fill(&mut bytes).expect("failed to generate random bytes");
+    base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// A HMAC-SHA256 key derived from the secret.
+pub fn hmac_key(secret: &[u8]) -> hmac::Key {
+    use ring::digest;
+    let digest = digest::digest(&digest::SHA256, secret);
+    hmac::Key::new(hmac::HMAC_SHA256, digest.as_ref())
+}
+
 fn key() -> &'static hmac::Key {
     use crate::context::secret;
-    use ring::digest;
     static KEY: OnceCell<hmac::Key> = OnceCell::new();
-    KEY.get_or_init(|| {
-        let digest = digest::digest(&digest::SHA256, secret().as_bytes());
-        hmac::Key::new(hmac::HMAC_SHA256, digest.as_ref())
-    })
+    KEY.get_or_init(|| hmac_key(secret().as_bytes()))
 }
 
 pub fn sign(message: &str) -> hmac::Tag {
-    hmac::sign(key(), message.as_bytes())
+    sign_with(key(), message)
+}
+
+pub fn sign_with(key: &hmac::Key, message: &str) -> hmac::Tag {
+    hmac::sign(key, message.as_bytes())
 }
 
 pub fn sha1(data: &[u8]) -> ring::digest::Digest {
@@ -57,8 +72,12 @@ pub fn sha1(data: &[u8]) -> ring::digest::Digest {
 }
 
 pub fn verify(message: &str, signature: &str) -> Result<(), anyhow::Error> {
+    verify_with(key(), message, signature)
+}
+
+pub fn verify_with(key: &hmac::Key, message: &str, signature: &str) -> Result<(), anyhow::Error> {
     let signature = base64::decode(signature.trim()).context("Failed to decode signature")?;
-    hmac::verify(key(), message.as_bytes(), &*signature)
+    hmac::verify(key, message.as_bytes(), &*signature)
         .map_err(|_| anyhow::anyhow!("Failed to verify signature of message {}", message))
 }
 
@@ -89,6 +108,11 @@ fn test_sign() {
     let signature = sign(message);
     let signature = base64::encode(&signature);
     verify(message, &*signature).unwrap();
+
+    let key = hmac_key(b"another secret");
+    let signature = base64::encode(&sign_with(&key, message));
+    verify_with(&key, message, &*signature).unwrap();
+    assert!(verify(message, &*signature).is_err());
 }
 
 pub struct MessageRng {
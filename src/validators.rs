@@ -1,6 +1,9 @@
 use crate::error::ValidationFailed;
 
-pub struct Validator<'a, T: ?Sized>(&'a [(&'static str, &'a (dyn Fn(&T) -> bool + Sync))]);
+/// A check and the message if it fails.
+type SubValidator<'a, T> = (&'static str, &'a (dyn Fn(&T) -> bool + Sync));
+
+pub struct Validator<'a, T: ?Sized>(&'a [SubValidator<'a, T>]);
 
 impl<'a, T: ?Sized> Validator<'a, T> {
     pub fn run<U: AsRef<T>>(&self, value: U) -> Result<(), ValidationFailed> {
@@ -37,6 +40,8 @@ pub static PASSWORD: Validator<str> = Validator(&[
     ("Password length shall not be more than 128.", &max!(128)),
 ]);
 
+pub static SPACE_PASSWORD: Validator<str> = Validator(&[("Password length shall not be more than 128.", &max!(128))]);
+
 pub static NAME: Validator<str> = Validator(&[
     ("Name length shall not be less than 3.", &min!(3)),
     ("Name length shall not be more than 32.", &max!(32)),
@@ -72,6 +77,25 @@ pub static TOPIC: Validator<str> = Validator(&[("Topic shall not be more than 12
 
 pub static DESCRIPTION: Validator<str> = Validator(&[("Description shall not be more than 512.", &max!(512))]);
 
+pub static WEBHOOK_URL: Validator<str> = Validator(&[
+    ("URL shall not be more than 512.", &max!(512)),
+    ("The URL of webhooks must be HTTPS", &is_match!(r"^https://\S+$")),
+]);
+
+static LOCAL_WEBHOOK_URL: Validator<str> = Validator(&[
+    ("URL shall not be more than 512.", &max!(512)),
+    ("Invalid URL", &is_match!(r"^https?://\S+$")),
+]);
+
+/// Local receivers may use plain HTTP.
+pub fn webhook_url(allow_local: bool) -> &'static Validator<'static, str> {
+    if allow_local {
+        &LOCAL_WEBHOOK_URL
+    } else {
+        &WEBHOOK_URL
+    }
+}
+
 pub static DICE: Validator<str> = Validator(&[("Illegal dice format.", &is_match!(r"^d\d{1,3}|FATE$"))]);
 
 #[test]
@@ -89,4 +113,9 @@ fn validator_test() {
 
     assert!(EMAIL.run("").is_err());
     assert!(EMAIL.run("example@example.com").is_ok());
+
+    assert!(webhook_url(false).run("https://example.com/hook").is_ok());
+    assert!(webhook_url(false).run("http://example.com/hook").is_err());
+    assert!(webhook_url(true).run("http://127.0.0.1/hook").is_ok());
+    assert!(webhook_url(true).run("ftp://example.com").is_err());
 }
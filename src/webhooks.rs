@@ -0,0 +1,8 @@
+pub mod api;
+mod delivery;
+mod handlers;
+mod models;
+
+pub use delivery::dispatch;
+pub use handlers::router;
+pub use models::{SpaceWebhook, WebhookDelivery};
@@ -0,0 +1,238 @@
+//! Deliver the events of spaces to webhooks.
+//!
+//! Every delivery is a `POST` of the event in JSON with these headers:
+//!
+//! - `X-Boluo-Event`: the type of the event.
+//! - `X-Boluo-Delivery`: the ID of the delivery, which is the same between retries.
+//! - `X-Boluo-Timestamp`: milliseconds since the UNIX epoch.
+//! - `X-Boluo-Signature`: the HMAC-SHA256 of `{timestamp}.{body}` in base64,
+//!   keyed with the SHA-256 digest of the secret of the webhook.
+//!
+//! Deliveries are refused if the host resolves into a loopback, private,
+//! link-local or unique-local network, unless `WEBHOOK_ALLOW_LOCAL` is set.
+//! Redirects are not followed; a `3xx` response counts as a failed attempt.
+//!
+//! Retries are kept in memory only. Deliveries still pending when the server
+//! stops are not resumed and stay in the log with `delivered` unset.
+use super::models::{SpaceWebhook, WebhookDelivery};
+use crate::context::webhook_allow_local;
+use crate::database;
+use crate::events::context::SyncEvent;
+use crate::events::EventBody;
+use crate::http_client::{public_client, PublicClient};
+use crate::utils::{hmac_key, sign_with, timestamp};
+use hyper::{Body, Method, Request, StatusCode};
+use once_cell::sync::OnceCell;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::spawn;
+use uuid::Uuid;
+
+/// The events which can be subscribed.
+pub const EVENT_TYPES: [&str; 7] = [
+    "NEW_MESSAGE",
+    "MESSAGE_EDITED",
+    "MESSAGE_DELETED",
+    "CHANNEL_EDITED",
+    "CHANNEL_DELETED",
+    "MEMBERS",
+    "SPACE_UPDATED",
+];
+
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long the set of spaces with webhooks is trusted before it is reloaded,
+/// so that changes made on other servers are picked up.
+const SPACES_TTL: Duration = Duration::from_secs(60);
+
+type SpacesCache = RwLock<Option<(Instant, HashSet<Uuid>)>>;
+
+/// Spaces which have enabled webhooks, and when the set was loaded.
+fn spaces_cache() -> &'static SpacesCache {
+    static SPACES: OnceCell<SpacesCache> = OnceCell::new();
+    SPACES.get_or_init(|| RwLock::new(None))
+}
+
+/// `None` if the cached set has expired.
+fn has_webhooks(space_id: &Uuid) -> Option<bool> {
+    let cache = spaces_cache().read().unwrap();
+    match &*cache {
+        Some((loaded, spaces)) if loaded.elapsed() < SPACES_TTL => Some(spaces.contains(space_id)),
+        _ => None,
+    }
+}
+
+/// Must be called after webhooks are created, edited or deleted.
+pub fn spaces_changed() {
+    *spaces_cache().write().unwrap() = None;
+}
+
+fn webhook_client() -> &'static PublicClient {
+    static CLIENT: OnceCell<PublicClient> = OnceCell::new();
+    CLIENT.get_or_init(|| public_client(webhook_allow_local()))
+}
+
+/// `None` if the event is never delivered, such as previews and whispers.
+fn event_type(body: &EventBody) -> Option<&'static str> {
+    match body {
+        EventBody::NewMessage { message, .. } if message.whisper_to_users.is_none() => Some("NEW_MESSAGE"),
+        EventBody::MessageEdited { message, .. } if message.whisper_to_users.is_none() => Some("MESSAGE_EDITED"),
+        EventBody::MessageDeleted { .. } => Some("MESSAGE_DELETED"),
+        EventBody::ChannelEdited { .. } => Some("CHANNEL_EDITED"),
+        EventBody::ChannelDeleted { .. } => Some("CHANNEL_DELETED"),
+        EventBody::Members { .. } => Some("MEMBERS"),
+        EventBody::SpaceUpdated { .. } => Some("SPACE_UPDATED"),
+        EventBody::NewMessage { .. }
+        | EventBody::MessageEdited { .. }
+        | EventBody::MessagePreview { .. }
+        | EventBody::Initialized
+        | EventBody::StatusMap { .. }
+        | EventBody::AppUpdated
+        | EventBody::ReadMarker { .. }
+        | EventBody::Notification { .. } => None,
+    }
+}
+
+/// Wait 2, 4, 8... seconds before retrying.
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(2u64.pow(attempt))
+}
+
+/// Send the event sent to the mailbox to the webhooks of the space.
+pub fn dispatch(mailbox: Uuid, event: Arc<SyncEvent>) {
+    let event_type = match event_type(&event.event.body) {
+        Some(event_type) => event_type,
+        None => return,
+    };
+    if has_webhooks(&mailbox) == Some(false) {
+        return;
+    }
+    spawn(async move {
+        if let Err(e) = dispatch_to_subscribers(mailbox, event_type, event).await {
+            log::warn!("Failed to dispatch the event to webhooks: {}", e);
+        }
+    });
+}
+
+async fn dispatch_to_subscribers(
+    space_id: Uuid,
+    event_type: &'static str,
+    event: Arc<SyncEvent>,
+) -> Result<(), anyhow::Error> {
+    let mut conn = database::get().await?;
+    if has_webhooks(&space_id).is_none() {
+        let spaces = SpaceWebhook::get_spaces(&mut *conn).await?;
+        let found = spaces.contains(&space_id);
+        *spaces_cache().write().unwrap() = Some((Instant::now(), spaces.into_iter().collect()));
+        if !found {
+            return Ok(());
+        }
+    }
+    let webhooks = SpaceWebhook::get_subscribers(&mut *conn, &space_id, event_type).await?;
+    drop(conn);
+    for webhook in webhooks {
+        let payload = event.encoded.clone();
+        spawn(async move {
+            if let Err(e) = deliver(&webhook, event_type, payload).await {
+                log::warn!("Failed to deliver the event to webhook {}: {}", webhook.id, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn deliver(webhook: &SpaceWebhook, event_type: &str, payload: String) -> Result<(), anyhow::Error> {
+    let mut conn = database::get().await?;
+    let delivery = WebhookDelivery::create(&mut *conn, &webhook.id, event_type, &*payload).await?;
+    drop(conn);
+    for attempt in 0..MAX_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(backoff(attempt)).await;
+        }
+        let result = post(
+            webhook_client(),
+            &*webhook.url,
+            &*webhook.secret,
+            &delivery.id,
+            event_type,
+            &*payload,
+        )
+        .await;
+        let (status_code, error) = match result {
+            Ok(status) if status.is_success() => (Some(status.as_u16() as i32), None),
+            Ok(status) => (Some(status.as_u16() as i32), Some(format!("responded {}", status))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let mut conn = database::get().await?;
+        WebhookDelivery::record_attempt(&mut *conn, &delivery.id, status_code, error.as_deref()).await?;
+        if error.is_none() {
+            return Ok(());
+        }
+    }
+    Err(anyhow::anyhow!("gave up after {} attempts", MAX_ATTEMPTS))
+}
+
+async fn post(
+    client: &PublicClient,
+    url: &str,
+    secret: &str,
+    delivery_id: &Uuid,
+    event_type: &str,
+    payload: &str,
+) -> Result<StatusCode, anyhow::Error> {
+    let timestamp = timestamp().to_string();
+    let key = hmac_key(secret.as_bytes());
+    let signature = sign_with(&key, &*format!("{}.{}", timestamp, payload));
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header("X-Boluo-Event", event_type)
+        .header("X-Boluo-Delivery", delivery_id.to_string())
+        .header("X-Boluo-Timestamp", &*timestamp)
+        .header("X-Boluo-Signature", base64::encode(&signature))
+        .body(Body::from(payload.to_string()))?;
+    let response = tokio::time::timeout(Duration::from_secs(10), client.request(request)).await??;
+    Ok(response.status())
+}
+
+#[tokio::test]
+async fn post_test() {
+    use crate::utils::verify_with;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use std::convert::Infallible;
+
+    const SECRET: &str = "webhook secret";
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let header = |name: &str| req.headers()[name].to_str().unwrap().to_string();
+            let timestamp = header("X-Boluo-Timestamp");
+            let signature = header("X-Boluo-Signature");
+            assert_eq!(header("X-Boluo-Event"), "NEW_MESSAGE");
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let message = format!("{}.{}", timestamp, String::from_utf8_lossy(&*body));
+            let status = match verify_with(&hmac_key(SECRET.as_bytes()), &*message, &*signature) {
+                Ok(()) => StatusCode::NO_CONTENT,
+                Err(_) => StatusCode::UNAUTHORIZED,
+            };
+            Ok::<_, Infallible>(Response::builder().status(status).body(Body::empty()).unwrap())
+        }))
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+    let url = format!("http://{}/hook", server.local_addr());
+    spawn(server);
+
+    let id = Uuid::new_v4();
+    let client = public_client(true);
+    let status = post(&client, &*url, SECRET, &id, "NEW_MESSAGE", "{}").await.unwrap();
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let status = post(&client, &*url, "wrong secret", &id, "NEW_MESSAGE", "{}")
+        .await
+        .unwrap();
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(post(&public_client(false), &*url, SECRET, &id, "NEW_MESSAGE", "{}")
+        .await
+        .is_err());
+}
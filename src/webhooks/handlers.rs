@@ -0,0 +1,95 @@
+use super::api::{CreateWebhook, EditWebhook};
+use super::delivery::{spaces_changed, EVENT_TYPES};
+use super::{SpaceWebhook, WebhookDelivery};
+use crate::csrf::authenticate;
+use crate::database;
+use crate::error::{AppError, Find};
+use crate::interface::{self, missing, ok_response, parse_query, IdQuery, Response};
+use crate::spaces::permissions;
+use hyper::{Body, Request};
+
+fn check_events(events: Option<&[String]>) -> Result<(), AppError> {
+    for event in events.unwrap_or(&[]) {
+        if !EVENT_TYPES.contains(&&**event) {
+            return Err(AppError::BadRequest(format!("{} can't be subscribed", event)));
+        }
+    }
+    Ok(())
+}
+
+async fn list(req: Request<Body>) -> Result<Vec<SpaceWebhook>, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    permissions::require_admin(db, &session.user_id, &id).await?;
+    SpaceWebhook::get_by_space(db, &id).await.map_err(Into::into)
+}
+
+async fn create(req: Request<Body>) -> Result<SpaceWebhook, AppError> {
+    let session = authenticate(&req).await?;
+    let CreateWebhook { space_id, url, events } = interface::parse_body(req).await?;
+    check_events(Some(&*events))?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    permissions::require_admin(db, &session.user_id, &space_id).await?;
+    let webhook = SpaceWebhook::create(db, &space_id, &session.user_id, &*url, &*events).await?;
+    spaces_changed();
+    log::info!("webhook {} is created in space {}", webhook.id, space_id);
+    Ok(webhook)
+}
+
+async fn edit(req: Request<Body>) -> Result<SpaceWebhook, AppError> {
+    let session = authenticate(&req).await?;
+    let EditWebhook {
+        webhook_id,
+        url,
+        events,
+        enabled,
+    } = interface::parse_body(req).await?;
+    check_events(events.as_deref())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let webhook = SpaceWebhook::get(db, &webhook_id).await.or_not_found()?;
+    permissions::require_admin(db, &session.user_id, &webhook.space_id).await?;
+    let webhook = SpaceWebhook::edit(db, &webhook_id, url.as_deref(), events.as_deref(), enabled)
+        .await?
+        .or_not_found()?;
+    spaces_changed();
+    Ok(webhook)
+}
+
+async fn delete(req: Request<Body>) -> Result<bool, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let webhook = SpaceWebhook::get(db, &id).await.or_not_found()?;
+    permissions::require_admin(db, &session.user_id, &webhook.space_id).await?;
+    SpaceWebhook::delete(db, &id).await?;
+    spaces_changed();
+    Ok(true)
+}
+
+async fn deliveries(req: Request<Body>) -> Result<Vec<WebhookDelivery>, AppError> {
+    let session = authenticate(&req).await?;
+    let IdQuery { id } = parse_query(req.uri())?;
+    let mut conn = database::get().await?;
+    let db = &mut *conn;
+    let webhook = SpaceWebhook::get(db, &id).await.or_not_found()?;
+    permissions::require_admin(db, &session.user_id, &webhook.space_id).await?;
+    WebhookDelivery::get_by_webhook(db, &id, 64).await.map_err(Into::into)
+}
+
+pub async fn router(req: Request<Body>, path: &str) -> Result<Response, AppError> {
+    use hyper::Method;
+
+    match (path, req.method().clone()) {
+        ("/list", Method::GET) => list(req).await.map(ok_response),
+        ("/create", Method::POST) => create(req).await.map(ok_response),
+        ("/edit", Method::POST) => edit(req).await.map(ok_response),
+        ("/delete", Method::POST) => delete(req).await.map(ok_response),
+        ("/deliveries", Method::GET) => deliveries(req).await.map(ok_response),
+        _ => missing(),
+    }
+}
@@ -0,0 +1,211 @@
+use chrono::NaiveDateTime;
+use postgres_types::FromSql;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::database::Querist;
+use crate::error::{DbError, ModelError};
+use crate::utils::inner_result_map;
+
+#[derive(Debug, Serialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "space_webhooks")]
+pub struct SpaceWebhook {
+    pub id: Uuid,
+    pub space_id: Uuid,
+    pub creator_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl SpaceWebhook {
+    pub async fn create<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        creator_id: &Uuid,
+        url: &str,
+        events: &[String],
+    ) -> Result<SpaceWebhook, ModelError> {
+        use crate::context::webhook_allow_local;
+        use crate::utils::random_token;
+        use crate::validators::webhook_url;
+
+        let url = url.trim();
+        webhook_url(webhook_allow_local()).run(url)?;
+        let secret = random_token(32);
+        let row = db
+            .query_exactly_one(
+                include_str!("sql/create.sql"),
+                &[space_id, creator_id, &url, &secret, &events],
+            )
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    pub async fn get<T: Querist>(db: &mut T, id: &Uuid) -> Result<Option<SpaceWebhook>, DbError> {
+        let result = db.query_one(include_str!("sql/get.sql"), &[id]).await;
+        inner_result_map(result, |row| row.try_get(0))
+    }
+
+    pub async fn get_by_space<T: Querist>(db: &mut T, space_id: &Uuid) -> Result<Vec<SpaceWebhook>, DbError> {
+        let rows = db.query(include_str!("sql/get_by_space.sql"), &[space_id]).await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    /// The enabled webhooks of the space which subscribe the type of events.
+    pub async fn get_subscribers<T: Querist>(
+        db: &mut T,
+        space_id: &Uuid,
+        event_type: &str,
+    ) -> Result<Vec<SpaceWebhook>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_subscribers.sql"), &[space_id, &event_type])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    /// Spaces which have at least one enabled webhook.
+    pub async fn get_spaces<T: Querist>(db: &mut T) -> Result<Vec<Uuid>, DbError> {
+        let rows = db.query(include_str!("sql/get_spaces.sql"), &[]).await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+
+    pub async fn edit<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        url: Option<&str>,
+        events: Option<&[String]>,
+        enabled: Option<bool>,
+    ) -> Result<Option<SpaceWebhook>, ModelError> {
+        use crate::context::webhook_allow_local;
+        use crate::validators::webhook_url;
+
+        let url = url.map(str::trim);
+        if let Some(url) = url {
+            webhook_url(webhook_allow_local()).run(url)?;
+        }
+        let result = db
+            .query_one(include_str!("sql/edit.sql"), &[id, &url, &events, &enabled])
+            .await;
+        Ok(inner_result_map(result, |row| row.try_get(0))?)
+    }
+
+    pub async fn delete<T: Querist>(db: &mut T, id: &Uuid) -> Result<u64, DbError> {
+        db.execute(include_str!("sql/delete.sql"), &[id]).await
+    }
+}
+
+#[derive(Debug, Serialize, FromSql, Clone)]
+#[serde(rename_all = "camelCase")]
+#[postgres(name = "webhook_deliveries")]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    #[serde(with = "crate::date_format::option")]
+    pub delivered: Option<NaiveDateTime>,
+    #[serde(with = "crate::date_format")]
+    pub created: NaiveDateTime,
+}
+
+impl WebhookDelivery {
+    pub async fn create<T: Querist>(
+        db: &mut T,
+        webhook_id: &Uuid,
+        event_type: &str,
+        payload: &str,
+    ) -> Result<WebhookDelivery, DbError> {
+        let row = db
+            .query_exactly_one(
+                include_str!("sql/create_delivery.sql"),
+                &[webhook_id, &event_type, &payload],
+            )
+            .await?;
+        row.try_get(0)
+    }
+
+    /// The delivery succeeded if there is no error.
+    pub async fn record_attempt<T: Querist>(
+        db: &mut T,
+        id: &Uuid,
+        status_code: Option<i32>,
+        error: Option<&str>,
+    ) -> Result<WebhookDelivery, DbError> {
+        let row = db
+            .query_exactly_one(include_str!("sql/record_attempt.sql"), &[id, &status_code, &error])
+            .await?;
+        row.try_get(0)
+    }
+
+    pub async fn get_by_webhook<T: Querist>(
+        db: &mut T,
+        webhook_id: &Uuid,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, DbError> {
+        let rows = db
+            .query(include_str!("sql/get_deliveries.sql"), &[webhook_id, &limit])
+            .await?;
+        rows.into_iter().map(|row| row.try_get(0)).collect()
+    }
+}
+
+#[tokio::test]
+async fn webhooks_test() -> Result<(), crate::error::AppError> {
+    use crate::database::Client;
+    use crate::spaces::Space;
+    use crate::users::User;
+
+    let mut client = Client::new().await?;
+    let mut trans = client.transaction().await?;
+    let db = &mut trans;
+    let user = User::register(db, "webhook_test@mythal.net", "webhook_test", "Webhook", "no password").await?;
+    let space = Space::create(db, "Webhook Space".to_string(), &user.id, String::new(), None, None).await?;
+
+    assert!(SpaceWebhook::create(db, &space.id, &user.id, "ftp://example.com", &[])
+        .await
+        .is_err());
+    assert!(SpaceWebhook::create(db, &space.id, &user.id, "http://example.com", &[])
+        .await
+        .is_err());
+    let events = vec!["NEW_MESSAGE".to_string()];
+    let webhook = SpaceWebhook::create(db, &space.id, &user.id, " https://example.com/hook ", &events).await?;
+    assert_eq!(webhook.url, "https://example.com/hook");
+    assert!(webhook.enabled);
+    assert_eq!(SpaceWebhook::get_by_space(db, &space.id).await?.len(), 1);
+    let subscribers = SpaceWebhook::get_subscribers(db, &space.id, "NEW_MESSAGE").await?;
+    assert_eq!(subscribers[0].id, webhook.id);
+    assert!(SpaceWebhook::get_subscribers(db, &space.id, "MEMBERS")
+        .await?
+        .is_empty());
+    assert!(SpaceWebhook::get_spaces(db).await?.contains(&space.id));
+
+    let webhook = SpaceWebhook::edit(db, &webhook.id, None, None, Some(false))
+        .await?
+        .unwrap();
+    assert!(!webhook.enabled);
+    assert!(SpaceWebhook::get_subscribers(db, &space.id, "NEW_MESSAGE")
+        .await?
+        .is_empty());
+    assert!(!SpaceWebhook::get_spaces(db).await?.contains(&space.id));
+
+    let delivery = WebhookDelivery::create(db, &webhook.id, "NEW_MESSAGE", "{}").await?;
+    let delivery = WebhookDelivery::record_attempt(db, &delivery.id, Some(500), Some("responded 500")).await?;
+    assert_eq!(delivery.attempts, 1);
+    assert!(delivery.delivered.is_none());
+    let delivery = WebhookDelivery::record_attempt(db, &delivery.id, Some(200), None).await?;
+    assert_eq!(delivery.attempts, 2);
+    assert!(delivery.delivered.is_some());
+    assert_eq!(WebhookDelivery::get_by_webhook(db, &webhook.id, 16).await?.len(), 1);
+
+    SpaceWebhook::delete(db, &webhook.id).await?;
+    assert!(SpaceWebhook::get(db, &webhook.id).await?.is_none());
+    Ok(())
+}
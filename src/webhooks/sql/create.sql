@@ -0,0 +1,3 @@
+INSERT INTO space_webhooks (space_id, creator_id, url, secret, events)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING space_webhooks;
@@ -0,0 +1,3 @@
+INSERT INTO webhook_deliveries (webhook_id, event_type, payload)
+VALUES ($1, $2, $3)
+RETURNING webhook_deliveries;
@@ -0,0 +1,3 @@
+DELETE
+FROM space_webhooks
+WHERE id = $1;
@@ -0,0 +1,6 @@
+UPDATE space_webhooks
+SET url     = COALESCE($2, url),
+    events  = COALESCE($3, events),
+    enabled = COALESCE($4, enabled)
+WHERE id = $1
+RETURNING space_webhooks;
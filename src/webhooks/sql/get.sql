@@ -0,0 +1,3 @@
+SELECT w
+FROM space_webhooks w
+WHERE id = $1;
@@ -0,0 +1,4 @@
+SELECT w
+FROM space_webhooks w
+WHERE space_id = $1
+ORDER BY created;
@@ -0,0 +1,5 @@
+SELECT d
+FROM webhook_deliveries d
+WHERE webhook_id = $1
+ORDER BY created DESC
+LIMIT $2;
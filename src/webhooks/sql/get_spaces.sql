@@ -0,0 +1,3 @@
+SELECT DISTINCT space_id
+FROM space_webhooks
+WHERE enabled;
@@ -0,0 +1,3 @@
+SELECT w
+FROM space_webhooks w
+WHERE space_id = $1 AND enabled AND $2 = ANY (events);
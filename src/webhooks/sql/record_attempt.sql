@@ -0,0 +1,7 @@
+UPDATE webhook_deliveries
+SET attempts    = attempts + 1,
+    status_code = $2,
+    error       = $3,
+    delivered   = CASE WHEN $3::text IS NULL THEN (now() at time zone 'utc') END
+WHERE id = $1
+RETURNING webhook_deliveries;